@@ -1,13 +1,37 @@
 #![cfg_attr(target_os = "wasi", allow(unused_variables))]
 use std::{env, path};
 use structopt::{clap::AppSettings, StructOpt};
+use wapm_cli::exit_code::{self, ErrorKind};
 #[cfg(feature = "update-notifications")]
 use wapm_cli::update_notifier;
+use wapm_cli::util::OutputFormat;
 #[allow(unused_imports)]
 use wapm_cli::{commands, logging};
 
 #[derive(StructOpt, Debug)]
 #[structopt(global_settings = &[AppSettings::VersionlessSubcommands, AppSettings::ColorAuto, AppSettings::ColoredHelp])]
+struct Opt {
+    /// The format of errors, `text` or `json`, defaults to `WAPM_ERROR_FORMAT` or `text`
+    #[structopt(long = "error-format", global = true)]
+    error_format: Option<OutputFormat>,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+impl Opt {
+    /// `wax` and `wapm execute` parse their own arguments, including `--error-format`.
+    #[cfg(feature = "full")]
+    fn execute(args: Vec<String>) -> Self {
+        let execute_options = commands::ExecuteOpt::ExecArgs(args);
+        Self {
+            error_format: execute_options.error_format(),
+            command: Command::Execute(execute_options),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
 enum Command {
     #[structopt(name = "whoami")]
     /// Prints the current user (if authed) in the stdout
@@ -15,7 +39,7 @@ enum Command {
 
     #[structopt(name = "login")]
     /// Logins into wapm, saving the token locally for future commands
-    Login,
+    Login(commands::LoginOpt),
 
     #[structopt(name = "logout")]
     /// Remove the token for the registry
@@ -29,6 +53,10 @@ enum Command {
     /// Install a package
     Install(commands::InstallOpt),
 
+    #[structopt(name = "ci")]
+    /// Install exactly the packages in wapm.lock, failing if it is out of date with wapm.toml
+    Ci(commands::CiOpt),
+
     #[cfg(feature = "full")]
     #[structopt(name = "publish")]
     /// Publish a package
@@ -41,6 +69,10 @@ enum Command {
     /// Run a command from the package or one of the dependencies
     Run(commands::RunOpt),
 
+    #[structopt(name = "which")]
+    /// Print how a command would be run and where its module comes from
+    Which(commands::WhichOpt),
+
     #[cfg(feature = "full")]
     #[structopt(name = "search")]
     /// Search packages
@@ -74,6 +106,15 @@ enum Command {
     /// Manage minisign keys for verifying packages
     Keys(commands::KeyOpt),
 
+    #[cfg(feature = "full")]
+    #[structopt(name = "outdated")]
+    /// List the dependencies that have newer versions in the registry
+    Outdated(commands::OutdatedOpt),
+
+    #[structopt(name = "update")]
+    /// Update locked packages to the newest versions allowed by wapm.toml
+    Update(commands::UpdateOpt),
+
     #[structopt(name = "uninstall")]
     /// Uninstall a package
     Uninstall(commands::UninstallOpt),
@@ -140,25 +181,43 @@ fn main() {
         .to_string_lossy();
 
     #[cfg(feature = "full")]
-    let args = if prog_name == "wax" {
-        Command::Execute(commands::ExecuteOpt::ExecArgs(
-            env::args().skip(1).collect(),
-        ))
+    let opt = if prog_name == "wax" {
+        Ok(Opt::execute(env::args().skip(1).collect()))
     } else if maybe_subcommand_name == Some("execute".to_string()) {
-        Command::Execute(commands::ExecuteOpt::ExecArgs(
-            env::args().skip(2).collect(),
-        ))
+        Ok(Opt::execute(env::args().skip(2).collect()))
     } else {
-        Command::from_args()
+        Opt::from_args_safe()
     };
 
     #[cfg(not(feature = "full"))]
-    let args = Command::from_args();
+    let opt = Opt::from_args_safe();
+
+    let opt = match opt {
+        Ok(opt) => opt,
+        // help and version are printed by clap
+        Err(e) if e.use_stderr() => {
+            let error_format = error_format_from_args()
+                .or_else(error_format_from_env)
+                .unwrap_or(OutputFormat::Text);
+            match error_format {
+                OutputFormat::Text => eprintln!("{}", e.message),
+                error_format => exit_code::report_error(&anyhow::Error::new(e), error_format),
+            }
+            std::process::exit(ErrorKind::Usage.exit_code());
+        }
+        Err(e) => e.exit(),
+    };
+    let error_format = opt
+        .error_format
+        .or_else(error_format_from_env)
+        .unwrap_or(OutputFormat::Text);
 
     #[cfg(feature = "update-notifications")]
     // Only show the async check on certain commands
-    let maybe_show_update_notification = match args {
+    let maybe_show_update_notification = match opt.command {
         Command::Install(_)
+        | Command::Ci(_)
+        | Command::Update(_)
         | Command::Add(_)
         | Command::Run(_)
         | Command::Execute(_)
@@ -172,17 +231,19 @@ fn main() {
         _ => false,
     };
 
-    let result = match args {
+    let result = match opt.command {
         Command::WhoAmI => commands::whoami(),
-        Command::Login => commands::login(),
+        Command::Login(login_options) => commands::login(login_options),
         Command::Logout => commands::logout(),
         Command::Config(config_options) => commands::config(config_options),
         Command::Install(install_options) => commands::install(install_options),
+        Command::Ci(ci_options) => commands::ci(ci_options),
         Command::Add(add_options) => commands::add(add_options),
         Command::Remove(remove_options) => commands::remove(remove_options),
         #[cfg(feature = "full")]
         Command::Publish(publish_options) => commands::publish(publish_options),
         Command::Run(run_options) => commands::run(run_options),
+        Command::Which(which_options) => commands::which(which_options),
         #[cfg(feature = "full")]
         Command::Execute(execute_options) => commands::execute(execute_options),
         #[cfg(feature = "full")]
@@ -197,13 +258,16 @@ fn main() {
         #[cfg(feature = "packagesigning")]
         Command::Keys(key_options) => commands::keys(key_options),
         Command::Completions(completion_options) => {
-            Command::clap().gen_completions_to(
+            Opt::clap().gen_completions_to(
                 "wapm",
                 completion_options.shell,
                 &mut ::std::io::stdout(),
             );
             Ok(())
         }
+        #[cfg(feature = "full")]
+        Command::Outdated(outdated_options) => commands::outdated(outdated_options),
+        Command::Update(update_options) => commands::update(update_options),
         Command::Uninstall(uninstall_options) => commands::uninstall(uninstall_options),
         #[cfg(feature = "full")]
         Command::Bin(bin_options) => commands::bin(bin_options),
@@ -222,8 +286,13 @@ fn main() {
         std::io::stderr().flush().unwrap();
     }
 
-    if let Err(e) = &result {
-        eprintln!("Error: {}", e);
+    // a failing command reports its own errors
+    let child_process_error = result
+        .as_ref()
+        .err()
+        .and_then(|e| e.downcast_ref::<commands::ChildProcessError>());
+    if let (Err(e), None) = (&result, child_process_error) {
+        exit_code::report_error(e, error_format);
     }
 
     #[cfg(feature = "update-notifications")]
@@ -238,6 +307,34 @@ fn main() {
         {
             drop(_guard);
         };
-        std::process::exit(-1);
+        if let Some(child_process_error) = child_process_error {
+            child_process_error.exit();
+        }
+        let error_kind = result
+            .as_ref()
+            .err()
+            .map(exit_code::error_kind)
+            .unwrap_or(ErrorKind::Failure);
+        std::process::exit(error_kind.exit_code());
     }
 }
+
+/// The error format from `--error-format`, for when the arguments could not be parsed.
+fn error_format_from_args() -> Option<OutputFormat> {
+    let args: Vec<String> = env::args().collect();
+    args.iter()
+        .enumerate()
+        .find_map(|(i, arg)| match arg.strip_prefix("--error-format=") {
+            Some(error_format) => Some(error_format),
+            None if arg == "--error-format" => args.get(i + 1).map(String::as_str),
+            None => None,
+        })
+        .and_then(|error_format| error_format.parse().ok())
+}
+
+/// The error format from the `WAPM_ERROR_FORMAT` environment variable.
+fn error_format_from_env() -> Option<OutputFormat> {
+    env::var("WAPM_ERROR_FORMAT")
+        .ok()
+        .and_then(|error_format| error_format.parse().ok())
+}
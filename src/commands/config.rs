@@ -1,4 +1,4 @@
-use crate::config::{get, set, Config};
+use crate::config::{entries, get, set, unset, Config, ConfigSources};
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
@@ -10,6 +10,14 @@ pub enum ConfigOpt {
     #[structopt(name = "get")]
     /// Gets a key
     Get(ConfigKey),
+
+    #[structopt(name = "unset")]
+    /// Removes a key from the global config, so that it has its default value
+    Unset(ConfigUnsetKey),
+
+    #[structopt(name = "list")]
+    /// Lists all keys that have a value
+    List(ConfigList),
 }
 
 #[derive(StructOpt, Debug)]
@@ -25,15 +33,75 @@ pub struct ConfigKeyValue {
 pub struct ConfigKey {
     #[structopt(parse(from_str))]
     key: String,
+
+    /// Also print where the value comes from: the default, the global or project config file or
+    /// an environment variable
+    #[structopt(long = "show-origin")]
+    show_origin: bool,
+
+    /// Print secrets like tokens instead of redacting them
+    #[structopt(long = "show-secrets")]
+    show_secrets: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ConfigUnsetKey {
+    #[structopt(parse(from_str))]
+    key: String,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ConfigList {
+    /// Also print where each value comes from
+    #[structopt(long = "show-origin")]
+    show_origin: bool,
+
+    /// Print secrets like tokens instead of redacting them
+    #[structopt(long = "show-secrets")]
+    show_secrets: bool,
 }
 
 pub fn config(config_opt: ConfigOpt) -> anyhow::Result<()> {
-    let mut config = Config::from_file()?;
     match config_opt {
-        ConfigOpt::Set(ConfigKeyValue { key, value }) => set(&mut config, key, value),
-        ConfigOpt::Get(ConfigKey { key }) => {
-            let value = get(&mut config, key)?;
-            println!("{}", value);
+        // only the global config is saved, never the values of the project or the environment
+        ConfigOpt::Set(ConfigKeyValue { key, value }) => {
+            let mut config = Config::from_global_file()?;
+            set(&mut config, key, value)
+        }
+        ConfigOpt::Unset(ConfigUnsetKey { key }) => {
+            let mut config = Config::from_global_file()?;
+            unset(&mut config, key)
+        }
+        ConfigOpt::Get(ConfigKey {
+            key,
+            show_origin,
+            show_secrets,
+        }) => {
+            let sources = ConfigSources::load()?;
+            let config = sources.config()?;
+            let value = get(&config, &key)?.display_value(show_secrets);
+            if show_origin {
+                println!("{}\t{}", sources.origin(&config, &key)?, value);
+            } else {
+                println!("{}", value);
+            }
+            Ok(())
+        }
+        ConfigOpt::List(ConfigList {
+            show_origin,
+            show_secrets,
+        }) => {
+            let sources = ConfigSources::load()?;
+            let config = sources.config()?;
+            for entry in entries(&config)? {
+                let value = entry.display_value(show_secrets);
+                if show_origin {
+                    let origin = sources.origin(&config, &entry.key)?;
+                    println!("{}\t{} = {}", origin, entry.key, value);
+                } else {
+                    println!("{} = {}", entry.key, value);
+                }
+            }
             Ok(())
         }
     }
@@ -47,6 +115,10 @@ impl ConfigOpt {
     }
 
     pub fn get(key: String) -> Self {
-        ConfigOpt::Get(ConfigKey { key })
+        ConfigOpt::Get(ConfigKey {
+            key,
+            show_origin: false,
+            show_secrets: false,
+        })
     }
 }
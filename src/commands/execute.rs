@@ -1,25 +1,32 @@
 //! Module for wax, executes a module immediately
 
-//use crate::constants::RFC3339_FORMAT_STRING_WITH_TIMEZONE;
+use crate::commands::run::{plan_run, CommandOrigin};
+use crate::commands::ChildProcessError;
 use crate::config;
+use crate::constants::RFC3339_FORMAT_STRING_WITH_TIMEZONE;
 use crate::data::wax_index;
-use crate::dataflow::find_command_result::FindCommandResult;
-use crate::dataflow::installed_packages::{InstalledPackages, RegistryInstaller};
+use crate::dataflow::find_command_result::{self, FindCommandResult};
+use crate::dataflow::installed_packages::{
+    Install, InstalledPackages, KeyCheck, RegistryInstaller,
+};
 use crate::dataflow::lockfile_packages::{LockfilePackages, LockfileResult};
 use crate::dataflow::merged_lockfile_packages::MergedLockfilePackages;
-use crate::dataflow::resolved_packages::ResolvedPackages;
+use crate::dataflow::resolved_packages::{PackageRegistries, ResolvedPackages};
 use crate::dataflow::retained_lockfile_packages::RetainedLockfilePackages;
 use crate::dataflow::WapmPackageKey;
+use crate::exit_code::{Classify, ErrorKind};
 use crate::graphql::{execute_query, DateTime};
-//use crate::keys;
+use crate::keys;
 use crate::util;
+use crate::util::OutputFormat;
 use thiserror::Error;
 
 use graphql_client::*;
 
+use semver::{Version, VersionReq};
 use std::convert::From;
 use std::ffi::OsString;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use structopt::{clap::AppSettings, StructOpt};
 
@@ -31,6 +38,13 @@ pub enum ExecuteOpt {
 }
 
 impl ExecuteOpt {
+    /// The format of errors given with `--error-format`, if the arguments can be parsed.
+    pub fn error_format(&self) -> Option<OutputFormat> {
+        transform_args(self.args())
+            .ok()
+            .and_then(|opt| opt.error_format)
+    }
+
     fn args(&self) -> &[String] {
         match self {
             ExecuteOpt::ExecArgs(args) => args.as_slice(),
@@ -51,7 +65,7 @@ struct ExecuteOptInner {
     #[structopt(long = "force-yes", short = "y")]
     force_yes: bool,
 
-    /// Package will be verified by its signature. Off by default
+    /// Package will be verified by its signature, trusting the key of its publisher on first use. Off by default
     #[structopt(long = "verify", short = "v")]
     verify_signature: bool,
 
@@ -59,6 +73,27 @@ struct ExecuteOptInner {
     #[structopt(long = "offline")]
     offline: bool,
 
+    /// Print how the command would be run instead of running it, without installing its package
+    /// or changing the wax index.
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+
+    /// List the packages installed by `wax` and the commands that run them.
+    #[structopt(long = "list")]
+    list: bool,
+
+    /// Remove the packages installed by `wax`.
+    #[structopt(long = "prune")]
+    prune: bool,
+
+    /// Run the command from this package, optionally at a version, e.g. `namespace/pkg@^1`.
+    #[structopt(long = "package", value_name = "PACKAGE")]
+    package: Option<String>,
+
+    /// Only prune the packages that were not used for this long, e.g. `30d` or `12h`.
+    #[structopt(long = "older-than", value_name = "DURATION", requires = "prune")]
+    older_than: Option<String>,
+
     /// Pre-open a directory for WASI.
     #[structopt(
         long = "dir",
@@ -72,11 +107,15 @@ struct ExecuteOptInner {
     #[structopt(long = "no-default-preopen")]
     no_default_preopen: bool,
 
+    /// The format of errors, `text` or `json`, defaults to `WAPM_ERROR_FORMAT` or `text`.
+    #[structopt(long = "error-format", value_name = "FORMAT")]
+    error_format: Option<OutputFormat>,
+
     /// The command to run.
     #[structopt(long = "which", value_name = "COMMAND")]
     which: Option<String>,
 
-    /// The command to run.
+    /// The command to run, optionally at a version, e.g. `foo@1.2.3`.
     #[structopt(conflicts_with_all(&["which"]), index = 1, value_name = "COMMAND")]
     command: Option<String>,
 
@@ -100,7 +139,7 @@ impl ExecuteOptInner {
 }
 
 #[derive(Debug, Error)]
-enum ExecuteError {
+pub(crate) enum ExecuteError {
     #[error(
         "Command `{0}` not found in the registry or in the current directory",
         name
@@ -119,6 +158,12 @@ enum ExecuteError {
     InstallationError(String),
     #[error("Please specify a command to run.")]
     NoCommandGiven,
+    #[error("Invalid version requirement `{0}`")]
+    InvalidVersionRequirement(String),
+    #[error("The version of the command is given twice, in `--package {0}` and in `{1}`")]
+    VersionGivenTwice(String, String),
+    #[error("Invalid duration `{0}`, expected a number followed by s, m, h, d or w, e.g. `30d`")]
+    InvalidDuration(String),
     #[error(
         "The command `{0}` was not found.\nIf you are offline you will need to reconnect to the Internet for this command to succeed.\nOtherwise there may be an error with the wapm registry that you're connected to: run `wapm config get registry.url` to print the URL that we tried to connect to.",
     )]
@@ -129,12 +174,38 @@ enum ExecuteError {
     CommandNotFoundOfflineModeOfflineFlag(String),
 }
 
+impl Classify for ExecuteError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            ExecuteError::CommandNotFound { .. }
+            | ExecuteError::CommandNotFoundOfflineModeOfflineFlag(_) => ErrorKind::CommandNotFound,
+            ExecuteError::CommandNotFoundOfflineMode(_)
+            | ExecuteError::ErrorInDataFromRegistry(_) => ErrorKind::Registry,
+            ExecuteError::EmscriptenDisabled { .. }
+            | ExecuteError::NoCommandGiven
+            | ExecuteError::InvalidVersionRequirement(_)
+            | ExecuteError::VersionGivenTwice(..)
+            | ExecuteError::InvalidDuration(_) => ErrorKind::Usage,
+            ExecuteError::WaxIndexError(e) => e.kind(),
+            ExecuteError::InstallationError(_) => ErrorKind::Dependency,
+        }
+    }
+}
+
 #[derive(Debug, Error)]
-enum ExecuteArgParsingError {
+pub(crate) enum ExecuteArgParsingError {
     #[error("Argument `{arg_name}` expects a value `{expected}` but none was found.")]
     MissingValue { arg_name: String, expected: String },
     #[error("Unrecognized argument `{arg_name}`")]
     UnrecognizedArgument { arg_name: String },
+    #[error("Invalid value for argument `{arg_name}`: {message}")]
+    InvalidValue { arg_name: String, message: String },
+}
+
+impl Classify for ExecuteArgParsingError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Usage
+    }
 }
 
 #[derive(GraphQLQuery)]
@@ -145,6 +216,225 @@ enum ExecuteArgParsingError {
 )]
 struct WaxGetCommandQuery;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/schema.graphql",
+    query_path = "graphql/queries/wax_get_package_versions.graphql",
+    response_derives = "Debug"
+)]
+struct WaxGetPackageVersionsQuery;
+
+/// What wax was asked to run: a command, optionally from a given package and version
+#[derive(Debug, PartialEq)]
+struct WaxRequest {
+    command: String,
+    package: Option<String>,
+    /// The version requirement as it was given, e.g. `1.2.3` or `^1`
+    requirement: Option<String>,
+    version: Option<VersionReq>,
+}
+
+impl WaxRequest {
+    /// Parse `command[@version]` and `--package namespace/name[@version]`. A full version
+    /// pins that exact version, anything else is a version requirement.
+    fn parse(command: &str, package: Option<&str>) -> Result<Self, ExecuteError> {
+        let (command, command_requirement) = split_version(command);
+        let (package_name, package_requirement) = match package.map(split_version) {
+            Some((package_name, requirement)) => (Some(package_name), requirement),
+            None => (None, None),
+        };
+        if let (Some(package), Some(command_requirement)) = (package, &command_requirement) {
+            if package_requirement.is_some() {
+                return Err(ExecuteError::VersionGivenTwice(
+                    package.to_string(),
+                    format!("{}@{}", command, command_requirement),
+                ));
+            }
+        }
+        let requirement = package_requirement.or(command_requirement);
+        let version = requirement
+            .as_deref()
+            .map(|requirement| match Version::parse(requirement) {
+                Ok(version) => Ok(VersionReq::exact(&version)),
+                Err(_) => VersionReq::parse(requirement)
+                    .map_err(|_| ExecuteError::InvalidVersionRequirement(requirement.to_string())),
+            })
+            .transpose()?;
+        Ok(WaxRequest {
+            command,
+            package: package_name,
+            requirement,
+            version,
+        })
+    }
+
+    /// Whether the command should run from a package or version other than the latest
+    fn is_pinned(&self) -> bool {
+        self.package.is_some() || self.version.is_some()
+    }
+
+    /// Whether an exact version was asked for, which never has to be checked for updates
+    fn is_exact(&self) -> bool {
+        self.requirement
+            .as_deref()
+            .map(|requirement| Version::parse(requirement).is_ok())
+            .unwrap_or(false)
+    }
+
+    /// The key of the command in the wax index, so pinned and latest versions of a command are
+    /// tracked separately.
+    fn index_key(&self) -> String {
+        let requirement = self.requirement.as_deref().unwrap_or("*");
+        match (&self.package, &self.requirement) {
+            (Some(package), _) => format!("{}@{}:{}", package, requirement, self.command),
+            (None, Some(requirement)) => format!("{}@{}", self.command, requirement),
+            (None, None) => self.command.clone(),
+        }
+    }
+}
+
+fn split_version(name: &str) -> (String, Option<String>) {
+    match name.find('@') {
+        Some(idx) => (name[..idx].to_string(), Some(name[idx + 1..].to_string())),
+        None => (name.to_string(), None),
+    }
+}
+
+/// The package version in the registry that provides a command
+#[derive(Debug)]
+struct RegistryCommand {
+    package_name: String,
+    version: Version,
+    download_url: String,
+    abi: Option<String>,
+    signature: Option<keys::WapmPackageSignature>,
+}
+
+/// The signature of a package version as sent by the registry.
+fn package_signature(
+    public_key_id: String,
+    public_key: String,
+    signature_data: String,
+    created_at: &str,
+    revoked: bool,
+    owner: String,
+) -> Result<keys::WapmPackageSignature, ExecuteError> {
+    let date_created = time::strptime(created_at, RFC3339_FORMAT_STRING_WITH_TIMEZONE)
+        .map_err(|e| ExecuteError::ErrorInDataFromRegistry(e.to_string()))?
+        .to_timespec();
+    Ok(keys::WapmPackageSignature {
+        public_key_id,
+        public_key,
+        signature_data,
+        date_created,
+        revoked,
+        owner,
+    })
+}
+
+/// Find the package version that provides the command in the registry: the latest one, or the
+/// newest one matching the request.
+fn find_command_in_registry(request: &WaxRequest) -> anyhow::Result<Option<RegistryCommand>> {
+    let package_name = match request.package {
+        Some(ref package_name) => package_name.clone(),
+        None => {
+            let q = WaxGetCommandQuery::build_query(wax_get_command_query::Variables {
+                command: request.command.clone(),
+            });
+            let response: wax_get_command_query::ResponseData = execute_query(&q)?;
+            trace!("Wax get command query: {:?}", response);
+            let command = match response.command {
+                Some(command) => command,
+                None => return Ok(None),
+            };
+            if !request.is_pinned() {
+                let version = Version::from_str(&command.package_version.version)
+                    .map_err(|e| ExecuteError::ErrorInDataFromRegistry(e.to_string()))?;
+                let signature = command
+                    .package_version
+                    .signature
+                    .map(|signature| {
+                        package_signature(
+                            signature.public_key.key_id,
+                            signature.public_key.key,
+                            signature.data,
+                            &signature.created_at,
+                            signature.public_key.revoked,
+                            signature.public_key.owner.username,
+                        )
+                    })
+                    .transpose()?;
+                return Ok(Some(RegistryCommand {
+                    package_name: command.package_version.package.name,
+                    version,
+                    download_url: command.package_version.distribution.download_url,
+                    abi: command.module.abi,
+                    signature,
+                }));
+            }
+            command.package_version.package.name
+        }
+    };
+
+    let q = WaxGetPackageVersionsQuery::build_query(wax_get_package_versions_query::Variables {
+        package: package_name.clone(),
+    });
+    let response: wax_get_package_versions_query::ResponseData = execute_query(&q)?;
+    trace!("Wax get package versions query: {:?}", response);
+    let versions = response
+        .package
+        .and_then(|package| package.versions)
+        .unwrap_or_default();
+    let mut newest: Option<RegistryCommand> = None;
+    for package_version in versions.into_iter().flatten() {
+        let version = match Version::from_str(&package_version.version) {
+            Ok(version) => version,
+            Err(_) => continue,
+        };
+        if let Some(ref requirement) = request.version {
+            if !requirement.matches(&version) {
+                continue;
+            }
+        }
+        if newest
+            .as_ref()
+            .map(|newest| newest.version >= version)
+            .unwrap_or(false)
+        {
+            continue;
+        }
+        let command = match package_version
+            .commands
+            .into_iter()
+            .find(|command| command.command == request.command)
+        {
+            Some(command) => command,
+            None => continue,
+        };
+        let signature = package_version
+            .signature
+            .map(|signature| {
+                package_signature(
+                    signature.public_key.key_id,
+                    signature.public_key.key,
+                    signature.data,
+                    &signature.created_at,
+                    signature.public_key.revoked,
+                    signature.public_key.owner.username,
+                )
+            })
+            .transpose()?;
+        newest = Some(RegistryCommand {
+            package_name: package_name.clone(),
+            version,
+            download_url: package_version.distribution.download_url,
+            abi: command.module.abi,
+            signature,
+        });
+    }
+    Ok(newest)
+}
+
 /// Do the real argument parsing into [`ExecuteOptInner`].
 fn transform_args(arg_stream: &[String]) -> Result<ExecuteOptInner, ExecuteArgParsingError> {
     let mut idx = 0;
@@ -156,6 +446,22 @@ fn transform_args(arg_stream: &[String]) -> Result<ExecuteOptInner, ExecuteArgPa
         })?;
         Ok(val)
     };
+    let parse_older_than =
+        |older_than_arg: Option<String>| -> Result<String, ExecuteArgParsingError> {
+            let val: String =
+                older_than_arg.ok_or_else(|| ExecuteArgParsingError::MissingValue {
+                    arg_name: "--older-than".to_string(),
+                    expected: "<DURATION>".to_string(),
+                })?;
+            Ok(val)
+        };
+    let parse_package = |package_arg: Option<String>| -> Result<String, ExecuteArgParsingError> {
+        let val: String = package_arg.ok_or_else(|| ExecuteArgParsingError::MissingValue {
+            arg_name: "--package".to_string(),
+            expected: "<PACKAGE>".to_string(),
+        })?;
+        Ok(val)
+    };
     let parse_dir = |dir_arg: Option<String>| -> Result<String, ExecuteArgParsingError> {
         let val: String = dir_arg.ok_or_else(|| ExecuteArgParsingError::MissingValue {
             arg_name: "--dir".to_string(),
@@ -163,6 +469,18 @@ fn transform_args(arg_stream: &[String]) -> Result<ExecuteOptInner, ExecuteArgPa
         })?;
         Ok(val)
     };
+    let parse_error_format =
+        |format_arg: Option<String>| -> Result<OutputFormat, ExecuteArgParsingError> {
+            let val: String = format_arg.ok_or_else(|| ExecuteArgParsingError::MissingValue {
+                arg_name: "--error-format".to_string(),
+                expected: "<FORMAT>".to_string(),
+            })?;
+            val.parse()
+                .map_err(|message| ExecuteArgParsingError::InvalidValue {
+                    arg_name: "--error-format".to_string(),
+                    message,
+                })
+        };
     while idx < arg_stream.len() {
         match arg_stream[idx].as_ref() {
             "--emscripten" => out.enable_emscripten = true,
@@ -170,6 +488,17 @@ fn transform_args(arg_stream: &[String]) -> Result<ExecuteOptInner, ExecuteArgPa
             "--verify" | "-v" => out.verify_signature = true,
             "--no-default-preopen" => out.no_default_preopen = true,
             "--offline" => out.offline = true,
+            "--dry-run" => out.dry_run = true,
+            "--list" => out.list = true,
+            "--prune" => out.prune = true,
+            "--package" => {
+                out.package = Some(parse_package(arg_stream.get(idx + 1).cloned())?);
+                idx += 1;
+            }
+            "--older-than" => {
+                out.older_than = Some(parse_older_than(arg_stream.get(idx + 1).cloned())?);
+                idx += 1;
+            }
             "--which" => {
                 out.which = Some(parse_which(arg_stream.get(idx + 1).cloned())?);
                 idx += 1;
@@ -179,6 +508,10 @@ fn transform_args(arg_stream: &[String]) -> Result<ExecuteOptInner, ExecuteArgPa
                     .push(parse_dir(arg_stream.get(idx + 1).cloned())?);
                 idx += 1;
             }
+            "--error-format" => {
+                out.error_format = Some(parse_error_format(arg_stream.get(idx + 1).cloned())?);
+                idx += 1;
+            }
             "help" | "--help" | "-h" => {
                 ExecuteOptInner::print_help_text();
                 std::process::exit(0);
@@ -191,9 +524,12 @@ fn transform_args(arg_stream: &[String]) -> Result<ExecuteOptInner, ExecuteArgPa
                     let val = splitter.next().map(|x| x.to_string());
                     match arg {
                         "--which" => out.which = Some(parse_which(val)?),
+                        "--older-than" => out.older_than = Some(parse_older_than(val)?),
+                        "--package" => out.package = Some(parse_package(val)?),
                         "--dir" => {
                             out.pre_opened_directories.push(parse_dir(val)?);
                         }
+                        "--error-format" => out.error_format = Some(parse_error_format(val)?),
                         otherwise => {
                             return Err(ExecuteArgParsingError::UnrecognizedArgument {
                                 arg_name: otherwise.to_string(),
@@ -251,21 +587,34 @@ pub fn execute(opt: ExecuteOpt) -> anyhow::Result<()> {
         println!("{}", dir.to_string_lossy());
         return Ok(());
     }
-    let command = if let Some(command) = &opt.command {
+    if opt.list {
+        return list_packages();
+    }
+    if opt.prune {
+        return prune_packages(opt.older_than.as_deref());
+    }
+    let command_spec = if let Some(command) = &opt.command {
         command.clone()
     } else {
         ExecuteOptInner::print_help_text();
         return Err(ExecuteError::NoCommandGiven.into());
     };
-    let command_name = command.as_str();
+    let request = WaxRequest::parse(&command_spec, opt.package.as_deref())?;
+    let command_name = request.command.as_str();
+    let index_key = request.index_key();
     let _value = util::set_wapm_should_accept_all_prompts(opt.force_yes);
     debug_assert!(
         _value.is_some(),
         "this function should only be called once!"
     );
 
-    // first search for locally installed command
-    match FindCommandResult::find_command_in_directory(&current_dir, &command_name) {
+    // first search for locally installed command, unless a particular version was asked for
+    let local_command = if request.is_pinned() {
+        FindCommandResult::CommandNotFound(command_spec.clone())
+    } else {
+        FindCommandResult::find_command_in_directory(&current_dir, command_name)
+    };
+    match local_command {
         FindCommandResult::CommandNotFound(_) => {
             // go to normal wax flow
             debug!(
@@ -277,26 +626,34 @@ pub fn execute(opt: ExecuteOpt) -> anyhow::Result<()> {
         FindCommandResult::CommandFound {
             source,
             manifest_dir,
-            args: _,
+            args,
             module_name,
             prehashed_cache_key,
+            package_name,
+            package_version,
         } => {
             debug!(
                 "Wax command found locally in {}",
                 current_dir.to_string_lossy()
             );
-            // run it and return
-            crate::commands::run::do_run(
-                current_dir,
+            let command = find_command_result::Command {
                 source,
                 manifest_dir,
-                command_name,
-                &module_name,
-                &opt.pre_opened_directories,
-                &opt.args,
+                args,
+                module_name,
+                is_global: false,
                 prehashed_cache_key,
-            )?;
-            return Ok(());
+                package_name,
+                package_version,
+            };
+            // run it and return
+            return run_command(
+                current_dir,
+                command_name,
+                command,
+                CommandOrigin::Local,
+                &opt,
+            );
         }
         FindCommandResult::Error(e) => {
             warn!(
@@ -307,10 +664,10 @@ pub fn execute(opt: ExecuteOpt) -> anyhow::Result<()> {
     }
 
     // get wax info from the index and find out if the entry is stale
-    let mut wax_index = wax_index::WaxIndex::open()?;
+    let mut wax_index = open_wax_index(&opt)?;
     let (wax_info, exists_and_recently_updated): (Option<(String, semver::Version)>, bool) =
         if let Ok((package_name, version, last_seen)) =
-            wax_index.search_for_entry(command_name.to_string())
+            wax_index.search_for_entry(index_key.clone())
         {
             let wax_cooldown = get_wax_cooldown();
             trace!("Using wax cooldown: {}", wax_cooldown);
@@ -319,7 +676,8 @@ pub fn execute(opt: ExecuteOpt) -> anyhow::Result<()> {
             let time_to_update = last_seen + cooldown_duration;
 
             // if we haven't yet hit the time to update, then we've recently updated
-            let recently_updated = now < time_to_update;
+            // an exact version never changes
+            let recently_updated = request.is_exact() || now < time_to_update;
             debug!("The package was recently updated? {}", recently_updated);
 
             (Some((package_name, version)), recently_updated)
@@ -327,7 +685,9 @@ pub fn execute(opt: ExecuteOpt) -> anyhow::Result<()> {
             (None, false)
         };
 
-    if exists_and_recently_updated {
+    // with --verify the package is installed again, which checks its signature even when it is
+    // already installed
+    if exists_and_recently_updated && !opt.verify_signature {
         let (package_name, version) = wax_info
             .clone()
             .expect("critical internal logic error in `wapm execute`");
@@ -342,15 +702,14 @@ pub fn execute(opt: ExecuteOpt) -> anyhow::Result<()> {
             debug!("Package found in index, but cache has been cleared! continuing to logic that should reinstall");
         } else {
             // do execute unless it fails then continue
-            wax_index.save()?;
+            if !opt.dry_run {
+                wax_index.save()?;
+            }
 
-            match run(
-                command_name,
-                location,
-                &opt.pre_opened_directories,
-                &opt.args,
-            ) {
+            match run(command_name, location, &opt) {
                 Ok(()) => return Ok(()),
+                // the command ran, but failed
+                Err(e) if e.is::<ChildProcessError>() => return Err(e),
                 Err(e) => {
                     // REVIEW: does this even make sense?
                     // maybe if we force a reinstall but eh
@@ -361,25 +720,23 @@ pub fn execute(opt: ExecuteOpt) -> anyhow::Result<()> {
     }
 
     // if not found, query the server and check if we already have it installed
-    let q = WaxGetCommandQuery::build_query(wax_get_command_query::Variables {
-        command: command_name.to_string(),
-    });
-    let response: wax_get_command_query::ResponseData = if !opt.offline {
+    let registry_command = if !opt.offline {
         debug!("Querying server for package info");
-        let response: Result<wax_get_command_query::ResponseData, _> = execute_query(&q);
-        if response.is_err() {
-            info!("Failed to connect to the wapm registry. Continuning in offline mode.");
-            return do_offline_run(command_name, &opt);
+        match find_command_in_registry(&request) {
+            Ok(registry_command) => registry_command,
+            Err(e) => {
+                debug!("Failed to query the registry: {}", e);
+                info!("Failed to connect to the wapm registry. Continuning in offline mode.");
+                return do_offline_run(&request, &opt);
+            }
         }
-        response?
     } else {
-        return do_offline_run(command_name, &opt);
+        return do_offline_run(&request, &opt);
     };
 
-    trace!("Wax get command query: {:?}", response);
-    if let Some(command) = response.command {
+    if let Some(command) = registry_command {
         // command found, check if it's installed
-        if let Some(abi) = command.module.abi.as_ref() {
+        if let Some(abi) = command.abi.as_ref() {
             if abi == "emscripten" && !opt.enable_emscripten {
                 return Err(ExecuteError::EmscriptenDisabled {
                     name: command_name.to_string(),
@@ -387,13 +744,13 @@ pub fn execute(opt: ExecuteOpt) -> anyhow::Result<()> {
                 .into());
             }
         }
-        let registry_version = semver::Version::from_str(&command.package_version.version)
-            .map_err(|e| ExecuteError::ErrorInDataFromRegistry(e.to_string()))?;
+        let registry_version = command.version.clone();
 
         if let Some((package_name, version)) = wax_info {
             let package_version_str = format!("{}@{}", &package_name, &version);
             let location = wax_index.base_path().join(&package_version_str);
             if registry_version > version
+                || opt.verify_signature
                 || !location
                     .join("wapm_packages")
                     .join(&package_version_str)
@@ -409,19 +766,16 @@ pub fn execute(opt: ExecuteOpt) -> anyhow::Result<()> {
                     "Command found in Wax index, executing version {} directly",
                     version
                 );
-                wax_index.insert_entry(
-                    command_name.to_string(),
-                    registry_version.clone(),
-                    command.package_version.package.name.clone(),
-                );
-                wax_index.save()?;
+                if !opt.dry_run {
+                    wax_index.insert_entry(
+                        index_key.clone(),
+                        registry_version.clone(),
+                        command.package_name.clone(),
+                    );
+                    wax_index.save()?;
+                }
 
-                run(
-                    command_name,
-                    location,
-                    &opt.pre_opened_directories,
-                    &opt.args,
-                )?;
+                run(command_name, location, &opt)?;
                 return Ok(());
             }
         } else {
@@ -433,91 +787,91 @@ pub fn execute(opt: ExecuteOpt) -> anyhow::Result<()> {
         // if we made it this far, it means we haven't executed the command yet,
         // so we install the package and run it
         trace!("Installing Wax package from registry");
-        let install_loc = wax_index.base_path().join(format!(
-            "{}@{}",
-            &command.package_version.package.name, &registry_version
-        ));
-        let resolved_packages = ResolvedPackages {
-            packages: vec![(
-                WapmPackageKey {
-                    name: command.package_version.package.name.clone().into(),
-                    version: registry_version.clone(),
-                },
-                (
-                    command.package_version.distribution.download_url.clone(),
-                    None, /*
-                              // package signing disabled for `wapm execute` for now
-                              command
-                              .package_version
-                              .signature
-                              .map(|sig| keys::WapmPackageSignature {
-                              public_key_id: sig.public_key.key_id.clone(),
-                              public_key: sig.public_key.key.clone(),
-                              signature_data: sig.data.clone(),
-                              date_created: time::strptime(
-                              &sig.public_key.uploaded_at,
-                              RFC3339_FORMAT_STRING_WITH_TIMEZONE,
-                          )
-                              .unwrap_or_else(|err| {
-                              panic!("Failed to parse time string: {}", err)
-                          })
-                              .to_timespec(),
-                              revoked: sig.public_key.revoked,
-                              owner: sig.public_key.owner.username.clone(),
-                          })*/
-                ),
-            )],
-        };
-
-        // perform the install and generate the lockfile (like a simpler version of dataflow::update updating without a manifest)
-        let lockfile_result = LockfileResult::find_in_directory(&install_loc);
-        let lockfile_packages = LockfilePackages::new_from_result(lockfile_result)
-            .map_err(|e| ExecuteError::InstallationError(e.to_string()))?;
-        let installed_packages = InstalledPackages::install::<RegistryInstaller>(
+        let install_loc = wax_index
+            .base_path()
+            .join(format!("{}@{}", &command.package_name, &registry_version));
+        if opt.dry_run {
+            // the command can only be planned once its package is installed
+            print!(
+                "command: {}\npackage: {} {} (wax, not installed)\ninstall: {} from {}\n",
+                command_name,
+                command.package_name,
+                registry_version,
+                install_loc.to_string_lossy(),
+                command.download_url,
+            );
+            return Ok(());
+        }
+        install_registry_command::<RegistryInstaller>(
             &install_loc,
-            resolved_packages,
-            !opt.verify_signature,
+            &command,
+            opt.verify_signature,
         )?;
-        let added_lockfile_data = LockfilePackages::from_installed_packages(&installed_packages)
-            .map_err(|e| ExecuteError::InstallationError(e.to_string()))?;
-
-        let retained_lockfile_packages =
-            RetainedLockfilePackages::from_lockfile_packages(lockfile_packages);
-        let final_lockfile_data =
-            MergedLockfilePackages::merge(added_lockfile_data, retained_lockfile_packages);
-        final_lockfile_data
-            .generate_lockfile(&install_loc)
-            .map_err(|e| ExecuteError::InstallationError(e.to_string()))?;
-
         debug!("Wax package installed to {}", install_loc.to_string_lossy());
 
-        wax_index.insert_entry(
-            command_name.to_string(),
-            registry_version,
-            command.package_version.package.name.clone(),
-        );
+        wax_index.insert_entry(index_key, registry_version, command.package_name.clone());
         wax_index.save()?;
-        run(
-            command_name,
-            install_loc,
-            &opt.pre_opened_directories,
-            &opt.args,
-        )?;
+        if let Some(max_size) = get_wax_max_size() {
+            for package in wax_index.evict_least_recently_used(max_size)? {
+                debug!(
+                    "Removed least recently used wax package {}",
+                    package.package_name
+                );
+            }
+        }
+        run(command_name, install_loc, &opt)?;
         return Ok(());
     } else {
-        return Err(ExecuteError::CommandNotFound {
-            name: command_name.to_string(),
-        }
-        .into());
+        return Err(ExecuteError::CommandNotFound { name: command_spec }.into());
     }
 }
 
-fn run(
-    command_name: &str,
-    location: PathBuf,
-    pre_opened_directories: &[String],
-    args: &[OsString],
+/// Install the package providing a command into its wax directory and generate its lockfile, like
+/// a simpler version of `dataflow::update` updating without a manifest. With `verify_signature`
+/// the package must pass the same signature checks as `wapm install`.
+fn install_registry_command<Installer: for<'a> Install<'a>>(
+    install_loc: &Path,
+    command: &RegistryCommand,
+    verify_signature: bool,
 ) -> anyhow::Result<()> {
+    let resolved_packages = ResolvedPackages {
+        packages: vec![(
+            WapmPackageKey {
+                name: command.package_name.clone().into(),
+                version: command.version.clone(),
+            },
+            (command.download_url.clone(), command.signature.clone()),
+        )],
+    };
+
+    let lockfile_result = LockfileResult::find_in_directory(install_loc);
+    let lockfile_packages = LockfilePackages::new_from_result(lockfile_result)
+        .map_err(|e| ExecuteError::InstallationError(e.to_string()))?;
+    let installed_packages = InstalledPackages::install::<Installer>(
+        install_loc,
+        resolved_packages,
+        &lockfile_packages,
+        &PackageRegistries::default(),
+        if verify_signature {
+            KeyCheck::Prompt
+        } else {
+            KeyCheck::Insecure
+        },
+    )?;
+    let added_lockfile_data = LockfilePackages::from_installed_packages(&installed_packages)
+        .map_err(|e| ExecuteError::InstallationError(e.to_string()))?;
+
+    let retained_lockfile_packages =
+        RetainedLockfilePackages::from_lockfile_packages(lockfile_packages);
+    let final_lockfile_data =
+        MergedLockfilePackages::merge(added_lockfile_data, retained_lockfile_packages);
+    final_lockfile_data
+        .generate_lockfile(install_loc, None)
+        .map_err(|e| ExecuteError::InstallationError(e.to_string()))?;
+    Ok(())
+}
+
+fn run(command_name: &str, location: PathBuf, opt: &ExecuteOptInner) -> anyhow::Result<()> {
     match FindCommandResult::find_command_in_directory(&location, command_name) {
         FindCommandResult::CommandNotFound(s) => {
             // this should only happen if the package is deleted immediately
@@ -533,41 +887,65 @@ fn run(
         FindCommandResult::CommandFound {
             source,
             manifest_dir,
-            args: _,
+            args,
             module_name,
             prehashed_cache_key,
+            package_name,
+            package_version,
         } => {
             crate::logging::clear_stdout()?;
-            return crate::commands::run::do_run(
-                location,
+            let command = find_command_result::Command {
                 source,
                 manifest_dir,
-                command_name,
-                &module_name,
-                pre_opened_directories,
                 args,
+                module_name,
+                is_global: false,
                 prehashed_cache_key,
-            );
+                package_name,
+                package_version,
+            };
+            return run_command(location, command_name, command, CommandOrigin::Wax, opt);
         }
         FindCommandResult::Error(e) => return Err(e),
     };
 }
 
-fn do_offline_run(command_name: &str, opt: &ExecuteOptInner) -> anyhow::Result<()> {
-    let mut wax_index = wax_index::WaxIndex::open()?;
-    if let Ok((package_name, version, _)) = wax_index.search_for_entry(command_name.to_string()) {
+/// Run a command found by wax, or only print how it would be run with `--dry-run`.
+fn run_command(
+    location: PathBuf,
+    command_name: &str,
+    command: find_command_result::Command,
+    origin: CommandOrigin,
+    opt: &ExecuteOptInner,
+) -> anyhow::Result<()> {
+    let plan = plan_run(
+        location,
+        command_name,
+        command,
+        origin,
+        &opt.pre_opened_directories,
+        &opt.args,
+    )?;
+    if opt.dry_run {
+        plan.print(OutputFormat::Text)
+    } else {
+        plan.execute()
+    }
+}
+
+fn do_offline_run(request: &WaxRequest, opt: &ExecuteOptInner) -> anyhow::Result<()> {
+    let command_name = request.command.as_str();
+    let mut wax_index = open_wax_index(opt)?;
+    if let Ok((package_name, version, _)) = wax_index.search_for_entry(request.index_key()) {
         let package_version_str = format!("{}@{}", &package_name, &version);
         let location = wax_index.base_path().join(&package_version_str);
 
-        wax_index.save()?;
+        if !opt.dry_run {
+            wax_index.save()?;
+        }
 
         crate::logging::clear_stdout()?;
-        run(
-            command_name,
-            location,
-            &opt.pre_opened_directories,
-            &opt.args,
-        )
+        run(command_name, location, opt)
     } else {
         if opt.offline {
             return Err(ExecuteError::CommandNotFoundOfflineModeOfflineFlag(
@@ -580,6 +958,15 @@ fn do_offline_run(command_name: &str, opt: &ExecuteOptInner) -> anyhow::Result<(
     }
 }
 
+/// Open the wax index, without changing it with `--dry-run`.
+fn open_wax_index(opt: &ExecuteOptInner) -> Result<wax_index::WaxIndex, wax_index::WaxIndexError> {
+    if opt.dry_run {
+        wax_index::WaxIndex::open_read_only()
+    } else {
+        wax_index::WaxIndex::open()
+    }
+}
+
 impl From<wax_index::WaxIndexError> for ExecuteError {
     fn from(other: wax_index::WaxIndexError) -> Self {
         ExecuteError::WaxIndexError(other)
@@ -592,3 +979,251 @@ fn get_wax_cooldown() -> i32 {
         .map(|c| c.wax_cooldown)
         .unwrap_or(config::wax_default_cooldown())
 }
+
+fn get_wax_max_size() -> Option<u64> {
+    config::Config::from_file()
+        .ok()
+        .and_then(|c| c.wax_max_size)
+}
+
+/// Print the packages installed by wax, least recently used first.
+fn list_packages() -> anyhow::Result<()> {
+    let wax_index = wax_index::WaxIndex::open()?;
+    let rows: Vec<[String; 4]> = wax_index
+        .packages()?
+        .into_iter()
+        .map(|package| {
+            let commands = if package.commands.is_empty() {
+                "-".to_string()
+            } else {
+                package.commands.join(", ")
+            };
+            let last_used = package
+                .last_used
+                .map(|last_used| {
+                    time::strftime("%Y-%m-%d %H:%M", &time::at_utc(last_used))
+                        .expect("Format time!")
+                })
+                .unwrap_or_else(|| "-".to_string());
+            [
+                package.package_name,
+                commands,
+                last_used,
+                util::format_size(package.size),
+            ]
+        })
+        .collect();
+    if rows.is_empty() {
+        println!("No packages installed by wax");
+        return Ok(());
+    }
+    let header = [
+        "PACKAGE".to_string(),
+        "COMMANDS".to_string(),
+        "LAST USED".to_string(),
+        "SIZE".to_string(),
+    ];
+    let mut widths = [0; 4];
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        for (width, column) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(column.len());
+        }
+    }
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        println!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+        );
+    }
+    Ok(())
+}
+
+/// Remove the packages installed by wax that were not used within `older_than`, or all of them.
+fn prune_packages(older_than: Option<&str>) -> anyhow::Result<()> {
+    let older_than = older_than
+        .map(|duration| {
+            util::parse_duration(duration)
+                .ok_or_else(|| ExecuteError::InvalidDuration(duration.to_string()))
+        })
+        .transpose()?;
+    let mut wax_index = wax_index::WaxIndex::open()?;
+    let removed = wax_index.prune(older_than)?;
+    for package in removed.iter() {
+        println!("Removed {}", package.package_name);
+    }
+    let freed: u64 = removed.iter().map(|package| package.size).sum();
+    println!(
+        "Removed {} wax package(s), freeing {}",
+        removed.len(),
+        util::format_size(freed)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commands::execute::{package_signature, transform_args, WaxRequest};
+    use crate::util::OutputFormat;
+    use semver::{Version, VersionReq};
+    #[cfg(feature = "full")]
+    use {
+        crate::commands::execute::{install_registry_command, RegistryCommand},
+        crate::data::manifest::{MANIFEST_FILE_NAME, PACKAGES_DIR_NAME},
+        crate::dataflow::installed_packages::{Error, Install, KeyCheck, UnpackedPackage},
+        crate::dataflow::package_cache::PackageSignature,
+        crate::dataflow::WapmPackageKey,
+        std::fs,
+        std::path::Path,
+    };
+
+    /// Installs packages whose signature never verifies, when it is checked.
+    #[cfg(feature = "full")]
+    struct UnverifiedInstaller;
+
+    #[cfg(feature = "full")]
+    impl<'a> Install<'a> for UnverifiedInstaller {
+        fn install_package(
+            directory: &Path,
+            key: WapmPackageKey<'a>,
+            download_url: &str,
+            _registry: Option<&str>,
+            _expected_integrity: Option<&str>,
+            signature: Option<PackageSignature>,
+            key_check: KeyCheck,
+        ) -> Result<UnpackedPackage<'a>, Error> {
+            if let (Some(signature), false) = (signature, key_check == KeyCheck::Insecure) {
+                return Err(Error::FailedToValidateSignature(
+                    key.to_string(),
+                    signature.public_key_id,
+                    "the signature does not match".to_string(),
+                ));
+            }
+            let package_dir = directory
+                .join(PACKAGES_DIR_NAME)
+                .join(format!("{}@{}", key.name, key.version));
+            fs::create_dir_all(&package_dir).unwrap();
+            let manifest = format!(
+                "[package]\nname = \"{}\"\nversion = \"{}\"\ndescription = \"test package\"\n",
+                key.name, key.version
+            );
+            fs::write(package_dir.join(MANIFEST_FILE_NAME), manifest).unwrap();
+            Ok((key, package_dir, download_url.to_string(), None, None))
+        }
+    }
+
+    #[test]
+    fn parse_wax_request() {
+        let request = WaxRequest::parse("foo", None).unwrap();
+        assert!(!request.is_pinned());
+        assert_eq!("foo", request.index_key());
+
+        let request = WaxRequest::parse("foo@1.2.3", None).unwrap();
+        assert_eq!("foo", request.command);
+        assert!(request.is_exact());
+        assert!(request.version.unwrap().matches(&Version::new(1, 2, 3)));
+        assert!(!WaxRequest::parse("foo@1.2.3", None)
+            .unwrap()
+            .version
+            .unwrap()
+            .matches(&Version::new(1, 2, 4)));
+        assert_eq!(
+            "foo@1.2.3",
+            WaxRequest::parse("foo@1.2.3", None).unwrap().index_key()
+        );
+
+        let request = WaxRequest::parse("foo", Some("wasmer/pkg@^1")).unwrap();
+        assert_eq!(Some("wasmer/pkg".to_string()), request.package);
+        assert!(request.is_pinned());
+        assert!(!request.is_exact());
+        assert_eq!(Some(VersionReq::parse("^1").unwrap()), request.version);
+        assert_eq!("wasmer/pkg@^1:foo", request.index_key());
+        assert_eq!(
+            "wasmer/pkg@*:foo",
+            WaxRequest::parse("foo", Some("wasmer/pkg"))
+                .unwrap()
+                .index_key()
+        );
+
+        assert!(WaxRequest::parse("foo@2", Some("wasmer/pkg@^1")).is_err());
+        assert!(WaxRequest::parse("foo@not-a-version", None).is_err());
+    }
+
+    #[test]
+    fn parse_error_format() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        let opt = transform_args(&args(&["--error-format", "json", "foo"])).unwrap();
+        assert_eq!(Some(OutputFormat::Json), opt.error_format);
+        assert_eq!(Some("foo".to_string()), opt.command);
+        let opt = transform_args(&args(&["--error-format=text", "foo"])).unwrap();
+        assert_eq!(Some(OutputFormat::Text), opt.error_format);
+        // arguments after the command belong to the command
+        let opt = transform_args(&args(&["foo", "--error-format", "json"])).unwrap();
+        assert_eq!(None, opt.error_format);
+        assert_eq!(2, opt.args.len());
+        assert!(transform_args(&args(&["--error-format", "yaml", "foo"])).is_err());
+    }
+
+    #[test]
+    fn parse_package_signature() {
+        let signature = package_signature(
+            "key-id".to_string(),
+            "untrusted comment: minisign public key\nRWQ".to_string(),
+            "signature".to_string(),
+            "2019-04-09T21:19:39.181455+00:00",
+            false,
+            "wasmer".to_string(),
+        )
+        .unwrap();
+        assert_eq!("key-id", signature.public_key_id);
+        assert_eq!("wasmer", signature.owner);
+        assert!(package_signature(
+            "key-id".to_string(),
+            "key".to_string(),
+            "signature".to_string(),
+            "yesterday",
+            false,
+            "wasmer".to_string(),
+        )
+        .is_err());
+    }
+
+    #[cfg(feature = "full")]
+    #[test]
+    fn verify_checks_the_signature_of_the_package() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let command = RegistryCommand {
+            package_name: "_/foo".to_string(),
+            version: Version::new(1, 0, 0),
+            download_url: "https://example.com/foo-1.0.0.tar.gz".to_string(),
+            abi: None,
+            signature: Some(
+                package_signature(
+                    "key-id".to_string(),
+                    "key".to_string(),
+                    "signature".to_string(),
+                    "2019-04-09T21:19:39.181455+00:00",
+                    false,
+                    "wasmer".to_string(),
+                )
+                .unwrap(),
+            ),
+        };
+
+        let error = install_registry_command::<UnverifiedInstaller>(tmp_dir.path(), &command, true)
+            .unwrap_err();
+        match error.downcast_ref::<Error>() {
+            Some(Error::FailedToValidateSignature(_, public_key_id, _)) => {
+                assert_eq!("key-id", public_key_id)
+            }
+            _ => panic!("Expected the signature check to fail, got {}", error),
+        }
+
+        install_registry_command::<UnverifiedInstaller>(tmp_dir.path(), &command, false).unwrap();
+    }
+}
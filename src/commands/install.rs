@@ -6,6 +6,8 @@ use graphql_client::*;
 
 use crate::config::Config;
 use crate::dataflow;
+use crate::dataflow::package_cache::{NotCachedError, PackageCache};
+use crate::exit_code::{Classify, ErrorKind};
 use crate::util;
 use std::borrow::Cow;
 use std::path::Path;
@@ -22,10 +24,27 @@ pub struct InstallOpt {
     /// Agree to all prompts. Useful for non-interactive uses. (WARNING: this may cause undesired behavior)
     #[structopt(long = "force-yes", short = "y")]
     force_yes: bool,
+    /// Install the package(s) from the local package cache without connecting to the registry
+    #[structopt(long = "offline")]
+    offline: bool,
+    /// Install exactly what is in wapm.lock, failing instead of updating it if it is out of date
+    #[structopt(long = "frozen-lockfile")]
+    frozen_lockfile: bool,
+}
+
+/// Options for the `ci` subcommand
+#[derive(StructOpt, Debug)]
+pub struct CiOpt {
+    /// Agree to all prompts. Useful for non-interactive uses. (WARNING: this may cause undesired behavior)
+    #[structopt(long = "force-yes", short = "y")]
+    force_yes: bool,
+    /// Install the packages from the local package cache without connecting to the registry
+    #[structopt(long = "offline")]
+    offline: bool,
 }
 
 #[derive(Debug, Error)]
-enum InstallError {
+pub(crate) enum InstallError {
     #[error("Package not found in the registry: {name}")]
     PackageNotFound { name: String },
 
@@ -48,6 +67,36 @@ enum InstallError {
     InvalidPackageIdentifier { name: String },
     #[error("Must supply package names to install command when using --global/-g flag.")]
     MustSupplyPackagesWithGlobalFlag,
+
+    #[error(transparent)]
+    PackageNotCached(#[from] NotCachedError),
+
+    #[error(
+        "Packages cannot be added with --frozen-lockfile, because it never changes wapm.lock."
+    )]
+    CannotAddPackagesWithFrozenLockfile,
+
+    #[error("Could not open the local package cache. {0}")]
+    CannotOpenPackageCache(crate::dataflow::package_cache::OpenError),
+}
+
+impl Classify for InstallError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            InstallError::PackageNotFound { .. } | InstallError::NoVersionsAvailable { .. } => {
+                ErrorKind::Dependency
+            }
+            InstallError::CannotRegenLockFile(e) | InstallError::FailureInstallingPackages(e) => {
+                e.kind()
+            }
+            InstallError::CannotCreateInstallDirectory(_) => ErrorKind::Io,
+            InstallError::InvalidPackageIdentifier { .. }
+            | InstallError::MustSupplyPackagesWithGlobalFlag
+            | InstallError::CannotAddPackagesWithFrozenLockfile => ErrorKind::Usage,
+            InstallError::PackageNotCached(e) => e.kind(),
+            InstallError::CannotOpenPackageCache(e) => e.kind(),
+        }
+    }
 }
 
 #[derive(GraphQLQuery)]
@@ -78,6 +127,17 @@ pub fn install(options: InstallOpt) -> anyhow::Result<()> {
         "this function should only be called once!"
     );
 
+    if options.frozen_lockfile {
+        if !options.packages.is_empty() {
+            return Err(InstallError::CannotAddPackagesWithFrozenLockfile.into());
+        }
+        let install_directory = match options.global {
+            true => Config::get_globals_directory()?,
+            false => current_directory,
+        };
+        return install_frozen(&install_directory, options.offline);
+    }
+
     match (options.global, options.packages.is_empty()) {
         (global_flag::GLOBAL_INSTALL, package_args::NO_PACKAGES) => {
             // install all global packages - unacceptable use case
@@ -86,7 +146,7 @@ pub fn install(options: InstallOpt) -> anyhow::Result<()> {
         (global_flag::LOCAL_INSTALL, package_args::NO_PACKAGES) => {
             // install all packages locally
             let added_packages = vec![];
-            dataflow::update(added_packages, vec![], &current_directory)
+            update(added_packages, vec![], &current_directory, options.offline)
                 .map_err(|err| InstallError::FailureInstallingPackages(err))?;
             println!("Packages installed to wapm_packages!");
         }
@@ -99,6 +159,15 @@ pub fn install(options: InstallOpt) -> anyhow::Result<()> {
                     [package_name, package_version] => {
                         packages.push((package_name.to_string(), package_version.to_string()));
                     }
+                    [name] if options.offline => {
+                        // use the newest cached version instead of asking the default registry
+                        let cache = PackageCache::open(None)
+                            .map_err(InstallError::CannotOpenPackageCache)?;
+                        let version = cache.versions(name).pop().ok_or_else(|| {
+                            InstallError::PackageNotCached(NotCachedError(name.to_string()))
+                        })?;
+                        packages.push((name.to_string(), version.to_string()));
+                    }
                     [name] => {
                         let q = GetPackageQuery::build_query(get_package_query::Variables {
                             name: name.to_string(),
@@ -142,9 +211,13 @@ pub fn install(options: InstallOpt) -> anyhow::Result<()> {
             std::fs::create_dir_all(install_directory.clone())
                 .map_err(|err| InstallError::CannotCreateInstallDirectory(err))?;
 
-            let changes_applied =
-                dataflow::update(installed_packages.clone(), vec![], install_directory)
-                    .map_err(|err| InstallError::CannotRegenLockFile(err))?;
+            let changes_applied = update(
+                installed_packages.clone(),
+                vec![],
+                install_directory,
+                options.offline,
+            )
+            .map_err(|err| InstallError::CannotRegenLockFile(err))?;
 
             if changes_applied {
                 if options.global {
@@ -159,3 +232,39 @@ pub fn install(options: InstallOpt) -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+/// Run the ci command, which installs with a frozen lockfile
+pub fn ci(options: CiOpt) -> anyhow::Result<()> {
+    let current_directory = crate::config::Config::get_current_dir()?;
+    let _value = util::set_wapm_should_accept_all_prompts(options.force_yes);
+    debug_assert!(
+        _value.is_some(),
+        "this function should only be called once!"
+    );
+    install_frozen(&current_directory, options.offline)
+}
+
+fn install_frozen(directory: &Path, offline: bool) -> anyhow::Result<()> {
+    let changes_applied = dataflow::install_frozen(directory, offline)
+        .map_err(InstallError::FailureInstallingPackages)?;
+    if changes_applied {
+        println!("Packages installed to wapm_packages!");
+    } else {
+        println!("All packages in wapm.lock are already installed");
+    }
+    Ok(())
+}
+
+/// Install from the registry, or only from the local package cache when offline.
+fn update<P: AsRef<Path>>(
+    added_packages: Vec<(&str, &str)>,
+    removed_packages: Vec<&str>,
+    directory: P,
+    offline: bool,
+) -> Result<bool, dataflow::Error> {
+    if offline {
+        dataflow::update_offline(added_packages, removed_packages, directory)
+    } else {
+        dataflow::update(added_packages, removed_packages, directory)
+    }
+}
@@ -1,11 +1,17 @@
-use crate::config::Config;
-use crate::graphql::execute_query;
+use crate::config;
+use crate::exit_code::{Classify, ErrorKind};
+use crate::graphql::{execute_query, GraphQLError};
 use rpassword_wasi as rpassword;
 use std::io::prelude::*;
 use std::io::{stdin, stdout};
+use structopt::StructOpt;
+use thiserror::Error;
 
 use graphql_client::*;
 
+/// The payload of a verified token.
+type GenericScalar = serde_json::Value;
+
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "graphql/schema.graphql",
@@ -14,30 +20,101 @@ use graphql_client::*;
 )]
 struct LoginMutation;
 
-pub fn login() -> anyhow::Result<()> {
-    print!("Username: ");
-    stdout().flush().ok().expect("Could not flush stdout");
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/schema.graphql",
+    query_path = "graphql/queries/verify_token.graphql",
+    response_derives = "Debug"
+)]
+struct VerifyTokenMutation;
 
-    let buffer = &mut String::new();
-    stdin().read_line(buffer)?;
-    let username = buffer.trim_end();
+#[derive(StructOpt, Debug)]
+pub struct LoginOpt {
+    /// Log in with an existing token instead of a username and password
+    #[structopt(long = "token", conflicts_with_all = &["username", "password-stdin"])]
+    token: Option<String>,
 
-    let password = rpassword::prompt_password("Password: ").expect("Can't get password");
+    /// The username to log in with, asked for if it is not given
+    #[structopt(long = "username", short = "u")]
+    username: Option<String>,
 
-    let q = LoginMutation::build_query(login_mutation::Variables {
-        username: username.to_string(),
-        password: password.to_string(),
-    });
-    let response: login_mutation::ResponseData = execute_query(&q)?;
-    let token = match response.token_auth {
-        Some(token_auth) => token_auth.refresh_token,
-        None => None,
+    /// Read the password from stdin instead of asking for it
+    #[structopt(long = "password-stdin", requires = "username")]
+    password_stdin: bool,
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum LoginError {
+    #[error("The registry rejected the token: {0}")]
+    InvalidToken(String),
+}
+
+impl Classify for LoginError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Registry
+    }
+}
+
+pub fn login(login_opt: LoginOpt) -> anyhow::Result<()> {
+    let token = match login_opt.token {
+        Some(token) => {
+            verify_token(&token)?;
+            Some(token)
+        }
+        None => {
+            let username = match login_opt.username {
+                Some(username) => username,
+                None => {
+                    print!("Username: ");
+                    stdout().flush().expect("Could not flush stdout");
+
+                    let buffer = &mut String::new();
+                    stdin().read_line(buffer)?;
+                    buffer.trim_end().to_string()
+                }
+            };
+            let password = if login_opt.password_stdin {
+                let mut password = String::new();
+                stdin().read_to_string(&mut password)?;
+                password.trim_end_matches(&['\r', '\n'][..]).to_string()
+            } else {
+                rpassword::prompt_password("Password: ").expect("Can't get password")
+            };
+            log_in(username, password)?
+        }
     };
     if let Some(token) = token {
         // Save the token
-        let mut config = Config::from_file()?;
-        config.registry.token = Some(token);
-        config.save()?;
+        config::save_token(Some(token))?;
     }
     Ok(())
 }
+
+/// Get a token for the username and password.
+fn log_in(username: String, password: String) -> anyhow::Result<Option<String>> {
+    let q = LoginMutation::build_query(login_mutation::Variables { username, password });
+    let response: login_mutation::ResponseData = execute_query(&q)?;
+    Ok(match response.token_auth {
+        Some(token_auth) => token_auth.refresh_token,
+        None => None,
+    })
+}
+
+/// Check that the registry accepts the token.
+fn verify_token(token: &str) -> anyhow::Result<()> {
+    let q = VerifyTokenMutation::build_query(verify_token_mutation::Variables {
+        token: token.to_string(),
+    });
+    let response: verify_token_mutation::ResponseData =
+        execute_query(&q).map_err(|e| match e.downcast::<GraphQLError>() {
+            Ok(e) => LoginError::InvalidToken(e.to_string()).into(),
+            Err(e) => e,
+        })?;
+    match response
+        .verify_token
+        .and_then(|verify_token| verify_token.payload)
+    {
+        Some(_payload) => Ok(()),
+        None => Err(LoginError::InvalidToken("it has no payload".to_string()).into()),
+    }
+}
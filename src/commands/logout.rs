@@ -1,8 +1,5 @@
-use crate::config::Config;
+use crate::config;
 
 pub fn logout() -> anyhow::Result<()> {
-    let mut config = Config::from_file()?;
-    config.registry.token = None;
-    config.save()?;
-    Ok(())
+    config::save_token(None)
 }
@@ -1,5 +1,7 @@
 //! List of exported subcommands for use by wapm
 
+use crate::exit_code::{classify_as, ErrorKind};
+
 mod add;
 mod bin;
 mod completions;
@@ -14,13 +16,17 @@ mod list;
 mod login;
 mod logout;
 #[cfg(feature = "full")]
+mod outdated;
+#[cfg(feature = "full")]
 mod publish;
 mod remove;
 mod run;
 #[cfg(feature = "full")]
 mod search;
 mod uninstall;
+mod update;
 mod validate;
+mod which;
 mod whoami;
 
 pub use self::add::{add, AddOpt};
@@ -29,19 +35,33 @@ pub use self::completions::CompletionOpt;
 pub use self::config::{config, ConfigOpt};
 pub use self::execute::{execute, ExecuteOpt};
 pub use self::init::{init, InitOpt};
-pub use self::install::{install, InstallOpt};
+pub use self::install::{ci, install, CiOpt, InstallOpt};
 #[cfg(feature = "full")]
 pub use self::keys::{keys, KeyOpt};
 #[cfg(feature = "full")]
 pub use self::list::{list, ListOpt};
-pub use self::login::login;
+pub use self::login::{login, LoginOpt};
 pub use self::logout::logout;
 #[cfg(feature = "full")]
+pub use self::outdated::{outdated, OutdatedOpt};
+#[cfg(feature = "full")]
 pub use self::publish::{publish, PublishOpt};
 pub use self::remove::{remove, RemoveOpt};
-pub use self::run::{run, RunOpt};
+pub use self::run::{run, ChildProcessError, RunOpt};
 #[cfg(feature = "full")]
 pub use self::search::{search, SearchOpt};
 pub use self::uninstall::{uninstall, UninstallOpt};
+pub use self::update::{update, UpdateOpt};
 pub use self::validate::{validate, ValidateOpt};
+pub use self::which::{which, WhichOpt};
 pub use self::whoami::whoami;
+
+/// The kind of an error of one of the subcommands, if it is one.
+pub(crate) fn error_kind(error: &(dyn std::error::Error + 'static)) -> Option<ErrorKind> {
+    classify_as::<execute::ExecuteError>(error)
+        .or_else(|| classify_as::<execute::ExecuteArgParsingError>(error))
+        .or_else(|| classify_as::<install::InstallError>(error))
+        .or_else(|| classify_as::<login::LoginError>(error))
+        .or_else(|| classify_as::<run::RunError>(error))
+        .or_else(|| classify_as::<update::UpdateError>(error))
+}
@@ -0,0 +1,209 @@
+//! Subcommand for finding dependencies that have newer versions in the registry
+
+use crate::dataflow::lockfile_packages::{LockfilePackages, LockfileResult};
+use crate::dataflow::manifest_packages::{ManifestPackages, ManifestResult};
+use crate::dataflow::resolved_packages::{PackageRegistries, RegistryResolver, Resolve};
+use crate::dataflow::{PackageKey, WapmPackageKey, WapmPackageRange};
+use crate::util::OutputFormat;
+use prettytable::{format, Table};
+use semver::{Version, VersionReq};
+use std::collections::HashMap;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct OutdatedOpt {
+    /// The output format, `table` or `json`
+    #[structopt(long = "format", default_value = "table")]
+    format: OutputFormat,
+}
+
+/// A dependency of the manifest whose locked version is not the newest published version.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct OutdatedPackage {
+    name: String,
+    /// The version requirement in the manifest
+    requirement: String,
+    /// The version in the lockfile, if the dependency is locked
+    locked: Option<Version>,
+    /// The newest published version matching the requirement
+    wanted: Option<Version>,
+    /// The newest published version
+    latest: Option<Version>,
+}
+
+pub fn outdated(options: OutdatedOpt) -> anyhow::Result<()> {
+    let current_dir = crate::config::Config::get_current_dir()?;
+    let manifest = match ManifestResult::find_in_directory(&current_dir) {
+        ManifestResult::Manifest(manifest) => manifest,
+        ManifestResult::NoManifest => {
+            return Err(anyhow!("No wapm.toml found in the current directory"))
+        }
+        ManifestResult::ManifestError(e) => return Err(e.into()),
+    };
+    let manifest_packages = ManifestPackages::new_from_manifest(&manifest)?;
+    let lockfile_packages =
+        LockfilePackages::new_from_result(LockfileResult::find_in_directory(&current_dir))?;
+
+    let names = manifest_packages
+        .packages
+        .iter()
+        .filter(|key| !key.is_source_package())
+        .map(|key| key.name().to_string())
+        .collect::<Vec<_>>();
+    let published_versions = if names.is_empty() {
+        HashMap::new()
+    } else {
+        let registries = PackageRegistries::from_manifest(&manifest);
+        RegistryResolver::get_package_versions(names, &registries)?
+    };
+    let outdated_packages =
+        find_outdated_packages(&manifest_packages, &lockfile_packages, &published_versions);
+
+    match options.format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&outdated_packages)?),
+        OutputFormat::Text if outdated_packages.is_empty() => {
+            println!("All dependencies are up to date")
+        }
+        OutputFormat::Text => print!("{}", create_outdated_ascii_table(&outdated_packages)),
+    }
+    Ok(())
+}
+
+/// Compare every registry dependency of the manifest with its locked version and the versions
+/// published in the registry. Dependencies from a path or a git repository are skipped.
+fn find_outdated_packages(
+    manifest_packages: &ManifestPackages,
+    lockfile_packages: &LockfilePackages,
+    published_versions: &HashMap<String, Vec<Version>>,
+) -> Vec<OutdatedPackage> {
+    let mut outdated_packages = vec![];
+    for manifest_key in manifest_packages.packages.iter() {
+        let (name, requirement) = match manifest_key {
+            PackageKey::WapmPackage(WapmPackageKey { name, version }) => {
+                (name, VersionReq::exact(version))
+            }
+            PackageKey::WapmPackageRange(WapmPackageRange { name, version_req }) => {
+                (name, version_req.clone())
+            }
+            PackageKey::PathPackage(_) | PackageKey::GitPackage(_) => continue,
+        };
+        let locked = lockfile_packages
+            .packages
+            .keys()
+            .filter(|key| lockfile_packages.is_locked_for(key, manifest_key))
+            .filter_map(|key| match key {
+                PackageKey::WapmPackage(WapmPackageKey { version, .. }) => Some(version.clone()),
+                _ => None,
+            })
+            .max();
+        let versions = published_versions
+            .get(name.as_ref())
+            .cloned()
+            .unwrap_or_default();
+        let wanted = versions
+            .iter()
+            .filter(|version| requirement.matches(version))
+            .max()
+            .cloned();
+        // prefer releases, but fall back to pre-releases for packages without a release
+        let latest = versions
+            .iter()
+            .filter(|version| !version.is_prerelease())
+            .max()
+            .or_else(|| versions.iter().max())
+            .cloned();
+        if locked.is_none() || latest > locked {
+            outdated_packages.push(OutdatedPackage {
+                name: name.to_string(),
+                requirement: requirement.to_string(),
+                locked,
+                wanted,
+                latest,
+            });
+        }
+    }
+    outdated_packages.sort_by(|a, b| a.name.cmp(&b.name));
+    outdated_packages
+}
+
+fn create_outdated_ascii_table(outdated_packages: &[OutdatedPackage]) -> String {
+    let display = |version: &Option<Version>| {
+        version
+            .as_ref()
+            .map(Version::to_string)
+            .unwrap_or_else(|| "-".to_string())
+    };
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.add_row(row!["PACKAGE", "REQUIRED", "LOCKED", "WANTED", "LATEST"]);
+    for package in outdated_packages {
+        table.add_row(row![
+            package.name,
+            package.requirement,
+            display(&package.locked),
+            display(&package.wanted),
+            display(&package.latest),
+        ]);
+    }
+    format!("{}", table)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commands::outdated::find_outdated_packages;
+    use crate::dataflow::lockfile_packages::{LockfilePackage, LockfilePackages};
+    use crate::dataflow::manifest_packages::ManifestPackages;
+    use crate::dataflow::PackageKey;
+    use semver::{Version, VersionReq};
+    use std::collections::{HashMap, HashSet};
+
+    #[test]
+    fn find_newer_versions() {
+        let mut manifest_keys = HashSet::new();
+        manifest_keys.insert(PackageKey::new_registry_package_range(
+            "_/foo",
+            VersionReq::parse("^1").unwrap(),
+        ));
+        manifest_keys.insert(PackageKey::new_registry_package_range(
+            "_/bar",
+            VersionReq::parse("^2").unwrap(),
+        ));
+        let manifest_packages = ManifestPackages {
+            packages: manifest_keys,
+        };
+        let mut packages = HashMap::new();
+        packages.insert(
+            PackageKey::new_registry_package("_/foo", Version::new(1, 0, 0)),
+            LockfilePackage::default(),
+        );
+        packages.insert(
+            PackageKey::new_registry_package("_/bar", Version::new(2, 1, 0)),
+            LockfilePackage::default(),
+        );
+        let lockfile_packages = LockfilePackages { packages };
+        let mut published_versions = HashMap::new();
+        published_versions.insert(
+            "_/foo".to_string(),
+            vec![
+                Version::new(1, 0, 0),
+                Version::new(1, 3, 0),
+                Version::new(2, 0, 0),
+                Version::parse("3.0.0-beta").unwrap(),
+            ],
+        );
+        published_versions.insert(
+            "_/bar".to_string(),
+            vec![Version::new(2, 0, 0), Version::new(2, 1, 0)],
+        );
+
+        let outdated_packages =
+            find_outdated_packages(&manifest_packages, &lockfile_packages, &published_versions);
+
+        assert_eq!(1, outdated_packages.len());
+        let foo = &outdated_packages[0];
+        assert_eq!("_/foo", foo.name);
+        assert_eq!(Some(Version::new(1, 0, 0)), foo.locked);
+        assert_eq!(Some(Version::new(1, 3, 0)), foo.wanted);
+        assert_eq!(Some(Version::new(2, 0, 0)), foo.latest);
+    }
+}
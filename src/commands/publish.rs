@@ -166,13 +166,12 @@ pub fn publish(publish_opts: PublishOpt) -> anyhow::Result<()> {
     assert!(archive_path.is_file());
     if !publish_opts.dry_run {
         let _response: publish_package_mutation::ResponseData =
-            execute_query_modifier(&q, |f| f.file(archive_name, archive_path).unwrap()).map_err(
-                |e| {
+            execute_query_modifier(&q, |f| f.file(archive_name.clone(), &archive_path).unwrap())
+                .map_err(|e| {
                     #[cfg(feature = "telemetry")]
                     sentry::integrations::anyhow::capture_anyhow(&e);
                     e
-                },
-            )?;
+                })?;
     }
 
     println!(
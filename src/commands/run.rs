@@ -1,12 +1,17 @@
 use crate::config::Config;
-use crate::constants::DEFAULT_RUNTIME;
 use crate::data::lock::is_lockfile_out_of_date;
+use crate::data::manifest::{Manifest, ManifestError, ValidationError};
 use crate::dataflow;
 use crate::dataflow::find_command_result;
 use crate::dataflow::find_command_result::get_command_from_anywhere;
 use crate::dataflow::manifest_packages::ManifestResult;
-use crate::util::get_runtime_with_args;
+use crate::exit_code::{Classify, ErrorKind};
+use crate::runtime::{RunOptions, RuntimeCommand};
+use crate::util::OutputFormat;
+use semver::Version;
+use std::collections::BTreeMap;
 use std::ffi::OsString;
+use std::fmt;
 use std::path::{Path, PathBuf};
 #[cfg(not(target_os = "wasi"))]
 use std::process::Command;
@@ -17,6 +22,12 @@ use wasm_bus_process::prelude::Command;
 
 #[derive(StructOpt, Debug)]
 pub struct RunOpt {
+    /// Print how the command would be run instead of running it
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+    /// The output format of `--dry-run`, `text` or `json`
+    #[structopt(long = "format", default_value = "text")]
+    format: OutputFormat,
     /// Command name
     command: String,
     /// WASI pre-opened directory
@@ -28,26 +39,43 @@ pub struct RunOpt {
 }
 
 pub fn run(run_options: RunOpt) -> anyhow::Result<()> {
-    let command_name = run_options.command.as_str();
-    let args = &run_options.args;
+    let plan = plan_command(
+        &run_options.command,
+        &run_options.pre_opened_directories,
+        &run_options.args,
+        !run_options.dry_run,
+    )?;
+    if run_options.dry_run {
+        plan.print(run_options.format)
+    } else {
+        plan.execute()
+    }
+}
+
+/// Find a command in the local directory or else in the global install directory, and plan how
+/// to run it. The local lockfile is updated first if the manifest has changed, unless
+/// `update_lockfile` is false, e.g. when the plan is only printed, in which case a stale lockfile
+/// is reported instead.
+pub(crate) fn plan_command(
+    command_name: &str,
+    pre_opened_directories: &[String],
+    args: &[OsString],
+    update_lockfile: bool,
+) -> anyhow::Result<RunPlan> {
     let current_dir = crate::config::Config::get_current_dir()?;
 
-    // always update the local lockfile if the manifest has changed
     match is_lockfile_out_of_date(&current_dir) {
         Ok(false) => {}
+        Ok(true) if !update_lockfile => {
+            warn!("The lockfile is out of date with wapm.toml, the command may change after running `wapm install`");
+        }
+        Err(_) if !update_lockfile => {}
         _ => dataflow::update(vec![], vec![], &current_dir)
             .map(|_| ())
             .map_err(|e| RunError::CannotRegenLockfile(command_name.to_string(), e))?,
     }
 
-    let find_command_result::Command {
-        source: source_path_buf,
-        manifest_dir,
-        args: _,
-        module_name,
-        is_global,
-        prehashed_cache_key,
-    } = match get_command_from_anywhere(command_name) {
+    let mut command = match get_command_from_anywhere(command_name) {
         Err(find_command_result::Error::CommandNotFound(command)) => {
             let package_info = find_command_result::PackageInfoFromCommand::get(command)?;
             return Err(anyhow!("Command {} not found, but package {} version {} has this command. You can install it with `wapm install {}@{}`",
@@ -61,215 +89,597 @@ pub fn run(run_options: RunOpt) -> anyhow::Result<()> {
         otherwise => otherwise?,
     };
 
-    let run_dir = if is_global {
-        Config::get_globals_directory().unwrap()
+    let (run_dir, origin) = if command.is_global {
+        (
+            Config::get_globals_directory().unwrap(),
+            CommandOrigin::Global,
+        )
     } else {
-        current_dir.clone()
+        (current_dir, CommandOrigin::Local)
     };
 
-    let manifest_dir = run_dir.join(manifest_dir);
+    command.manifest_dir = run_dir.join(&command.manifest_dir);
 
-    do_run(
+    plan_run(
         run_dir,
-        source_path_buf,
-        manifest_dir,
         command_name,
-        &module_name,
-        &run_options.pre_opened_directories,
-        &args,
-        prehashed_cache_key,
+        command,
+        origin,
+        pre_opened_directories,
+        args,
     )
 }
 
-pub(crate) fn do_run(
+/// Where a command was found.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CommandOrigin {
+    /// The project in the current directory or one of its dependencies
+    Local,
+    /// A globally installed package
+    Global,
+    /// A package installed by `wax`
+    Wax,
+}
+
+impl fmt::Display for CommandOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let origin = match self {
+            CommandOrigin::Local => "local",
+            CommandOrigin::Global => "global",
+            CommandOrigin::Wax => "wax",
+        };
+        write!(f, "{}", origin)
+    }
+}
+
+/// How a command is run: the executable with its arguments and environment, and where the
+/// module of the command came from.
+#[derive(Debug)]
+pub(crate) struct RunPlan {
+    command_name: String,
+    origin: CommandOrigin,
+    package_name: String,
+    package_version: Version,
+    module_name: String,
+    options: RunOptions,
+    program: String,
+    args: Vec<OsString>,
+    env: BTreeMap<String, String>,
+}
+
+/// Plan how to run a command found in `run_dir`.
+pub(crate) fn plan_run(
     run_dir: PathBuf,
-    source_path_buf: PathBuf,
-    manifest_dir: PathBuf,
     command_name: &str,
-    module_name: &str,
+    command: find_command_result::Command,
+    origin: CommandOrigin,
     pre_opened_directories: &[String],
     args: &[OsString],
-    prehashed_cache_key: Option<String>,
-) -> anyhow::Result<()> {
-    debug!(
-        "Running module located at {:?}",
-        &run_dir.join(&source_path_buf)
-    );
+) -> anyhow::Result<RunPlan> {
+    let module_path = run_dir.join(&command.source);
+    debug!("Running module located at {:?}", &module_path);
 
-    run_dir.join(&source_path_buf).metadata().map_err(|_| {
+    module_path.metadata().map_err(|_| {
         RunError::SourceForCommandNotFound(
             command_name.to_string(),
-            module_name.to_string(),
-            source_path_buf.to_string_lossy().to_string(),
+            command.module_name.clone(),
+            command.source.to_string_lossy().to_string(),
         )
     })?;
 
-    let wasmer_extra_flags: Option<Vec<OsString>> =
-        match ManifestResult::find_in_directory(&manifest_dir) {
-            ManifestResult::Manifest(manifest) => {
-                manifest
-                    .package
-                    .wasmer_extra_flags
-                    .clone()
-                    .map(|extra_flags| {
-                        extra_flags
-                            .split_whitespace()
-                            .map(|str| OsString::from(str))
-                            .collect()
-                    })
+    let mut options = RunOptions {
+        module_path,
+        command_name: Some(command_name.to_string()),
+        pre_opened_directories: pre_opened_directories.to_vec(),
+        args: with_main_args(command.args.as_deref(), args)?,
+        cache_key: command.prehashed_cache_key,
+        ..RunOptions::default()
+    };
+    let manifest_dir = &command.manifest_dir;
+    if let ManifestResult::Manifest(manifest) = ManifestResult::find_in_directory(manifest_dir) {
+        add_manifest_settings(&mut options, &manifest, command_name, manifest_dir)?;
+    }
+
+    // on wasi the module itself is run as a process
+    let (program, args, env) = if cfg!(target_os = "wasi") {
+        (
+            command.source.to_string_lossy().to_string(),
+            options.args.clone(),
+            options.env.clone(),
+        )
+    } else {
+        let runtime = RuntimeCommand::find(&run_dir)?;
+        let args = runtime.command_args(&options);
+        let env = runtime.backend.process_env(&options);
+        (runtime.program, args, env)
+    };
+
+    Ok(RunPlan {
+        command_name: command_name.to_string(),
+        origin,
+        package_name: command.package_name,
+        package_version: command.package_version,
+        module_name: command.module_name,
+        options,
+        program,
+        args,
+        env,
+    })
+}
+
+impl RunPlan {
+    /// Run the command and wait for it to exit.
+    pub(crate) fn execute(self) -> anyhow::Result<()> {
+        debug!("Running {:?} with args: {:?}", self.program, self.args);
+        let mut cmd = Command::new(&self.program);
+        cmd.args(&self.args);
+        for (key, value) in self.env.iter() {
+            cmd.env(key, value);
+        }
+        #[cfg(target_os = "wasi")]
+        {
+            for directory in self.options.pre_opened_directories.iter() {
+                cmd.pre_open(directory.replace("\"", ""));
             }
-            _ => None,
-        };
+            for (guest_path, host_path) in self.options.mapped_directories.iter() {
+                cmd.pre_open(format!("{}:{}", guest_path, host_path.to_string_lossy()));
+            }
+        }
 
-    let mut wasi_preopened_dir_flags: Vec<OsString> = pre_opened_directories
-        .iter()
-        .map(|entry| OsString::from(format!("--dir={}", entry)))
-        .collect();
-
-    let mut disable_command_rename = false;
-
-    match ManifestResult::find_in_directory(&manifest_dir) {
-        ManifestResult::Manifest(manifest) => {
-            disable_command_rename = manifest.package.disable_command_rename;
-            manifest.package.rename_commands_to_raw_command_name;
-            if let Some(ref fs) = manifest.fs {
-                // todo: normalize (rm `:` and newline, etc) these paths if we haven't yet
-                for (guest_path, host_path) in fs.iter() {
-                    wasi_preopened_dir_flags.push(OsString::from(format!(
-                        "--mapdir={}:{}",
-                        guest_path,
-                        manifest_dir.join(host_path).to_string_lossy(),
-                    )));
-                }
+        // forward termination requests to the runtime while waiting for it to exit, from before
+        // it is spawned so that none is missed
+        #[cfg(unix)]
+        let signal_forwarder = signal_forwarding::SignalForwarder::install();
+        let mut child = cmd.spawn().map_err(|e| -> RunError {
+            RunError::ProcessFailed(self.program.clone(), format!("{:?}", e))
+        })?;
+        #[cfg(unix)]
+        signal_forwarder.forward_to(child.id());
+        let status = child.wait()?;
+
+        let command_name = self.command_name.as_str();
+        #[cfg(target_os = "wasi")]
+        return match status.code() {
+            Some(0) => Ok(()),
+            code => {
+                Err(ChildProcessError::Exited(command_name.to_string(), code.unwrap_or(1)).into())
             }
+        };
+        #[cfg(not(target_os = "wasi"))]
+        check_exit_status(command_name, status).map_err(anyhow::Error::from)
+    }
+
+    /// Print the plan instead of running it.
+    pub(crate) fn print(&self, format: OutputFormat) -> anyhow::Result<()> {
+        match format {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&self.to_json())?),
+            OutputFormat::Text => print!("{}", self.to_text()),
         }
-        _ => (),
+        Ok(())
     }
 
-    let (runtime, runtime_args) = get_runtime_with_args();
+    fn argv(&self) -> Vec<String> {
+        std::iter::once(self.program.clone())
+            .chain(
+                self.args
+                    .iter()
+                    .map(|arg| arg.to_string_lossy().to_string()),
+            )
+            .collect()
+    }
 
-    let mut cmd;
-    if cfg!(target_os = "wasi") {
-        debug!("Running wapm process: {:?}", source_path_buf);
-        cmd = Command::new(source_path_buf.to_string_lossy().as_ref());
-        cmd.args(args);
-        #[cfg(target_os = "wasi")]
-        for preopen in wasi_preopened_dir_flags
-            .iter()
-            .map(|a| a.to_string_lossy())
-            .filter_map(|a| a.split_once("=").map(|a| a.1.replace("\"", "")))
-        {
-            cmd.pre_open(preopen.to_string());
+    fn to_text(&self) -> String {
+        let mut text = format!(
+            "command: {}\npackage: {} {} ({})\nmodule:  {} ({})\n",
+            self.command_name,
+            self.package_name,
+            self.package_version,
+            self.origin,
+            self.module_name,
+            self.options.module_path.to_string_lossy(),
+        );
+        for (key, value) in self.env.iter() {
+            text.push_str(&format!("env:     {}={}\n", key, value));
         }
-    } else {
-        // avoid `wasmer-js`, allow other wasmers
-        let using_default_runtime = Path::new(&runtime)
-            .file_name()
-            .map(|file_name| file_name.to_string_lossy().ends_with(DEFAULT_RUNTIME))
-            .unwrap_or(false);
-        let command_override_name = if !using_default_runtime || disable_command_rename {
-            None
-        } else {
-            Some(command_name.to_string())
-        };
-        let command_vec = create_run_command(
-            args,
-            wasmer_extra_flags,
-            wasi_preopened_dir_flags,
-            &run_dir,
-            source_path_buf,
-            command_override_name,
-            prehashed_cache_key,
-        )?;
-        debug!("Running command with args: {:?}", command_vec);
-        cmd = Command::new(&runtime);
-        cmd.args(&runtime_args);
-        cmd.args(&command_vec);
-    };
+        let argv: Vec<String> = self
+            .argv()
+            .into_iter()
+            .map(|arg| {
+                shlex::try_quote(&arg)
+                    .map(|arg| arg.to_string())
+                    .unwrap_or(arg)
+            })
+            .collect();
+        text.push_str(&format!("argv:    {}\n", argv.join(" ")));
+        text
+    }
 
-    let mut child = cmd
-        .spawn()
-        .map_err(|e| -> RunError { RunError::ProcessFailed(runtime, format!("{:?}", e)) })?;
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "command": self.command_name,
+            "origin": self.origin,
+            "package": {
+                "name": self.package_name,
+                "version": self.package_version,
+            },
+            "module": {
+                "name": self.module_name,
+                "path": self.options.module_path,
+            },
+            "mapped_directories": self.options.mapped_directories,
+            "cache_key": self.options.cache_key,
+            "env": self.env,
+            "argv": self.argv(),
+        })
+    }
+}
 
-    child.wait()?;
+/// Add the mapped directories and runtime flags of the package, and the environment variables,
+/// mapped directories and runtime flags of the command from its manifest.
+fn add_manifest_settings(
+    options: &mut RunOptions,
+    manifest: &Manifest,
+    command_name: &str,
+    manifest_dir: &Path,
+) -> anyhow::Result<()> {
+    if manifest.package.disable_command_rename {
+        options.command_name = None;
+    }
+    if let Some(ref extra_flags) = manifest.package.wasmer_extra_flags {
+        let extra_flags = shlex::split(extra_flags).ok_or_else(|| {
+            ManifestError::ValidationError(ValidationError::InvalidPackageExtraFlags(
+                extra_flags.clone(),
+                manifest.package.name.clone(),
+            ))
+        })?;
+        options
+            .wasmer_extra_flags
+            .extend(extra_flags.into_iter().map(OsString::from));
+    }
+    // todo: normalize (rm `:` and newline, etc) these paths if we haven't yet
+    for (guest_path, host_path) in manifest.fs.iter().flatten() {
+        options
+            .mapped_directories
+            .push((guest_path.clone(), manifest_dir.join(host_path)));
+    }
+    // the settings of the command are added to the settings of the package
+    let command = match manifest
+        .command
+        .iter()
+        .flatten()
+        .find(|command| command.name == command_name)
+    {
+        Some(command) => command,
+        None => return Ok(()),
+    };
+    for (guest_path, host_path) in command.mapdir.iter().flatten() {
+        options
+            .mapped_directories
+            .push((guest_path.clone(), manifest_dir.join(host_path)));
+    }
+    options.env.extend(command.env.clone().unwrap_or_default());
+    if let Some(ref extra_flags) = command.wasmer_extra_flags {
+        let extra_flags = shlex::split(extra_flags).ok_or_else(|| {
+            ManifestError::ValidationError(ValidationError::InvalidExtraFlags(
+                extra_flags.clone(),
+                command.name.clone(),
+            ))
+        })?;
+        options
+            .wasmer_extra_flags
+            .extend(extra_flags.into_iter().map(OsString::from));
+    }
     Ok(())
 }
 
-fn create_run_command<P: AsRef<Path>, P2: AsRef<Path>>(
-    args: &[OsString],
-    wasmer_extra_flags: Option<Vec<OsString>>,
-    wasi_preopened_dir_flags: Vec<OsString>,
-    directory: P,
-    wasm_file_path: P2,
-    override_command_name: Option<String>,
-    prehashed_cache_key: Option<String>,
-) -> anyhow::Result<Vec<OsString>> {
-    let mut path = PathBuf::new();
-    path.push(directory);
-    path.push(wasm_file_path);
-    let path_string = path.into_os_string();
-    let command_vec = vec![path_string];
-    let override_command_name = override_command_name
-        .map(|cn| vec![OsString::from(format!("--command-name={}", cn))])
-        .unwrap_or_default();
-    let prehashed_cache_key_flag = prehashed_cache_key
-        .map(|pck| vec![OsString::from(format!("--cache-key=\"{}\"", pck))])
-        .unwrap_or_default();
-
-    // NOTE:
-    // for optional types, use an empty vec here:
-    // an empty OsString may pass empty args to the child program which can cause issues
-    Ok([
-        &command_vec[..],
-        &override_command_name[..],
-        &wasi_preopened_dir_flags[..],
-        &wasmer_extra_flags.unwrap_or_default()[..],
-        &prehashed_cache_key_flag[..],
-        &[OsString::from("--")],
-        &args[..],
-    ]
-    .concat())
+/// Combine the `main_args` of a command with the arguments given by the user. The `main_args`
+/// are split like a shell would split them and come first.
+fn with_main_args(main_args: Option<&str>, args: &[OsString]) -> anyhow::Result<Vec<OsString>> {
+    let main_args = match main_args {
+        Some(main_args) => shlex::split(main_args)
+            .ok_or_else(|| RunError::InvalidMainArgs(main_args.to_string()))?,
+        None => return Ok(args.to_vec()),
+    };
+    let mut combined_args: Vec<OsString> = main_args.into_iter().map(OsString::from).collect();
+    combined_args.extend(args.iter().cloned());
+    Ok(combined_args)
+}
+
+#[cfg(not(target_os = "wasi"))]
+fn check_exit_status(
+    command_name: &str,
+    status: std::process::ExitStatus,
+) -> Result<(), ChildProcessError> {
+    if status.success() {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return Err(ChildProcessError::Signaled(
+                command_name.to_string(),
+                signal,
+            ));
+        }
+    }
+    Err(ChildProcessError::Exited(
+        command_name.to_string(),
+        status.code().unwrap_or(1),
+    ))
+}
+
+#[cfg(unix)]
+mod signal_forwarding {
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    static CHILD_PID: AtomicI32 = AtomicI32::new(0);
+    /// A signal that arrived before the child was spawned, 0 for none.
+    static PENDING_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+    const FORWARDED_SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+    extern "C" fn forward_signal(
+        signal: libc::c_int,
+        info: *mut libc::siginfo_t,
+        _context: *mut libc::c_void,
+    ) {
+        // the terminal already sends Ctrl-C to the whole foreground process group, child included
+        if signal == libc::SIGINT && sent_by_terminal(info) {
+            return;
+        }
+        let pid = CHILD_PID.load(Ordering::SeqCst);
+        if pid > 0 {
+            unsafe {
+                libc::kill(pid, signal);
+            }
+        } else {
+            PENDING_SIGNAL.store(signal, Ordering::SeqCst);
+        }
+    }
+
+    /// Whether the signal comes from the terminal rather than from a process: it has no sender
+    /// and `wapm`, with the child, is in the foreground process group of the terminal.
+    fn sent_by_terminal(info: *const libc::siginfo_t) -> bool {
+        if info.is_null() || unsafe { (*info).si_pid() } != 0 {
+            return false;
+        }
+        let process_group = unsafe { libc::getpgrp() };
+        [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO]
+            .iter()
+            .any(|&fd| unsafe { libc::tcgetpgrp(fd) } == process_group)
+    }
+
+    /// Forwards SIGINT, SIGTERM and SIGHUP to the child process until it is dropped, so that
+    /// `wapm` keeps waiting for the child to exit instead of leaving it running on its own.
+    pub struct SignalForwarder {
+        previous_actions: Vec<(libc::c_int, libc::sigaction)>,
+    }
+
+    impl SignalForwarder {
+        /// Install the handlers before spawning the child. Signals that arrive in the meantime are
+        /// forwarded once the child is known.
+        pub fn install() -> Self {
+            CHILD_PID.store(0, Ordering::SeqCst);
+            PENDING_SIGNAL.store(0, Ordering::SeqCst);
+            // a handler, unlike `SIG_IGN`, is reset to the default when the child execs
+            let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+            action.sa_sigaction = forward_signal
+                as extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void)
+                as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+            unsafe {
+                libc::sigemptyset(&mut action.sa_mask);
+            }
+            let previous_actions = FORWARDED_SIGNALS
+                .iter()
+                .map(|&signal| {
+                    let mut previous_action: libc::sigaction = unsafe { std::mem::zeroed() };
+                    unsafe {
+                        libc::sigaction(signal, &action, &mut previous_action);
+                    }
+                    (signal, previous_action)
+                })
+                .collect();
+            Self { previous_actions }
+        }
+
+        pub fn forward_to(&self, child_pid: u32) {
+            CHILD_PID.store(child_pid as i32, Ordering::SeqCst);
+            let pending_signal = PENDING_SIGNAL.swap(0, Ordering::SeqCst);
+            if pending_signal != 0 {
+                unsafe {
+                    libc::kill(child_pid as i32, pending_signal);
+                }
+            }
+        }
+    }
+
+    impl Drop for SignalForwarder {
+        fn drop(&mut self) {
+            for (signal, previous_action) in self.previous_actions.iter() {
+                unsafe {
+                    libc::sigaction(*signal, previous_action, std::ptr::null_mut());
+                }
+            }
+            CHILD_PID.store(0, Ordering::SeqCst);
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::commands::run::create_run_command;
-    use crate::data::manifest::PACKAGES_DIR_NAME;
-    use crate::util::create_temp_dir;
+    use crate::commands::run::{add_manifest_settings, with_main_args, CommandOrigin, RunPlan};
+    #[cfg(unix)]
+    use crate::commands::run::{check_exit_status, signal_forwarding, ChildProcessError};
+    use crate::data::manifest::Manifest;
+    use crate::runtime::RunOptions;
+    use semver::Version;
+    use std::collections::BTreeMap;
     use std::ffi::OsString;
-    use std::fs;
     use std::path::PathBuf;
 
+    #[cfg(unix)]
     #[test]
-    fn create_run_command_vec() {
-        let args: Vec<OsString> = vec![OsString::from("arg1"), OsString::from("arg2")];
-        let tmp_dir = create_temp_dir().unwrap();
-        let dir = tmp_dir.clone();
-        let wapm_module_dir = dir.join(
-            [PACKAGES_DIR_NAME, "_", "foo@1.0.2"]
-                .iter()
-                .collect::<PathBuf>(),
+    fn signals_are_forwarded_to_the_child() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::Command;
+
+        let signal_forwarder = signal_forwarding::SignalForwarder::install();
+        unsafe {
+            // sent by a process, like `kill -INT`, and arrives before the child is spawned
+            libc::raise(libc::SIGINT);
+        }
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        signal_forwarder.forward_to(child.id());
+        assert_eq!(Some(libc::SIGINT), child.wait().unwrap().signal());
+
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        signal_forwarder.forward_to(child.id());
+        unsafe {
+            libc::raise(libc::SIGTERM);
+        }
+        assert_eq!(Some(libc::SIGTERM), child.wait().unwrap().signal());
+        drop(signal_forwarder);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn child_exit_status_is_propagated() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::ExitStatus;
+
+        assert!(check_exit_status("foo", ExitStatus::from_raw(0)).is_ok());
+        match check_exit_status("foo", ExitStatus::from_raw(3 << 8)) {
+            Err(ChildProcessError::Exited(command, 3)) => assert_eq!("foo", command),
+            otherwise => panic!("unexpected result {:?}", otherwise),
+        }
+        match check_exit_status("foo", ExitStatus::from_raw(libc::SIGTERM)) {
+            Err(ChildProcessError::Signaled(_, signal)) => assert_eq!(libc::SIGTERM, signal),
+            otherwise => panic!("unexpected result {:?}", otherwise),
+        }
+    }
+
+    #[test]
+    fn manifest_settings_become_run_options() {
+        let wapm_toml = r#"
+            [package]
+            name = "test"
+            version = "1.0.0"
+            description = "The best package."
+            wasmer-extra-flags = "--enable-simd --mapdir '/cache:my cache'"
+            [[module]]
+            name = "server"
+            source = "server.wasm"
+            abi = "wasi"
+            [[command]]
+            name = "serve"
+            module = "server"
+            env = { PORT = "8080", LOG_LEVEL = "debug" }
+            mapdir = { "/data" = "data" }
+            wasmer-extra-flags = "--enable-threads --llvm"
+            [fs]
+            "/assets" = "assets"
+        "#;
+        let manifest: Manifest = toml::from_str(wapm_toml).unwrap();
+        let manifest_dir = PathBuf::from("package");
+        let mut options = RunOptions {
+            command_name: Some("serve".to_string()),
+            ..RunOptions::default()
+        };
+
+        add_manifest_settings(&mut options, &manifest, "serve", &manifest_dir).unwrap();
+        assert_eq!(Some("serve".to_string()), options.command_name);
+        assert_eq!(
+            vec![
+                ("/assets".to_string(), manifest_dir.join("assets")),
+                ("/data".to_string(), manifest_dir.join("data")),
+            ],
+            options.mapped_directories
+        );
+        assert_eq!(
+            vec!["LOG_LEVEL", "PORT"],
+            options.env.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                OsString::from("--enable-simd"),
+                OsString::from("--mapdir"),
+                OsString::from("/cache:my cache"),
+                OsString::from("--enable-threads"),
+                OsString::from("--llvm"),
+            ],
+            options.wasmer_extra_flags
+        );
+
+        // other commands only get the settings of the package
+        let mut options = RunOptions::default();
+        add_manifest_settings(&mut options, &manifest, "other", &manifest_dir).unwrap();
+        assert_eq!(1, options.mapped_directories.len());
+        assert!(options.env.is_empty());
+        assert_eq!(3, options.wasmer_extra_flags.len());
+
+        // the flags of the package are quoted like the flags of a command
+        let mut manifest = manifest;
+        manifest.package.wasmer_extra_flags = Some("--mapdir '/cache:my cache".to_string());
+        let mut options = RunOptions::default();
+        assert!(add_manifest_settings(&mut options, &manifest, "serve", &manifest_dir).is_err());
+    }
+
+    #[test]
+    fn main_args_come_before_args() {
+        let args: Vec<OsString> = vec![OsString::from("arg1")];
+
+        assert_eq!(
+            vec![
+                OsString::from("serve"),
+                OsString::from("--config"),
+                OsString::from("my config.toml"),
+                OsString::from("arg1"),
+            ],
+            with_main_args(Some("serve --config 'my config.toml'"), &args).unwrap()
+        );
+        assert_eq!(args, with_main_args(None, &args).unwrap());
+        assert!(with_main_args(Some("--config 'my config.toml"), &args).is_err());
+    }
+
+    #[test]
+    fn run_plan_output() {
+        let plan = RunPlan {
+            command_name: "serve".to_string(),
+            origin: CommandOrigin::Local,
+            package_name: "_/server".to_string(),
+            package_version: Version::new(1, 0, 0),
+            module_name: "server".to_string(),
+            options: RunOptions {
+                module_path: PathBuf::from("server.wasm"),
+                ..RunOptions::default()
+            },
+            program: "wasmer".to_string(),
+            args: vec![
+                OsString::from("server.wasm"),
+                OsString::from("--"),
+                OsString::from("my arg"),
+            ],
+            env: BTreeMap::new(),
+        };
+
+        let text = plan.to_text();
+        assert!(text.contains("package: _/server 1.0.0 (local)\n"));
+        assert!(text.contains("argv:    wasmer server.wasm -- 'my arg'\n"));
+        let json = plan.to_json();
+        assert_eq!("local", json["origin"]);
+        assert_eq!("1.0.0", json["package"]["version"]);
+        assert_eq!(
+            serde_json::json!(["wasmer", "server.wasm", "--", "my arg"]),
+            json["argv"]
         );
-        fs::create_dir_all(&wapm_module_dir).unwrap();
-        let expected_dir: PathBuf = wapm_module_dir.clone();
-        let expected_dir = expected_dir.join("foo_entry.wasm");
-        let expected_command = vec![
-            expected_dir.into_os_string(),
-            OsString::from("--"),
-            OsString::from("arg1"),
-            OsString::from("arg2"),
-        ];
-        let wasm_relative_path: PathBuf = ["wapm_packages", "_", "foo@1.0.2", "foo_entry.wasm"]
-            .iter()
-            .collect();
-        let actual_command =
-            create_run_command(&args, None, vec![], &dir, wasm_relative_path, None, None).unwrap();
-        assert_eq!(expected_command, actual_command);
     }
 }
 
 #[derive(Debug, Error)]
-enum RunError {
+pub(crate) enum RunError {
     #[error("Failed to run command \"{0}\". {1}")]
     CannotRegenLockfile(String, dataflow::Error),
     #[error(
@@ -278,4 +688,46 @@ enum RunError {
     SourceForCommandNotFound(String, String, String),
     #[error("Failed to run {0}: {1}")]
     ProcessFailed(String, String),
+    #[error("Could not parse the main_args \"{0}\" of the command, check the quotes.")]
+    InvalidMainArgs(String),
+}
+
+impl Classify for RunError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            RunError::CannotRegenLockfile(_, e) => e.kind(),
+            RunError::SourceForCommandNotFound(..) | RunError::InvalidMainArgs(_) => {
+                ErrorKind::Manifest
+            }
+            RunError::ProcessFailed(..) => ErrorKind::Failure,
+        }
+    }
+}
+
+/// The runtime did not exit successfully. Instead of reporting an error, `wapm` exits with the
+/// same code or is terminated by the same signal.
+#[derive(Debug, Error)]
+pub enum ChildProcessError {
+    #[error("The command \"{0}\" exited with code {1}")]
+    Exited(String, i32),
+    #[error("The command \"{0}\" was terminated by signal {1}")]
+    Signaled(String, i32),
+}
+
+impl ChildProcessError {
+    /// Exit the current process the way the child process exited.
+    pub fn exit(&self) -> ! {
+        match *self {
+            ChildProcessError::Exited(_, code) => std::process::exit(code),
+            ChildProcessError::Signaled(_, signal) => {
+                #[cfg(unix)]
+                unsafe {
+                    libc::signal(signal, libc::SIG_DFL);
+                    libc::raise(signal);
+                }
+                // the shell convention for processes terminated by a signal
+                std::process::exit(128 + signal)
+            }
+        }
+    }
 }
@@ -0,0 +1,137 @@
+//! Code pertaining to the `update` subcommand
+
+use crate::dataflow;
+use crate::dataflow::lockfile_packages::{LockfilePackages, LockfileResult};
+use crate::dataflow::{PackageKey, WapmPackageKey};
+use crate::exit_code::{Classify, ErrorKind};
+use semver::Version;
+use std::collections::BTreeMap;
+use std::path::Path;
+use structopt::StructOpt;
+use thiserror::Error;
+
+/// Options for the `update` subcommand
+#[derive(StructOpt, Debug)]
+pub struct UpdateOpt {
+    /// The packages to update, all packages are updated if none are given
+    packages: Vec<String>,
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum UpdateError {
+    #[error("Failed to update packages. {0}")]
+    CannotUpdatePackages(dataflow::Error),
+    #[error("Could not read wapm.lock. {0}")]
+    CannotReadLockfile(dataflow::lockfile_packages::LockfileError),
+}
+
+impl Classify for UpdateError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            UpdateError::CannotUpdatePackages(e) => e.kind(),
+            UpdateError::CannotReadLockfile(_) => ErrorKind::Lockfile,
+        }
+    }
+}
+
+/// Run the update command
+pub fn update(options: UpdateOpt) -> anyhow::Result<()> {
+    let current_directory = crate::config::Config::get_current_dir()?;
+    let before = locked_versions(&current_directory)?;
+    let package_names = options.packages.iter().map(String::as_str).collect();
+    dataflow::update_locked_versions(package_names, &current_directory)
+        .map_err(UpdateError::CannotUpdatePackages)?;
+    let after = locked_versions(&current_directory)?;
+
+    let changes = diff_locked_versions(&before, &after);
+    if changes.is_empty() {
+        println!("All packages are already at the newest versions allowed by wapm.toml");
+    } else {
+        println!("Updated packages:");
+        for change in changes {
+            println!("  {}", change);
+        }
+    }
+    Ok(())
+}
+
+/// The locked versions of every installed package, leaving out the local package.
+fn locked_versions(directory: &Path) -> anyhow::Result<BTreeMap<String, Vec<Version>>> {
+    let lockfile_packages =
+        LockfilePackages::new_from_result(LockfileResult::find_in_directory(directory))
+            .map_err(UpdateError::CannotReadLockfile)?;
+    let mut versions: BTreeMap<String, Vec<Version>> = BTreeMap::new();
+    for (key, data) in lockfile_packages.packages.iter() {
+        if data.resolved_source() == Some("local") {
+            continue;
+        }
+        if let PackageKey::WapmPackage(WapmPackageKey { name, version }) = key {
+            versions
+                .entry(name.to_string())
+                .or_default()
+                .push(version.clone());
+        }
+    }
+    for package_versions in versions.values_mut() {
+        package_versions.sort();
+    }
+    Ok(versions)
+}
+
+/// Describe how the locked versions changed, one line per package, e.g. `_/foo 1.0.0 -> 1.3.0`.
+fn diff_locked_versions(
+    before: &BTreeMap<String, Vec<Version>>,
+    after: &BTreeMap<String, Vec<Version>>,
+) -> Vec<String> {
+    let display = |versions: Option<&Vec<Version>>| match versions {
+        Some(versions) if !versions.is_empty() => versions
+            .iter()
+            .map(Version::to_string)
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => "(none)".to_string(),
+    };
+    let mut names: Vec<&String> = before.keys().chain(after.keys()).collect();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .filter(|name| before.get(*name) != after.get(*name))
+        .map(|name| {
+            format!(
+                "{} {} -> {}",
+                name,
+                display(before.get(name)),
+                display(after.get(name))
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commands::update::diff_locked_versions;
+    use semver::Version;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn diff_changed_versions() {
+        let mut before = BTreeMap::new();
+        before.insert("_/foo".to_string(), vec![Version::new(1, 0, 0)]);
+        before.insert("_/bar".to_string(), vec![Version::new(2, 0, 0)]);
+        before.insert("_/old".to_string(), vec![Version::new(0, 1, 0)]);
+        let mut after = BTreeMap::new();
+        after.insert("_/foo".to_string(), vec![Version::new(1, 3, 0)]);
+        after.insert("_/bar".to_string(), vec![Version::new(2, 0, 0)]);
+        after.insert("_/new".to_string(), vec![Version::new(0, 2, 0)]);
+
+        assert_eq!(
+            vec![
+                "_/foo 1.0.0 -> 1.3.0".to_string(),
+                "_/new (none) -> 0.2.0".to_string(),
+                "_/old 0.1.0 -> (none)".to_string(),
+            ],
+            diff_locked_versions(&before, &after)
+        );
+    }
+}
@@ -0,0 +1,17 @@
+use crate::commands::run::plan_command;
+use crate::util::OutputFormat;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct WhichOpt {
+    /// The output format, `text` or `json`
+    #[structopt(long = "format", default_value = "text")]
+    format: OutputFormat,
+    /// Command name
+    command: String,
+}
+
+/// Print how `wapm run` would run the command, without running it or updating the lockfile.
+pub fn which(options: WhichOpt) -> anyhow::Result<()> {
+    plan_command(&options.command, &[], &[], false)?.print(options.format)
+}
@@ -2,10 +2,15 @@
     not(feature = "full"),
     allow(dead_code, unused_imports, unused_variables)
 )]
+use crate::constants::DEFAULT_RUNTIME;
+use crate::exit_code::{Classify, ErrorKind};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::env;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 pub static GLOBAL_CONFIG_FILE_NAME: &str = if cfg!(target_os = "wasi") {
@@ -17,7 +22,12 @@ pub static GLOBAL_CONFIG_FILE_NAME: &str = if cfg!(target_os = "wasi") {
 pub static GLOBAL_CONFIG_FOLDER_NAME: &str = ".wasmer";
 pub static GLOBAL_WAX_INDEX_FILE_NAME: &str = ".wax_index.json";
 pub static GLOBAL_CONFIG_DATABASE_FILE_NAME: &str = "wapm.sqlite";
+pub static GLOBAL_PACKAGE_CACHE_FOLDER_NAME: &str = "package_cache";
+pub static GLOBAL_WAX_FOLDER_NAME: &str = "wax";
 pub static GLOBAL_CONFIG_FOLDER_ENV_VAR: &str = "WASMER_DIR";
+pub static PROJECT_CONFIG_FOLDER_NAME: &str = ".wapm";
+pub static PROJECT_CONFIG_FILE_NAME: &str = "config.toml";
+pub static DEFAULT_REGISTRY_NAME: &str = "wapm";
 
 #[derive(Deserialize, Serialize, Debug, PartialEq)]
 pub struct Config {
@@ -26,8 +36,26 @@ pub struct Config {
     #[serde(default = "wax_default_cooldown")]
     pub wax_cooldown: i32,
 
-    /// The registry that wapm will connect to.
-    pub registry: Registry,
+    /// The maximum size in bytes of the packages installed by wax, the least recently used
+    /// packages are removed when it is exceeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wax_max_size: Option<u64>,
+
+    /// The directory wax installs packages in, `wax` in the config folder by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wax_directory: Option<PathBuf>,
+
+    /// The name of the registry that wapm connects to, unless a dependency names another one.
+    #[serde(default = "default_registry_name")]
+    pub default_registry: String,
+
+    /// The registries that wapm can connect to, by name.
+    #[serde(default = "default_registries")]
+    pub registries: BTreeMap<String, Registry>,
+
+    /// The single registry of older configs, moved into `registries` when the config is loaded.
+    #[serde(default, skip_serializing)]
+    registry: Option<Registry>,
 
     /// Whether or not telemetry is enabled.
     #[cfg(feature = "telemetry")]
@@ -42,6 +70,10 @@ pub struct Config {
     /// The proxy to use when connecting to the Internet.
     #[serde(default)]
     pub proxy: Proxy,
+
+    /// The runtime used to run commands, unless the project sets one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime: Option<String>,
 }
 
 /// The default cooldown for wax.
@@ -49,6 +81,22 @@ pub const fn wax_default_cooldown() -> i32 {
     5 * 60
 }
 
+fn default_registry_name() -> String {
+    DEFAULT_REGISTRY_NAME.to_string()
+}
+
+fn default_registries() -> BTreeMap<String, Registry> {
+    let mut registries = BTreeMap::new();
+    registries.insert(
+        default_registry_name(),
+        Registry {
+            url: "https://registry.wapm.io".to_string(),
+            token: None,
+        },
+    );
+    registries
+}
+
 #[derive(Deserialize, Serialize, Debug, PartialEq)]
 pub struct Registry {
     pub url: String,
@@ -93,16 +141,18 @@ pub struct Proxy {
 impl Default for Config {
     fn default() -> Config {
         Config {
-            registry: Registry {
-                url: "https://registry.wapm.io".to_string(),
-                token: None,
-            },
+            default_registry: default_registry_name(),
+            registries: default_registries(),
+            registry: None,
             #[cfg(feature = "telemetry")]
             telemetry: Telemetry::default(),
             #[cfg(feature = "update-notifications")]
             update_notifications: UpdateNotifications::default(),
             proxy: Proxy::default(),
+            runtime: None,
             wax_cooldown: wax_default_cooldown(),
+            wax_max_size: None,
+            wax_directory: None,
         }
     }
 }
@@ -152,36 +202,65 @@ impl Config {
         Config::get_folder().map(|config_folder| config_folder.join(GLOBAL_WAX_INDEX_FILE_NAME))
     }
 
+    /// The directory wax installs packages in.
+    pub fn wax_directory(&self) -> Result<PathBuf, GlobalConfigError> {
+        match self.wax_directory {
+            Some(ref wax_directory) => Ok(wax_directory.clone()),
+            None => {
+                Config::get_folder().map(|config_folder| config_folder.join(GLOBAL_WAX_FOLDER_NAME))
+            }
+        }
+    }
+
     pub fn get_database_file_path() -> Result<PathBuf, GlobalConfigError> {
         Config::get_folder()
             .map(|config_folder| config_folder.join(GLOBAL_CONFIG_DATABASE_FILE_NAME))
     }
 
-    /// Load the config from a file
-    #[cfg(not(feature = "integration_tests"))]
-    pub fn from_file() -> Result<Self, GlobalConfigError> {
-        let path = Self::get_file_location()?;
-        match File::open(&path) {
-            Ok(mut file) => {
-                let mut config_toml = String::new();
-                file.read_to_string(&mut config_toml)
-                    .map_err(|e| GlobalConfigError::Io(e))?;
-                toml::from_str(&config_toml).map_err(|e| GlobalConfigError::Toml(e))
-            }
-            Err(_e) => Ok(Self::default()),
-        }
+    pub fn get_package_cache_directory() -> Result<PathBuf, GlobalConfigError> {
+        Config::get_folder()
+            .map(|config_folder| config_folder.join(GLOBAL_PACKAGE_CACHE_FOLDER_NAME))
     }
 
-    /// A mocked version of the standard function for integration tests
-    #[cfg(feature = "integration_tests")]
+    /// Load the config, layering the project config and the `WAPM_*` environment variables over
+    /// the global config
     pub fn from_file() -> Result<Self, GlobalConfigError> {
-        crate::integration_tests::data::RAW_CONFIG_DATA.with(|rcd| {
-            if let Some(ref config_toml) = *rcd.borrow() {
-                toml::from_str(&config_toml).map_err(|e| GlobalConfigError::Toml(e))
-            } else {
-                Ok(Self::default())
-            }
-        })
+        ConfigSources::load()?.config()
+    }
+
+    /// Load only the global config, the one that `save` writes to
+    pub fn from_global_file() -> Result<Self, GlobalConfigError> {
+        let sources = ConfigSources {
+            layers: ConfigLayer::read_global()?.into_iter().collect(),
+            environment: vec![],
+        };
+        sources.config()
+    }
+
+    /// Parse the config, moving the `[registry]` of older configs into `registries`.
+    fn from_toml(config_toml: &str) -> Result<Self, GlobalConfigError> {
+        let config_value = parse_config_value(config_toml)?;
+        config_value.try_into().map_err(GlobalConfigError::Toml)
+    }
+
+    /// The registry with the given name, or the default registry.
+    pub fn registry(&self, name: Option<&str>) -> Result<&Registry, ConfigError> {
+        let name = name.unwrap_or(&self.default_registry);
+        self.registries
+            .get(name)
+            .ok_or_else(|| ConfigError::UnknownRegistry {
+                name: name.to_string(),
+            })
+    }
+
+    /// The registry with the given name, or the default registry, to change it.
+    pub fn registry_mut(&mut self, name: Option<&str>) -> Result<&mut Registry, ConfigError> {
+        let name = name.unwrap_or(&self.default_registry);
+        self.registries
+            .get_mut(name)
+            .ok_or_else(|| ConfigError::UnknownRegistry {
+                name: name.to_string(),
+            })
     }
 
     pub fn get_globals_directory() -> Result<PathBuf, GlobalConfigError> {
@@ -228,12 +307,547 @@ impl Registry {
     }
 }
 
+/// Where the value of a config key comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigOrigin {
+    /// The built-in default
+    Default,
+    /// The global config file in the config folder
+    Global(PathBuf),
+    /// The `.wapm/config.toml` of the project
+    Project(PathBuf),
+    /// An environment variable
+    Environment(String),
+}
+
+impl fmt::Display for ConfigOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigOrigin::Default => write!(f, "default"),
+            ConfigOrigin::Global(path) => write!(f, "global:{}", path.display()),
+            ConfigOrigin::Project(path) => write!(f, "project:{}", path.display()),
+            ConfigOrigin::Environment(name) => write!(f, "env:{}", name),
+        }
+    }
+}
+
+/// The config of a single file.
+struct ConfigLayer {
+    origin: ConfigOrigin,
+    value: toml::Value,
+}
+
+impl ConfigLayer {
+    fn parse(origin: ConfigOrigin, config_toml: &str) -> Result<Self, GlobalConfigError> {
+        let value = parse_config_value(config_toml)?;
+        Ok(Self { origin, value })
+    }
+
+    /// Read the config file, if it exists.
+    fn read(origin: ConfigOrigin, path: &Path) -> Result<Option<Self>, GlobalConfigError> {
+        match File::open(path) {
+            Ok(mut file) => {
+                let mut config_toml = String::new();
+                file.read_to_string(&mut config_toml)
+                    .map_err(GlobalConfigError::Io)?;
+                Self::parse(origin, &config_toml).map(Some)
+            }
+            Err(_e) => Ok(None),
+        }
+    }
+
+    #[cfg(not(feature = "integration_tests"))]
+    fn read_global() -> Result<Option<Self>, GlobalConfigError> {
+        let path = Config::get_file_location()?;
+        Self::read(ConfigOrigin::Global(path.clone()), &path)
+    }
+
+    /// A mocked version of the standard function for integration tests
+    #[cfg(feature = "integration_tests")]
+    fn read_global() -> Result<Option<Self>, GlobalConfigError> {
+        crate::integration_tests::data::RAW_CONFIG_DATA.with(|rcd| match *rcd.borrow() {
+            Some(ref config_toml) => Self::parse(
+                ConfigOrigin::Global(PathBuf::from(GLOBAL_CONFIG_FILE_NAME)),
+                config_toml,
+            )
+            .map(Some),
+            None => Ok(None),
+        })
+    }
+
+    /// Read the `.wapm/config.toml` in the current directory or the closest of its parents.
+    #[cfg(not(feature = "integration_tests"))]
+    fn read_project() -> Result<Option<Self>, GlobalConfigError> {
+        let current_dir = Config::get_current_dir().map_err(GlobalConfigError::Io)?;
+        for directory in current_dir.ancestors() {
+            let path = directory
+                .join(PROJECT_CONFIG_FOLDER_NAME)
+                .join(PROJECT_CONFIG_FILE_NAME);
+            if path.is_file() {
+                return Self::read(ConfigOrigin::Project(path.clone()), &path);
+            }
+        }
+        Ok(None)
+    }
+
+    /// A mocked version of the standard function for integration tests
+    #[cfg(feature = "integration_tests")]
+    fn read_project() -> Result<Option<Self>, GlobalConfigError> {
+        crate::integration_tests::data::RAW_PROJECT_CONFIG_DATA.with(|rpcd| match *rpcd.borrow() {
+            Some(ref config_toml) => Self::parse(
+                ConfigOrigin::Project(
+                    Path::new(PROJECT_CONFIG_FOLDER_NAME).join(PROJECT_CONFIG_FILE_NAME),
+                ),
+                config_toml,
+            )
+            .map(Some),
+            None => Ok(None),
+        })
+    }
+
+    /// Whether the file sets the value at the path.
+    fn contains(&self, path: &[String]) -> bool {
+        lookup_value(&self.value, path).is_some()
+    }
+}
+
+/// A config key set by an environment variable.
+struct EnvironmentValue {
+    key: &'static str,
+    name: String,
+    value: String,
+}
+
+/// The sources of the config, from the lowest to the highest precedence: the built-in defaults,
+/// the global config file, the `.wapm/config.toml` of the project and the `WAPM_*` environment
+/// variables.
+pub struct ConfigSources {
+    layers: Vec<ConfigLayer>,
+    environment: Vec<EnvironmentValue>,
+}
+
+impl ConfigSources {
+    /// Read all sources of the config.
+    pub fn load() -> Result<Self, GlobalConfigError> {
+        let layers = ConfigLayer::read_global()?
+            .into_iter()
+            .chain(ConfigLayer::read_project()?)
+            .collect();
+        Ok(Self {
+            layers,
+            environment: Self::read_environment(),
+        })
+    }
+
+    /// Read the global config and the environment variables, without the config of the project,
+    /// for settings that a project must not change.
+    pub fn load_without_project() -> Result<Self, GlobalConfigError> {
+        Ok(Self {
+            layers: ConfigLayer::read_global()?.into_iter().collect(),
+            environment: Self::read_environment(),
+        })
+    }
+
+    fn read_environment() -> Vec<EnvironmentValue> {
+        KEYS.iter()
+            .filter(|definition| !matches!(definition.location, ValueLocation::Registry(_)))
+            .filter_map(|definition| {
+                let key = definition.name;
+                let name = environment_variable_name(key);
+                let value = env::var(&name).ok()?;
+                Some(EnvironmentValue { key, name, value })
+            })
+            .collect()
+    }
+
+    /// The config of all files merged, with the environment variables applied.
+    pub fn config(&self) -> Result<Config, GlobalConfigError> {
+        let mut config: Config = self
+            .merged_value()?
+            .try_into()
+            .map_err(GlobalConfigError::Toml)?;
+        for environment_value in self.environment.iter() {
+            set_value(
+                &mut config,
+                environment_value.key.to_string(),
+                environment_value.value.clone(),
+            )
+            .map_err(|e| GlobalConfigError::InvalidEnvironmentVariable {
+                name: environment_value.name.clone(),
+                message: e.to_string(),
+            })?;
+        }
+        Ok(config)
+    }
+
+    /// Where the value of the key in the config comes from.
+    pub fn origin(&self, config: &Config, key: &str) -> anyhow::Result<ConfigOrigin> {
+        let path = ResolvedKey::find(config, key)?.path;
+        let environment_value = self.environment.iter().rev().find(|environment_value| {
+            ResolvedKey::find(config, environment_value.key)
+                .map(|resolved| resolved.path == path)
+                .unwrap_or(false)
+        });
+        if let Some(environment_value) = environment_value {
+            return Ok(ConfigOrigin::Environment(environment_value.name.clone()));
+        }
+        // a value removed while merging, like the token of a registry whose url was changed
+        if lookup_value(&self.merged_value()?, &path).is_none() {
+            return Ok(ConfigOrigin::Default);
+        }
+        Ok(self
+            .layers
+            .iter()
+            .rev()
+            .find(|layer| layer.contains(&path))
+            .map(|layer| layer.origin.clone())
+            .unwrap_or(ConfigOrigin::Default))
+    }
+
+    /// The defaults with the tables of all files merged into them.
+    fn merged_value(&self) -> Result<toml::Value, GlobalConfigError> {
+        let mut merged =
+            toml::Value::try_from(Config::default()).map_err(GlobalConfigError::TomlSerialize)?;
+        for layer in self.layers.iter() {
+            reset_overridden_tokens(&mut merged, &layer.value);
+            merge_values(&mut merged, layer.value.clone());
+        }
+        Ok(merged)
+    }
+}
+
+/// How the value of a config key is parsed and checked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ValueKind {
+    /// Any text
+    Text,
+    /// `true` or `false`, kept as text in the config file
+    #[cfg_attr(
+        not(any(feature = "telemetry", feature = "update-notifications")),
+        allow(dead_code)
+    )]
+    Bool,
+    /// A whole number
+    Integer,
+    /// A number of bytes with an optional unit, e.g. `500MB`
+    Size,
+    /// A path, relative to the current directory
+    Path,
+    /// An absolute url
+    Url,
+}
+
+impl ValueKind {
+    /// The value as it is kept in the config file, if it is valid.
+    fn parse(self, value: &str) -> Option<toml::Value> {
+        let value = match self {
+            ValueKind::Text => toml::Value::String(value.to_string()),
+            ValueKind::Bool => toml::Value::String(value.parse::<bool>().ok()?.to_string()),
+            ValueKind::Integer => toml::Value::Integer(value.parse().ok()?),
+            ValueKind::Size => {
+                toml::Value::Integer(i64::try_from(crate::util::parse_size(value)?).ok()?)
+            }
+            ValueKind::Path => toml::Value::String(
+                Config::get_current_dir()
+                    .ok()?
+                    .join(value)
+                    .to_string_lossy()
+                    .to_string(),
+            ),
+            ValueKind::Url => {
+                url::Url::parse(value).ok()?;
+                toml::Value::String(value.to_string())
+            }
+        };
+        Some(value)
+    }
+}
+
+/// Where the value of a config key is kept in the config file.
+enum ValueLocation {
+    /// A field of the config, by its path in the config file
+    Field(&'static [&'static str]),
+    /// A field of the default registry
+    DefaultRegistry(&'static str),
+    /// A field of the registry named in the key, `registries.<name>.<field>`
+    Registry(&'static str),
+}
+
+/// A key of the config, like `wax.cooldown`.
+struct KeyDefinition {
+    name: &'static str,
+    location: ValueLocation,
+    kind: ValueKind,
+    /// Whether the value is redacted when it is displayed
+    secret: bool,
+    /// The value used when the key is not set, for values that are not in the config file
+    default: fn() -> Option<String>,
+}
+
+/// Every key of the config. Environment variables are applied in this order, so a
+/// `WAPM_REGISTRY_DEFAULT` decides which registry `WAPM_REGISTRY_URL` changes.
+static KEYS: &[KeyDefinition] = &[
+    KeyDefinition {
+        name: "registry.default",
+        location: ValueLocation::Field(&["default_registry"]),
+        kind: ValueKind::Text,
+        secret: false,
+        default: no_default,
+    },
+    KeyDefinition {
+        name: "registry.url",
+        location: ValueLocation::DefaultRegistry("url"),
+        kind: ValueKind::Url,
+        secret: false,
+        default: no_default,
+    },
+    KeyDefinition {
+        name: "registry.token",
+        location: ValueLocation::DefaultRegistry("token"),
+        kind: ValueKind::Text,
+        secret: true,
+        default: no_default,
+    },
+    KeyDefinition {
+        name: "registries.<name>.url",
+        location: ValueLocation::Registry("url"),
+        kind: ValueKind::Url,
+        secret: false,
+        default: no_default,
+    },
+    KeyDefinition {
+        name: "registries.<name>.token",
+        location: ValueLocation::Registry("token"),
+        kind: ValueKind::Text,
+        secret: true,
+        default: no_default,
+    },
+    #[cfg(feature = "telemetry")]
+    KeyDefinition {
+        name: "telemetry.enabled",
+        location: ValueLocation::Field(&["telemetry", "enabled"]),
+        kind: ValueKind::Bool,
+        secret: false,
+        default: no_default,
+    },
+    #[cfg(feature = "update-notifications")]
+    KeyDefinition {
+        name: "update-notifications.enabled",
+        location: ValueLocation::Field(&["update_notifications", "enabled"]),
+        kind: ValueKind::Bool,
+        secret: false,
+        default: no_default,
+    },
+    KeyDefinition {
+        name: "proxy.url",
+        location: ValueLocation::Field(&["proxy", "url"]),
+        kind: ValueKind::Url,
+        secret: false,
+        default: no_default,
+    },
+    KeyDefinition {
+        name: "runtime",
+        location: ValueLocation::Field(&["runtime"]),
+        kind: ValueKind::Text,
+        secret: false,
+        default: || Some(DEFAULT_RUNTIME.to_string()),
+    },
+    KeyDefinition {
+        name: "wax.cooldown",
+        location: ValueLocation::Field(&["wax_cooldown"]),
+        kind: ValueKind::Integer,
+        secret: false,
+        default: no_default,
+    },
+    KeyDefinition {
+        name: "wax.directory",
+        location: ValueLocation::Field(&["wax_directory"]),
+        kind: ValueKind::Path,
+        secret: false,
+        default: || {
+            let wax_directory = Config::default().wax_directory().ok()?;
+            Some(wax_directory.to_string_lossy().to_string())
+        },
+    },
+    KeyDefinition {
+        name: "wax.max-size",
+        location: ValueLocation::Field(&["wax_max_size"]),
+        kind: ValueKind::Size,
+        secret: false,
+        default: no_default,
+    },
+];
+
+fn no_default() -> Option<String> {
+    None
+}
+
+/// A key with its definition and the path of its value in the config file.
+struct ResolvedKey {
+    definition: &'static KeyDefinition,
+    path: Vec<String>,
+}
+
+impl ResolvedKey {
+    fn find(config: &Config, key: &str) -> Result<Self, ConfigError> {
+        for definition in KEYS.iter() {
+            let path = match definition.location {
+                ValueLocation::Field(path) if definition.name == key => {
+                    path.iter().map(|part| part.to_string()).collect()
+                }
+                ValueLocation::DefaultRegistry(field) if definition.name == key => {
+                    registry_value_path(&config.default_registry, field)
+                }
+                ValueLocation::Registry(field) => match parse_registries_key(key) {
+                    Some((name, key_field)) if key_field == field => {
+                        registry_value_path(name, field)
+                    }
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            return Ok(Self { definition, path });
+        }
+        Err(ConfigError::KeyNotFound {
+            key: key.to_string(),
+        })
+    }
+
+    /// The name of the registry the value belongs to, if it is a field of a registry.
+    fn registry_name(&self) -> Option<&str> {
+        match self.path.as_slice() {
+            [registries, name, _field] if registries == "registries" => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Whether the value is the url of a registry, which adds the registry when it is set and
+    /// removes it when it is unset.
+    fn is_registry_url(&self) -> bool {
+        self.registry_name().is_some() && self.path[2] == "url"
+    }
+}
+
+fn registry_value_path(name: &str, field: &str) -> Vec<String> {
+    vec![
+        "registries".to_string(),
+        name.to_string(),
+        field.to_string(),
+    ]
+}
+
+/// The environment variable of a key, e.g. `WAPM_WAX_MAX_SIZE` for `wax.max-size`.
+fn environment_variable_name(key: &str) -> String {
+    format!("WAPM_{}", key.replace(['.', '-'], "_").to_uppercase())
+}
+
+fn lookup_value<'a>(value: &'a toml::Value, path: &[String]) -> Option<&'a toml::Value> {
+    path.iter().try_fold(value, |value, part| value.get(part))
+}
+
+fn remove_value(value: &mut toml::Value, path: &[String]) {
+    if let Some((last, parents)) = path.split_last() {
+        let parent = parents
+            .iter()
+            .try_fold(value, |value, part| value.get_mut(part));
+        if let Some(toml::Value::Table(table)) = parent {
+            table.remove(last);
+        }
+    }
+}
+
+/// A table with only the value at the path.
+fn value_at_path(path: &[String], value: toml::Value) -> toml::Value {
+    path.iter().rev().fold(value, |value, part| {
+        let mut table = toml::value::Table::new();
+        table.insert(part.clone(), value);
+        toml::Value::Table(table)
+    })
+}
+
+/// Parse a config file, moving the `[registry]` of older configs into `registries`.
+fn parse_config_value(config_toml: &str) -> Result<toml::Value, GlobalConfigError> {
+    let mut value: toml::Value = toml::from_str(config_toml).map_err(GlobalConfigError::Toml)?;
+    if let Some(table) = value.as_table_mut() {
+        if let Some(registry) = table.remove("registry") {
+            let default_registry = table
+                .get("default_registry")
+                .and_then(toml::Value::as_str)
+                .unwrap_or(DEFAULT_REGISTRY_NAME)
+                .to_string();
+            let mut registries = toml::value::Table::new();
+            registries.insert(default_registry, registry);
+            merge_values(
+                table
+                    .entry("registries")
+                    .or_insert_with(|| toml::Value::Table(toml::value::Table::new())),
+                toml::Value::Table(registries),
+            );
+        }
+    }
+    Ok(value)
+}
+
+/// Merge the tables of `upper` into `lower`, any other value of `upper` replaces the one of
+/// `lower`.
+fn merge_values(lower: &mut toml::Value, upper: toml::Value) {
+    match (lower, upper) {
+        (toml::Value::Table(lower), toml::Value::Table(upper)) => {
+            for (key, upper_value) in upper {
+                match lower.get_mut(&key) {
+                    Some(lower_value) => merge_values(lower_value, upper_value),
+                    None => {
+                        lower.insert(key, upper_value);
+                    }
+                }
+            }
+        }
+        (lower, upper) => *lower = upper,
+    }
+}
+
+/// Remove the tokens of the registries whose url `upper` changes without setting a token, so
+/// that a token is never sent to another registry than the one it was made for.
+fn reset_overridden_tokens(lower: &mut toml::Value, upper: &toml::Value) {
+    let upper_registries = match upper.get("registries").and_then(toml::Value::as_table) {
+        Some(upper_registries) => upper_registries,
+        None => return,
+    };
+    let lower_registries = match lower
+        .get_mut("registries")
+        .and_then(toml::Value::as_table_mut)
+    {
+        Some(lower_registries) => lower_registries,
+        None => return,
+    };
+    for (name, upper_registry) in upper_registries {
+        let lower_registry = match lower_registries
+            .get_mut(name)
+            .and_then(toml::Value::as_table_mut)
+        {
+            Some(lower_registry) => lower_registry,
+            None => continue,
+        };
+        let url_changed = match upper_registry.get("url") {
+            Some(url) => lower_registry.get("url") != Some(url),
+            None => false,
+        };
+        if url_changed && upper_registry.get("token").is_none() {
+            lower_registry.remove("token");
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum GlobalConfigError {
     #[error("Error while reading config: [{0}]")]
     Io(std::io::Error),
     #[error("Error while reading config: [{0}]")]
     Toml(toml::de::Error),
+    #[error("Error while reading config: [{0}]")]
+    TomlSerialize(toml::ser::Error),
+    #[error("Invalid value of the environment variable {name}: {message}")]
+    InvalidEnvironmentVariable { name: String, message: String },
     #[error(
         "While falling back to the default location for WASMER_DIR, could not resolve the user's home directory"
     )]
@@ -242,84 +856,222 @@ pub enum GlobalConfigError {
     CannotCreateConfigDirectory(std::io::Error),
 }
 
+impl Classify for GlobalConfigError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Config
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ConfigError {
     #[error("Key not found: {key}")]
     KeyNotFound { key: String },
     #[error("Failed to parse value `{value}` for key `{key}`")]
     CanNotParse { value: String, key: String },
+    #[error("No registry named `{name}` is configured, add it with `wapm config set registries.{name}.url <url>`")]
+    UnknownRegistry { name: String },
+    #[error("The registry `{name}` is {url} here, but {global_url} in the global config, so its token can not be saved. Add the registry to the global config under another name with `wapm config set registries.<name>.url {url}` and use it with `wapm config set registry.default <name>`")]
+    OverriddenRegistryUrl {
+        name: String,
+        url: String,
+        global_url: String,
+    },
+}
+
+impl Classify for ConfigError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Config
+    }
+}
+
+/// The name of the registry and the field of a `registries.<name>.<field>` key.
+fn parse_registries_key(key: &str) -> Option<(&str, &str)> {
+    let key = key.strip_prefix("registries.")?;
+    let field_start = key.rfind('.')?;
+    let (name, field) = (&key[..field_start], &key[field_start + 1..]);
+    if name.is_empty() {
+        return None;
+    }
+    Some((name, field))
 }
 
 pub fn set(config: &mut Config, key: String, value: String) -> anyhow::Result<()> {
-    match key.as_ref() {
-        "registry.url" => {
-            if config.registry.url != value {
-                config.registry.url = value;
-                // Resets the registry token automatically
-                config.registry.token = None;
-            }
-        }
-        "registry.token" => {
-            config.registry.token = Some(value);
-        }
-        #[cfg(feature = "telemetry")]
-        "telemetry.enabled" => {
-            config.telemetry.enabled = value;
-        }
-        #[cfg(feature = "update-notifications")]
-        "update-notifications.enabled" => {
-            config.update_notifications.enabled = value;
-        }
-        "proxy.url" => {
-            config.proxy.url = if value.is_empty() { None } else { Some(value) };
-        }
-        "wax.cooldown" => {
-            let num = value.parse::<i32>().map_err(|_| ConfigError::CanNotParse {
-                value: value.clone(),
-                key: key.clone(),
-            })?;
-            config.wax_cooldown = num;
-        }
-        _ => {
-            return Err(ConfigError::KeyNotFound { key }.into());
-        }
+    set_value(config, key, value)?;
+    config.save()?;
+    Ok(())
+}
+
+/// Set the value of the key without saving the config, an empty value unsets the key.
+fn set_value(config: &mut Config, key: String, value: String) -> anyhow::Result<()> {
+    if value.is_empty() {
+        return unset_value(config, &key);
+    }
+    let resolved = ResolvedKey::find(config, &key)?;
+    let can_not_parse = || ConfigError::CanNotParse {
+        value: value.clone(),
+        key: key.clone(),
     };
+    let parsed_value = resolved
+        .definition
+        .kind
+        .parse(&value)
+        .ok_or_else(can_not_parse)?;
+    if let (Some(name), false) = (resolved.registry_name(), resolved.is_registry_url()) {
+        config.registry(Some(name))?;
+    }
+    let mut config_value = config_to_value(config)?;
+    let new_value = value_at_path(&resolved.path, parsed_value);
+    // changing the url of a registry resets its token
+    reset_overridden_tokens(&mut config_value, &new_value);
+    merge_values(&mut config_value, new_value);
+    let new_config: Config = config_value.try_into().map_err(|_| can_not_parse())?;
+    new_config.registry(None)?;
+    *config = new_config;
+    Ok(())
+}
+
+pub fn unset(config: &mut Config, key: String) -> anyhow::Result<()> {
+    unset_value(config, &key)?;
     config.save()?;
     Ok(())
 }
 
-pub fn get(config: &mut Config, key: String) -> anyhow::Result<String> {
-    let value = match key.as_ref() {
-        "registry.url" => config.registry.url.clone(),
-        "registry.token" => {
-            unimplemented!()
-            // &(config.registry.token.as_ref().map_or("".to_string(), |n| n.to_string()).to_owned())
+/// Unset the key without saving the config, the value falls back to its default.
+fn unset_value(config: &mut Config, key: &str) -> anyhow::Result<()> {
+    let resolved = ResolvedKey::find(config, key)?;
+    let mut config_value = config_to_value(config)?;
+    if resolved.is_registry_url() {
+        remove_value(&mut config_value, &resolved.path[..2]);
+    } else {
+        remove_value(&mut config_value, &resolved.path);
+    }
+    let mut default_value = config_to_value(&Config::default())?;
+    merge_values(&mut default_value, config_value);
+    let new_config: Config = default_value.try_into().map_err(GlobalConfigError::Toml)?;
+    new_config.registry(None)?;
+    *config = new_config;
+    Ok(())
+}
+
+fn config_to_value(config: &Config) -> Result<toml::Value, GlobalConfigError> {
+    toml::Value::try_from(config).map_err(GlobalConfigError::TomlSerialize)
+}
+
+/// A key of the config and its value, if it is set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigEntry {
+    pub key: String,
+    pub value: Option<String>,
+    /// Whether the value is a secret like a token
+    pub secret: bool,
+}
+
+impl ConfigEntry {
+    /// The value to display, with secrets redacted unless they should be shown.
+    pub fn display_value(&self, show_secrets: bool) -> String {
+        match self.value {
+            Some(_) if self.secret && !show_secrets => REDACTED_VALUE.to_string(),
+            Some(ref value) => value.clone(),
+            None => String::new(),
         }
-        #[cfg(feature = "telemetry")]
-        "telemetry.enabled" => config.telemetry.enabled.clone(),
-        #[cfg(feature = "update-notifications")]
-        "update-notifications.enabled" => config.update_notifications.enabled.clone(),
-        "proxy.url" => {
-            if let Some(url) = &config.proxy.url {
-                url.clone()
-            } else {
-                "No proxy configured".to_owned()
+    }
+}
+
+static REDACTED_VALUE: &str = "********";
+
+pub fn get(config: &Config, key: &str) -> anyhow::Result<ConfigEntry> {
+    let resolved = ResolvedKey::find(config, key)?;
+    if let Some(name) = resolved.registry_name() {
+        config.registry(Some(name))?;
+    }
+    let config_value = config_to_value(config)?;
+    let value = match lookup_value(&config_value, &resolved.path) {
+        Some(toml::Value::String(value)) => Some(value.clone()),
+        Some(value) => Some(value.to_string()),
+        None => (resolved.definition.default)(),
+    };
+    Ok(ConfigEntry {
+        key: key.to_string(),
+        value,
+        secret: resolved.definition.secret,
+    })
+}
+
+/// All keys of the config that have a value, the fields of the registries by registry.
+pub fn entries(config: &Config) -> anyhow::Result<Vec<ConfigEntry>> {
+    let mut keys = vec![];
+    for definition in KEYS.iter() {
+        match definition.location {
+            ValueLocation::Registry(_) => continue,
+            _ => keys.push(definition.name.to_string()),
+        }
+    }
+    for name in config.registries.keys() {
+        for definition in KEYS.iter() {
+            if let ValueLocation::Registry(field) = definition.location {
+                keys.push(format!("registries.{}.{}", name, field));
             }
         }
-        "wax.cooldown" => format!("{}", config.wax_cooldown),
-        _ => {
-            return Err(ConfigError::KeyNotFound { key }.into());
+    }
+    let mut entries = vec![];
+    for key in keys {
+        let entry = get(config, &key)?;
+        if entry.value.is_some() {
+            entries.push(entry);
         }
-    };
-    Ok(value)
+    }
+    Ok(entries)
+}
+
+/// Save the token of the default registry in the global config, adding the registry to it if
+/// only the project or the environment configures it.
+pub fn save_token(token: Option<String>) -> anyhow::Result<()> {
+    let config = Config::from_file()?;
+    let mut global_config = Config::from_global_file()?;
+    set_default_registry_token(&config, &mut global_config, token)?;
+    global_config.save()
+}
+
+/// Set the token of the default registry of `config` in the global config. The token belongs to
+/// the url of the registry in effect, so it is not saved for a registry of the same name that
+/// has another url in the global config.
+fn set_default_registry_token(
+    config: &Config,
+    global_config: &mut Config,
+    token: Option<String>,
+) -> Result<(), ConfigError> {
+    let registry = config.registry(None)?;
+    let global_registry = global_config
+        .registries
+        .entry(config.default_registry.clone())
+        .or_insert_with(|| Registry {
+            url: registry.url.clone(),
+            token: None,
+        });
+    if global_registry.url != registry.url {
+        return Err(ConfigError::OverriddenRegistryUrl {
+            name: config.default_registry.clone(),
+            url: registry.url.clone(),
+            global_url: global_registry.url.clone(),
+        });
+    }
+    global_registry.token = token;
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
-    use crate::config::{Config, GLOBAL_CONFIG_FILE_NAME, GLOBAL_CONFIG_FOLDER_ENV_VAR};
+    use crate::config::{
+        entries, environment_variable_name, get, set_default_registry_token, set_value,
+        unset_value, Config, ConfigError, ConfigLayer, ConfigOrigin, ConfigSources,
+        EnvironmentValue, Proxy, Registry, ResolvedKey, DEFAULT_REGISTRY_NAME,
+        GLOBAL_CONFIG_FILE_NAME, GLOBAL_CONFIG_FOLDER_ENV_VAR,
+    };
     use crate::util::create_temp_dir;
+    use std::collections::BTreeMap;
     use std::fs::*;
     use std::io::Write;
+    use std::path::PathBuf;
 
     #[test]
     fn get_config_and_wasmer_dir_does_not_exist() {
@@ -360,4 +1112,353 @@ mod test {
         let config_result = Config::from_file();
         assert!(config_result.is_ok(), "Config not found.");
     }
+
+    #[test]
+    fn move_single_registry_into_registries() {
+        let config = Config::from_toml(
+            r#"
+[registry]
+url = "https://registry.wapm.dev"
+token = "abc"
+"#,
+        )
+        .unwrap();
+        assert_eq!(DEFAULT_REGISTRY_NAME, config.default_registry);
+        assert_eq!(
+            &Registry {
+                url: "https://registry.wapm.dev".to_string(),
+                token: Some("abc".to_string()),
+            },
+            config.registry(None).unwrap()
+        );
+
+        // the registries are saved in the new format
+        let mut config = config;
+        config.registries.insert(
+            "internal".to_string(),
+            Registry {
+                url: "https://wapm.example.com".to_string(),
+                token: None,
+            },
+        );
+        config.default_registry = "internal".to_string();
+        let config_toml = toml::to_string(&config).unwrap();
+        assert!(!config_toml.contains("[registry]"));
+        let reparsed = Config::from_toml(&config_toml).unwrap();
+        assert_eq!(config, reparsed);
+        assert_eq!(
+            "https://wapm.example.com",
+            reparsed.registry(None).unwrap().url
+        );
+        assert!(reparsed.registry(Some("missing")).is_err());
+    }
+
+    fn config_sources(
+        global_toml: &str,
+        project_toml: &str,
+        environment: &[(&'static str, &str)],
+    ) -> ConfigSources {
+        ConfigSources {
+            layers: vec![
+                ConfigLayer::parse(
+                    ConfigOrigin::Global(PathBuf::from("wapm.toml")),
+                    global_toml,
+                )
+                .unwrap(),
+                ConfigLayer::parse(
+                    ConfigOrigin::Project(PathBuf::from(".wapm/config.toml")),
+                    project_toml,
+                )
+                .unwrap(),
+            ],
+            environment: environment
+                .iter()
+                .map(|(key, value)| EnvironmentValue {
+                    key,
+                    name: environment_variable_name(key),
+                    value: value.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn token_is_saved_for_the_registry_url_in_effect() {
+        let global_toml = r#"
+[registries.wapm]
+url = "https://registry.wapm.io"
+token = "abc"
+"#;
+        let global_sources = config_sources(global_toml, "", &[]);
+        let mut global_config = global_sources.config().unwrap();
+        let config = config_sources(global_toml, "", &[]).config().unwrap();
+        set_default_registry_token(&config, &mut global_config, Some("def".to_string())).unwrap();
+        assert_eq!(
+            Some("def"),
+            global_config.registry(None).unwrap().token.as_deref()
+        );
+
+        // a token from the registry of the project is not saved for the global registry
+        let config = config_sources(
+            global_toml,
+            r#"
+[registries.wapm]
+url = "https://registry.example.com"
+"#,
+            &[],
+        )
+        .config()
+        .unwrap();
+        let mut global_config = global_sources.config().unwrap();
+        let result = set_default_registry_token(&config, &mut global_config, None);
+        assert!(matches!(
+            result,
+            Err(ConfigError::OverriddenRegistryUrl { .. })
+        ));
+        assert_eq!(
+            Some("abc"),
+            global_config.registry(None).unwrap().token.as_deref()
+        );
+
+        // a registry only the project configures is added to the global config
+        let config = config_sources(
+            global_toml,
+            r#"
+default_registry = "internal"
+
+[registries.internal]
+url = "https://registry.example.com"
+"#,
+            &[],
+        )
+        .config()
+        .unwrap();
+        set_default_registry_token(&config, &mut global_config, Some("ghi".to_string())).unwrap();
+        assert_eq!(
+            &Registry {
+                url: "https://registry.example.com".to_string(),
+                token: Some("ghi".to_string()),
+            },
+            global_config.registry(Some("internal")).unwrap()
+        );
+    }
+
+    #[test]
+    fn layered_config_and_origins() {
+        let sources = config_sources(
+            r#"
+wax_cooldown = 10
+runtime = "wasmtime"
+
+[proxy]
+url = "http://proxy.example.com"
+
+[registry]
+url = "https://registry.wapm.io"
+token = "abc"
+"#,
+            r#"
+wax_cooldown = 20
+
+[registries.wapm]
+url = "https://registry.wapm.dev"
+"#,
+            &[("proxy.url", "http://localhost:3128")],
+        );
+        let config = sources.config().unwrap();
+        assert_eq!(20, config.wax_cooldown);
+        assert_eq!(Some("wasmtime".to_string()), config.runtime);
+        assert_eq!(Some("http://localhost:3128".to_string()), config.proxy.url);
+        // the token of the global registry is not sent to the registry of the project
+        assert_eq!(
+            &Registry {
+                url: "https://registry.wapm.dev".to_string(),
+                token: None,
+            },
+            config.registry(None).unwrap()
+        );
+
+        let origin = |key| sources.origin(&config, key).unwrap();
+        assert_eq!(
+            ConfigOrigin::Project(PathBuf::from(".wapm/config.toml")),
+            origin("wax.cooldown")
+        );
+        assert_eq!(
+            ConfigOrigin::Project(PathBuf::from(".wapm/config.toml")),
+            origin("registry.url")
+        );
+        assert_eq!(
+            ConfigOrigin::Global(PathBuf::from("wapm.toml")),
+            origin("runtime")
+        );
+        assert_eq!(
+            ConfigOrigin::Environment("WAPM_PROXY_URL".to_string()),
+            origin("proxy.url")
+        );
+        assert_eq!(ConfigOrigin::Default, origin("registry.token"));
+        assert_eq!(ConfigOrigin::Default, origin("registry.default"));
+        assert_eq!(ConfigOrigin::Default, origin("wax.max-size"));
+        assert!(sources.origin(&config, "not.a.key").is_err());
+    }
+
+    #[test]
+    fn environment_variables_of_keys() {
+        assert_eq!(
+            "WAPM_WAX_MAX_SIZE",
+            environment_variable_name("wax.max-size")
+        );
+        assert_eq!(
+            "WAPM_REGISTRY_URL",
+            environment_variable_name("registry.url")
+        );
+
+        let sources = config_sources(
+            "",
+            "",
+            &[
+                ("registry.url", "https://registry.wapm.dev"),
+                ("registry.token", "abc"),
+                ("wax.max-size", "1MB"),
+            ],
+        );
+        let config = sources.config().unwrap();
+        assert_eq!(Some(1 << 20), config.wax_max_size);
+        assert_eq!(
+            Some("abc".to_string()),
+            config.registry(None).unwrap().token
+        );
+        assert_eq!(
+            ConfigOrigin::Environment("WAPM_REGISTRY_URL".to_string()),
+            sources.origin(&config, "registries.wapm.url").unwrap()
+        );
+
+        let sources = config_sources("", "", &[("wax.cooldown", "soon")]);
+        let error = sources.config().unwrap_err().to_string();
+        assert!(error.contains("WAPM_WAX_COOLDOWN"), "{}", error);
+    }
+
+    fn set_key(config: &mut Config, key: &str, value: &str) -> anyhow::Result<()> {
+        set_value(config, key.to_string(), value.to_string())
+    }
+
+    fn get_key(config: &Config, key: &str) -> Option<String> {
+        get(config, key).unwrap().value
+    }
+
+    #[test]
+    fn set_get_and_unset_keys() {
+        let mut config = Config::default();
+        set_key(&mut config, "wax.cooldown", "60").unwrap();
+        set_key(&mut config, "wax.max-size", "1KB").unwrap();
+        set_key(&mut config, "proxy.url", "http://localhost:3128").unwrap();
+        assert_eq!(60, config.wax_cooldown);
+        assert_eq!(Some(1024), config.wax_max_size);
+        assert_eq!(Some("1024".to_string()), get_key(&config, "wax.max-size"));
+        assert_eq!(
+            Some("http://localhost:3128".to_string()),
+            get_key(&config, "proxy.url")
+        );
+        assert!(set_key(&mut config, "wax.cooldown", "soon").is_err());
+        assert!(set_key(&mut config, "wax.cooldown", "99999999999").is_err());
+        assert!(set_key(&mut config, "proxy.url", "localhost").is_err());
+        assert!(set_key(&mut config, "wax.speed", "1").is_err());
+        assert!(get(&config, "wax.speed").is_err());
+
+        // secrets are redacted
+        set_key(&mut config, "registry.token", "abc").unwrap();
+        let token = get(&config, "registry.token").unwrap();
+        assert_eq!("********", token.display_value(false));
+        assert_eq!("abc", token.display_value(true));
+        // changing the url of a registry resets its token
+        set_key(&mut config, "registry.url", "https://registry.wapm.dev").unwrap();
+        assert_eq!(None, get_key(&config, "registry.token"));
+
+        set_key(
+            &mut config,
+            "registries.internal.url",
+            "https://wapm.example.com",
+        )
+        .unwrap();
+        set_key(&mut config, "registries.internal.token", "def").unwrap();
+        set_key(&mut config, "registry.default", "internal").unwrap();
+        assert_eq!(
+            Some("def".to_string()),
+            config.registry(None).unwrap().token
+        );
+        assert!(set_key(&mut config, "registries.missing.token", "ghi").is_err());
+        assert!(set_key(&mut config, "registry.default", "missing").is_err());
+        // the default registry can not be removed
+        assert!(unset_value(&mut config, "registry.url").is_err());
+
+        unset_value(&mut config, "registry.default").unwrap();
+        unset_value(&mut config, "registries.internal.url").unwrap();
+        unset_value(&mut config, "registry.url").unwrap();
+        unset_value(&mut config, "wax.cooldown").unwrap();
+        unset_value(&mut config, "wax.max-size").unwrap();
+        set_key(&mut config, "proxy.url", "").unwrap();
+        assert_eq!(Config::default(), config);
+        assert_eq!(None, get_key(&config, "proxy.url"));
+        assert_eq!(
+            Some(crate::constants::DEFAULT_RUNTIME.to_string()),
+            get_key(&config, "runtime")
+        );
+    }
+
+    #[test]
+    fn every_field_has_a_key() {
+        let mut registries = BTreeMap::new();
+        registries.insert(
+            "internal".to_string(),
+            Registry {
+                url: "https://wapm.example.com".to_string(),
+                token: Some("abc".to_string()),
+            },
+        );
+        // adding a field to the config breaks this until it is added here and to the keys
+        let config = Config {
+            wax_cooldown: 60,
+            wax_max_size: Some(1024),
+            wax_directory: Some("wax".into()),
+            default_registry: "internal".to_string(),
+            registries,
+            registry: None,
+            #[cfg(feature = "telemetry")]
+            telemetry: Default::default(),
+            #[cfg(feature = "update-notifications")]
+            update_notifications: Default::default(),
+            proxy: Proxy {
+                url: Some("http://localhost:3128".to_string()),
+            },
+            runtime: Some("wasmtime".to_string()),
+        };
+
+        fn leaf_paths(value: &toml::Value, path: Vec<String>, paths: &mut Vec<Vec<String>>) {
+            match value {
+                toml::Value::Table(table) => {
+                    for (key, value) in table {
+                        let mut path = path.clone();
+                        path.push(key.clone());
+                        leaf_paths(value, path, paths);
+                    }
+                }
+                _ => paths.push(path),
+            }
+        }
+        let mut paths = vec![];
+        leaf_paths(&toml::Value::try_from(&config).unwrap(), vec![], &mut paths);
+
+        let entries = entries(&config).unwrap();
+        for path in paths {
+            assert!(
+                entries
+                    .iter()
+                    .any(|entry| ResolvedKey::find(&config, &entry.key).unwrap().path == path),
+                "no key for {}",
+                path.join(".")
+            );
+        }
+        assert!(entries
+            .iter()
+            .any(|entry| entry.key == "registries.internal.token" && entry.secret));
+    }
 }
@@ -1,5 +1,7 @@
 pub const DEFAULT_RUNTIME: &str = "wasmer";
 pub const WAPM_RUNTIME_ENV_KEY: &str = "WAPM_RUNTIME";
+pub const WAPM_INSTALL_JOBS_ENV_KEY: &str = "WAPM_INSTALL_JOBS";
+pub const DEFAULT_INSTALL_JOBS: usize = 8;
 
 pub const RFC3339_FORMAT_STRING: &'static str = "%Y-%m-%dT%H:%M:%S-%f";
 pub const RFC3339_FORMAT_STRING_WITH_TIMEZONE: &'static str = "%Y-%m-%dT%H:%M:%S.%f+%Z";
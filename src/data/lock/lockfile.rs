@@ -3,6 +3,7 @@ use crate::data::lock::lockfile_module::{
     LockfileModule, LockfileModuleV2, LockfileModuleV3, LockfileModuleV4,
 };
 use crate::data::lock::{LOCKFILE_HEADER, LOCKFILE_NAME};
+use crate::exit_code::{Classify, ErrorKind};
 use semver::Version;
 use std::collections::BTreeMap;
 use std::fs::File;
@@ -38,6 +39,10 @@ pub type CommandMap = BTreeMap<String, LockfileCommand>;
 /// The latest Lockfile version
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct Lockfile {
+    /// The `Manifest::lockfile_hash` of the manifest this lockfile was generated from, used to
+    /// tell if the lockfile is out of date
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest_hash: Option<String>,
     pub modules: ModuleMap, // PackageName -> VersionNumber -> ModuleName -> Module
     pub commands: CommandMap, // CommandName -> Command
 }
@@ -130,3 +135,12 @@ pub enum LockfileError {
     )]
     TomlParseError(toml::de::Error),
 }
+
+impl Classify for LockfileError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            LockfileError::CommandNotFound(_) => ErrorKind::CommandNotFound,
+            _ => ErrorKind::Lockfile,
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::abi::Abi;
 use crate::data::manifest::{Module, PACKAGES_DIR_NAME};
+use crate::dataflow::package_cache::PackageSignature;
 use crate::util;
 use semver::Version;
 use std::path::{Path, PathBuf};
@@ -50,6 +51,15 @@ pub struct LockfileModule {
     pub source: String,
     /// The hash of the wasm module cached here for faster startup time
     pub prehashed_module_key: Option<String>,
+    /// The sha256 hash of the package archive, e.g. `sha256-<hex>`, checked when the package is
+    /// installed again
+    pub integrity: Option<String>,
+    /// The name of the registry in the config the package was installed from, unless it is the
+    /// default registry
+    pub registry: Option<String>,
+    /// The signature the package archive was verified with, checked when the package is installed
+    /// again without asking to trust its key
+    pub signature: Option<PackageSignature>,
 }
 
 pub type LockfileModuleV4 = LockfileModule;
@@ -61,6 +71,8 @@ impl LockfileModule {
         version: &Version,
         module: &Module,
         download_url: &str,
+        integrity: Option<String>,
+        signature: Option<PackageSignature>,
     ) -> Self {
         // build the entry path
         // this is path like /wapm_packages/_/lua@0.1.3/path/to/module/lua.wasm
@@ -82,16 +94,27 @@ impl LockfileModule {
             }
         };
 
+        // packages installed from a path or a git repository record where they came from
+        let resolved_source =
+            if download_url.starts_with("path+") || download_url.starts_with("git+") {
+                download_url.to_string()
+            } else {
+                format!("registry+{}", module.name)
+            };
+
         let lockfile_module = LockfileModule {
             name: module.name.to_string(),
             package_version: version.to_string(),
             package_name: name.to_string(),
             package_path: format!("{}@{}", name.to_string(), version.to_string()),
             resolved: download_url.to_string(),
-            resolved_source: format!("registry+{}", module.name),
+            resolved_source,
             abi: module.abi.clone(),
             prehashed_module_key: util::get_hashed_module_key(&path.join(&source)),
             source,
+            integrity,
+            registry: None,
+            signature,
         };
         lockfile_module
     }
@@ -115,6 +138,9 @@ impl LockfileModule {
             abi: module.abi.clone(),
             source: module.source.to_string_lossy().to_string(),
             prehashed_module_key: util::get_hashed_module_key(&wasm_module_full_path),
+            integrity: None,
+            registry: None,
+            signature: None,
         }
     }
 
@@ -160,6 +160,9 @@ pub fn convert_lockfilev3_to_v4(lockfile: LockfileV3, directory: &Path) -> Lockf
                     },
                     package_path,
                     prehashed_module_key: module_data.prehashed_module_key,
+                    integrity: None,
+                    registry: None,
+                    signature: None,
                 };
                 name_map.insert(k3, module);
             }
@@ -168,6 +171,7 @@ pub fn convert_lockfilev3_to_v4(lockfile: LockfileV3, directory: &Path) -> Lockf
         modules.insert(k1, ver_map);
     }
     LockfileV4 {
+        manifest_hash: None,
         modules,
         commands: lockfile.commands,
     }
@@ -9,14 +9,61 @@ static LOCKFILE_HEADER: &str = r#"# Lockfile v4
 # This file is automatically generated by Wapm.
 # It is not intended for manual editing. The schema of this file may change."#;
 
-use crate::data::manifest::MANIFEST_FILE_NAME;
+use crate::data::manifest::Manifest;
+use crate::dataflow::lockfile_packages::LockfileResult;
 use std::path::Path;
 
+/// Returns true if the lockfile was not generated from the current contents of the manifest.
 pub fn is_lockfile_out_of_date<P: AsRef<Path>>(directory: P) -> anyhow::Result<bool> {
+    let manifest = Manifest::find_in_directory(&directory)?;
+    let lockfile = match LockfileResult::find_in_directory(&directory) {
+        LockfileResult::Lockfile(lockfile) => lockfile,
+        LockfileResult::NoLockfile => return Ok(true),
+        LockfileResult::LockfileError(e) => return Err(e.into()),
+    };
+    Ok(lockfile.manifest_hash != Some(manifest.lockfile_hash()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::lock::is_lockfile_out_of_date;
+    use crate::data::lock::lockfile::Lockfile;
+    use crate::data::manifest::{Manifest, MANIFEST_FILE_NAME};
     use std::fs;
-    let wapm_lock_metadata = fs::metadata(directory.as_ref().join(LOCKFILE_NAME))?;
-    let wapm_toml_metadata = fs::metadata(directory.as_ref().join(MANIFEST_FILE_NAME))?;
-    let wapm_lock_last_modified = wapm_lock_metadata.modified()?;
-    let wapm_toml_last_modified = wapm_toml_metadata.modified()?;
-    Ok(wapm_lock_last_modified < wapm_toml_last_modified)
+
+    static MANIFEST: &str = r#"
+[package]
+name = "test"
+version = "1.0.0"
+description = "test package"
+
+[dependencies]
+"_/foo" = "^1"
+"#;
+
+    #[test]
+    fn lockfile_out_of_date_when_manifest_changes() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        fs::write(tmp_dir.path().join(MANIFEST_FILE_NAME), MANIFEST).unwrap();
+        assert!(is_lockfile_out_of_date(tmp_dir.path()).unwrap());
+
+        let manifest = Manifest::find_in_directory(tmp_dir.path()).unwrap();
+        let lockfile = Lockfile {
+            manifest_hash: Some(manifest.lockfile_hash()),
+            modules: Default::default(),
+            commands: Default::default(),
+        };
+        lockfile.save(tmp_dir.path()).unwrap();
+        assert!(!is_lockfile_out_of_date(tmp_dir.path()).unwrap());
+
+        // formatting is not a change
+        let reformatted =
+            MANIFEST.replace("\"_/foo\" = \"^1\"", "# pinned\n\"_/foo\"   =   \"^1\"");
+        fs::write(tmp_dir.path().join(MANIFEST_FILE_NAME), reformatted).unwrap();
+        assert!(!is_lockfile_out_of_date(tmp_dir.path()).unwrap());
+
+        let changed = MANIFEST.replace("^1", "^2");
+        fs::write(tmp_dir.path().join(MANIFEST_FILE_NAME), changed).unwrap();
+        assert!(is_lockfile_out_of_date(tmp_dir.path()).unwrap());
+    }
 }
@@ -1,6 +1,9 @@
 //! The Manifest file is where the core metadata of a wapm package lives
 use crate::abi::Abi;
+use crate::exit_code::{Classify, ErrorKind};
 use semver::Version;
+use sha2::{Digest, Sha256};
+use std::collections::btree_map::BTreeMap;
 use std::collections::hash_map::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -25,6 +28,9 @@ pub struct Package {
     pub homepage: Option<String>,
     #[serde(rename = "wasmer-extra-flags")]
     pub wasmer_extra_flags: Option<String>,
+    /// The runtime used to run the commands of this project, e.g. `wasmtime`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime: Option<String>,
     #[serde(
         rename = "disable-command-rename",
         default,
@@ -50,6 +56,55 @@ pub struct Command {
     pub module: String,
     pub main_args: Option<String>,
     pub package: Option<String>,
+    /// Environment variables set when running the command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<BTreeMap<String, String>>,
+    /// Directories made available to the command, of the form Guest -> Host path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mapdir: Option<BTreeMap<String, PathBuf>>,
+    /// Runtime flags used for this command in addition to the `wasmer-extra-flags` of the package
+    #[serde(
+        rename = "wasmer-extra-flags",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub wasmer_extra_flags: Option<String>,
+}
+
+impl Command {
+    /// Check that the environment variables, mapped directories and runtime flags of the command
+    /// can be passed to the runtime.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        for (key, value) in self.env.iter().flatten() {
+            let valid_key = !key.is_empty()
+                && !key.starts_with(|c: char| c.is_ascii_digit())
+                && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid_key || value.contains('\0') {
+                return Err(ValidationError::InvalidEnvironmentVariable(
+                    key.clone(),
+                    self.name.clone(),
+                ));
+            }
+        }
+        for (guest_path, host_path) in self.mapdir.iter().flatten() {
+            if guest_path.is_empty() || guest_path.contains(':') || host_path.as_os_str().is_empty()
+            {
+                return Err(ValidationError::InvalidMapdir(
+                    guest_path.clone(),
+                    self.name.clone(),
+                ));
+            }
+        }
+        if let Some(ref flags) = self.wasmer_extra_flags {
+            if shlex::split(flags).is_none() {
+                return Err(ValidationError::InvalidExtraFlags(
+                    flags.clone(),
+                    self.name.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -64,6 +119,60 @@ pub struct Module {
     pub interfaces: Option<HashMap<String, String>>,
 }
 
+/// A dependency of a package. Either a version (requirement) of a package in the default
+/// registry, or a table describing where the package can be found instead.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum Dependency {
+    Version(String),
+    Source(DependencySource),
+}
+
+/// The location of a package that is not installed from the default registry, e.g.
+/// `foo = { version = "^1", registry = "internal" }`, `foo = { path = "../foo" }` or
+/// `foo = { git = "https://...", rev = "main" }`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct DependencySource {
+    /// The version (requirement) of the package in the registry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The name of the registry in the config to install the package from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registry: Option<String>,
+    /// A directory containing the package, relative to this manifest
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// The url of a git repository containing the package at its root
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git: Option<String>,
+    /// The branch, tag or commit to check out of the git repository
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+}
+
+/// Serialize the dependencies with plain versions first, as TOML does not allow values after the
+/// tables of dependencies with a source.
+fn serialize_dependencies<S: serde::Serializer>(
+    dependencies: &Option<HashMap<String, Dependency>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeMap;
+    match dependencies {
+        Some(dependencies) => {
+            let mut entries: Vec<_> = dependencies.iter().collect();
+            entries.sort_by_key(|(name, dependency)| {
+                (matches!(dependency, Dependency::Source(_)), name.as_str())
+            });
+            let mut map = serializer.serialize_map(Some(entries.len()))?;
+            for (name, dependency) in entries {
+                map.serialize_entry(name, dependency)?;
+            }
+            map.end()
+        }
+        None => serializer.serialize_none(),
+    }
+}
+
 /// The manifest represents the file used to describe a Wasm package.
 ///
 /// The `module` field represents the wasm file to be published.
@@ -76,7 +185,8 @@ pub struct Module {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Manifest {
     pub package: Package,
-    pub dependencies: Option<HashMap<String, String>>,
+    #[serde(default, serialize_with = "serialize_dependencies")]
+    pub dependencies: Option<HashMap<String, Dependency>>,
     pub module: Option<Vec<Module>>,
     pub command: Option<Vec<Command>>,
     /// Of the form Guest -> Host path
@@ -118,6 +228,16 @@ impl Manifest {
             })
             .unwrap_or_default();
 
+        if let Some(ref flags) = self.package.wasmer_extra_flags {
+            if shlex::split(flags).is_none() {
+                return Err(ManifestError::ValidationError(
+                    ValidationError::InvalidPackageExtraFlags(
+                        flags.clone(),
+                        self.package.name.clone(),
+                    ),
+                ));
+            }
+        }
         if let Some(ref commands) = self.command {
             for command in commands {
                 if let Some(ref module) = module_map.get(&command.module) {
@@ -135,23 +255,52 @@ impl Manifest {
                         ),
                     ));
                 }
+                command.validate().map_err(ManifestError::ValidationError)?;
             }
         }
         Ok(())
     }
 
-    /// add a dependency
+    /// add a dependency, keeping the registry of a dependency that is already in the manifest
     pub fn add_dependency(&mut self, dependency_name: String, dependency_version: String) {
         let dependencies = self.dependencies.get_or_insert(Default::default());
-        dependencies.insert(dependency_name, dependency_version);
+        match dependencies.get_mut(&dependency_name) {
+            Some(Dependency::Source(source)) if source.version.is_some() => {
+                source.version = Some(dependency_version);
+            }
+            _ => {
+                dependencies.insert(dependency_name, Dependency::Version(dependency_version));
+            }
+        }
     }
 
     /// remove dependency by package name
-    pub fn remove_dependency(&mut self, dependency_name: &str) -> Option<String> {
+    pub fn remove_dependency(&mut self, dependency_name: &str) -> Option<Dependency> {
         let dependencies = self.dependencies.get_or_insert(Default::default());
         dependencies.remove(dependency_name)
     }
 
+    /// Hash the parts of the manifest that are recorded in the lockfile: the name and version of
+    /// the package, its dependencies, modules and commands. Formatting, comments and the order of
+    /// entries do not change the hash.
+    pub fn lockfile_hash(&self) -> String {
+        let mut modules: Vec<&Module> = self.module.iter().flatten().collect();
+        modules.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut commands: Vec<&Command> = self.command.iter().flatten().collect();
+        commands.sort_by(|a, b| a.name.cmp(&b.name));
+        // the keys of json objects are sorted
+        let sections = serde_json::json!({
+            "name": self.package.name,
+            "version": self.package.version,
+            "dependencies": self.dependencies,
+            "module": modules,
+            "command": commands,
+        });
+        let mut hasher = Sha256::new();
+        hasher.update(sections.to_string());
+        format!("sha256-{:x}", hasher.finalize())
+    }
+
     pub fn to_string(&self) -> anyhow::Result<String> {
         Ok(toml::to_string(self)?)
     }
@@ -215,6 +364,12 @@ pub enum ManifestError {
     ValidationError(ValidationError),
 }
 
+impl Classify for ManifestError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Manifest
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ValidationError {
     #[error(
@@ -223,6 +378,20 @@ pub enum ValidationError {
     MissingABI(String, String),
     #[error("missing module {0} in manifest used by command {1}")]
     MissingModuleForCommand(String, String),
+    #[error("invalid environment variable name {0} or value used by command {1}")]
+    InvalidEnvironmentVariable(String, String),
+    #[error("invalid mapped directory {0} used by command {1}; the guest path must not be empty or contain `:`")]
+    InvalidMapdir(String, String),
+    #[error("could not parse the wasmer-extra-flags `{0}` of command {1}")]
+    InvalidExtraFlags(String, String),
+    #[error("could not parse the wasmer-extra-flags `{0}` of package {1}")]
+    InvalidPackageExtraFlags(String, String),
+}
+
+impl Classify for ValidationError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Manifest
+    }
 }
 
 #[cfg(test)]
@@ -275,11 +444,56 @@ mod command_tests {
         let commands = &manifest.command.unwrap();
         assert_eq!(2, commands.len());
     }
+
+    #[test]
+    fn validate_command_runtime_settings() {
+        let wapm_toml = r#"
+            [package]
+            name = "test"
+            version = "1.0.0"
+            description = "The best package."
+            [[module]]
+            name = "test"
+            source = "test.wasm"
+            abi = "wasi"
+            [[command]]
+            name = "serve"
+            module = "test"
+            env = { LOG_LEVEL = "debug", PORT = "8080" }
+            mapdir = { "/data" = "data" }
+            wasmer-extra-flags = "--enable-threads"
+        "#;
+        let mut manifest: Manifest = toml::from_str(wapm_toml).unwrap();
+        manifest.validate().unwrap();
+        let command = &manifest.command.as_ref().unwrap()[0];
+        assert_eq!("debug", command.env.as_ref().unwrap()["LOG_LEVEL"]);
+        assert_eq!(
+            std::path::Path::new("data"),
+            command.mapdir.as_ref().unwrap()["/data"]
+        );
+
+        let command = &mut manifest.command.as_mut().unwrap()[0];
+        command
+            .env
+            .as_mut()
+            .unwrap()
+            .insert("LOG LEVEL".to_string(), "debug".to_string());
+        assert!(manifest.validate().is_err());
+
+        let command = &mut manifest.command.as_mut().unwrap()[0];
+        command.env = None;
+        command
+            .mapdir
+            .as_mut()
+            .unwrap()
+            .insert("/data:rw".to_string(), "data".into());
+        assert!(manifest.validate().is_err());
+    }
 }
 
 #[cfg(test)]
 mod dependency_tests {
-    use crate::data::manifest::{Manifest, MANIFEST_FILE_NAME};
+    use crate::data::manifest::{Dependency, DependencySource, Manifest, MANIFEST_FILE_NAME};
     use crate::util::create_temp_dir;
     use std::fs::File;
     use std::io::Write;
@@ -322,6 +536,55 @@ mod dependency_tests {
         );
         assert_eq!(2, manifest.dependencies.as_ref().unwrap().len());
     }
+
+    #[test]
+    fn parse_dependency_sources() {
+        let manifest_str = r#"
+[package]
+name = "test"
+version = "1.0.0"
+description = "description"
+
+[dependencies]
+foo = "^1"
+bar = { path = "../bar" }
+baz = { git = "https://example.com/baz.git", rev = "v1.0.0" }
+qux = { version = "^2", registry = "internal" }
+"#;
+        let manifest: Manifest = toml::from_str(manifest_str).unwrap();
+        let dependencies = manifest.dependencies.as_ref().unwrap();
+        assert_eq!(
+            Some(&Dependency::Version("^1".to_string())),
+            dependencies.get("foo")
+        );
+        assert_eq!(
+            Some(&Dependency::Source(DependencySource {
+                path: Some("../bar".into()),
+                ..Default::default()
+            })),
+            dependencies.get("bar")
+        );
+        assert_eq!(
+            Some(&Dependency::Source(DependencySource {
+                git: Some("https://example.com/baz.git".to_string()),
+                rev: Some("v1.0.0".to_string()),
+                ..Default::default()
+            })),
+            dependencies.get("baz")
+        );
+        assert_eq!(
+            Some(&Dependency::Source(DependencySource {
+                version: Some("^2".to_string()),
+                registry: Some("internal".to_string()),
+                ..Default::default()
+            })),
+            dependencies.get("qux")
+        );
+
+        // dependency sources survive a round trip through the manifest
+        let reparsed: Manifest = toml::from_str(&manifest.to_string().unwrap()).unwrap();
+        assert_eq!(manifest.dependencies, reparsed.dependencies);
+    }
 }
 
 #[cfg(test)]
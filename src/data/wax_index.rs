@@ -3,46 +3,106 @@
 
 use crate::config;
 use crate::constants::RFC3339_FORMAT_STRING;
+use crate::exit_code::{Classify, ErrorKind};
 use semver::Version;
 use std::convert::From;
-use std::env;
 use std::fs;
 use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 use thiserror::Error;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Package directories being removed are moved here until the index is saved
+static REMOVING_DIR_NAME: &str = ".removing";
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct WaxIndex {
     base_dir: PathBuf,
     index: HashMap<String, WaxEntry>,
+    /// Where the index is saved
+    #[serde(skip)]
+    index_path: PathBuf,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 struct WaxEntry {
     /// Fully qualified package name `namespace/name@version`
     package_name: String,
     /// Timestamp when wax was last updated
     last_updated: String,
+    /// Timestamp when the command was last looked up to run it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_used: Option<String>,
 }
 
 impl WaxEntry {
     fn new(name: String, version: Version, time: String) -> Self {
         WaxEntry {
             package_name: format!("{}@{}", name, version),
-            last_updated: time,
+            last_updated: time.clone(),
+            last_used: Some(time),
         }
     }
+
+    /// When the command was last used, entries written by older versions of wax only know when
+    /// they were updated.
+    fn last_used(&self) -> Option<time::Timespec> {
+        let last_used = self.last_used.as_ref().unwrap_or(&self.last_updated);
+        time::strptime(last_used, RFC3339_FORMAT_STRING)
+            .ok()
+            .map(|tm| tm.to_timespec())
+    }
+}
+
+/// A package version in the wax directory and the commands that run it.
+#[derive(Debug)]
+pub struct WaxPackage {
+    /// Fully qualified package name `namespace/name@version`
+    pub package_name: String,
+    /// The commands in the index that run this package version
+    pub commands: Vec<String>,
+    /// When one of the commands was last used, `None` if no command runs this version anymore
+    pub last_used: Option<time::Timespec>,
+    /// The size of the package directory in bytes
+    pub size: u64,
 }
 
 impl WaxIndex {
     /// Read the `WaxIndex` from disk
     pub fn open() -> Result<Self, WaxIndexError> {
+        // a project can not move the packages of every project
+        let wax_directory = config::ConfigSources::load_without_project()?
+            .config()?
+            .wax_directory()?;
+        let mut wax_index = Self::read(wax_directory.clone())?;
+        // the index keeps the directory it was written for, move the packages when it changed
+        if wax_index.base_dir != wax_directory {
+            wax_index.move_to(wax_directory)?;
+        }
+
+        // ensure the directory exists
+        fs::create_dir_all(&wax_index.base_dir)?;
+        trace!("WaxIndex created!");
+
+        Ok(wax_index)
+    }
+
+    /// Read the `WaxIndex` from disk without changing anything, leaving the packages where the
+    /// index was last saved with them. For looking up packages without installing them.
+    pub fn open_read_only() -> Result<Self, WaxIndexError> {
+        let wax_directory = config::ConfigSources::load_without_project()?
+            .config()?
+            .wax_directory()?;
+        Self::read(wax_directory)
+    }
+
+    /// Read the index file, or start an empty index for the wax directory if there is none.
+    fn read(wax_directory: PathBuf) -> Result<Self, WaxIndexError> {
         trace!("Loading WaxIndex!");
         let wax_path = config::Config::get_wax_file_path()?;
-        let wax_index = if wax_path.exists() {
-            let mut f = fs::OpenOptions::new().read(true).open(wax_path)?;
+        let mut wax_index = if wax_path.exists() {
+            let mut f = fs::OpenOptions::new().read(true).open(&wax_path)?;
 
             let index_str = {
                 let mut s = String::new();
@@ -53,7 +113,8 @@ impl WaxIndex {
             if index_str.is_empty() {
                 WaxIndex {
                     index: Default::default(),
-                    base_dir: env::temp_dir().join("wax"),
+                    base_dir: wax_directory,
+                    index_path: PathBuf::new(),
                 }
             } else {
                 serde_json::from_str(&index_str)?
@@ -61,36 +122,38 @@ impl WaxIndex {
         } else {
             WaxIndex {
                 index: Default::default(),
-                base_dir: env::temp_dir().join("wax"),
+                base_dir: wax_directory,
+                index_path: PathBuf::new(),
             }
         };
-
-        // ensure the directory exists
-        fs::create_dir_all(&wax_index.base_dir)?;
-        trace!("WaxIndex created!");
-
+        wax_index.index_path = wax_path;
         Ok(wax_index)
     }
 
     /// Save the `WaxIndex` to disk
     pub fn save(&self) -> Result<(), WaxIndexError> {
         trace!("Saving WaxIndex!");
-        let wax_path = config::Config::get_wax_file_path()?;
+        // write a new file and move it over the old one, so the index is never half written
+        let mut temp_path = self.index_path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
         let mut f = fs::OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
-            .open(wax_path)?;
+            .open(&temp_path)?;
 
         let json_str = serde_json::to_string(self)?;
 
         f.write_all(json_str.as_bytes())?;
+        drop(f);
+        fs::rename(&temp_path, &self.index_path)?;
         trace!("WaxIndex saved!");
         Ok(())
     }
 
     /// This function takes a `&mut` because it will update itself with the
-    /// information that it finds.
+    /// information that it finds. Finding an entry marks it as used.
     pub fn search_for_entry(
         &mut self,
         entry: String,
@@ -98,9 +161,10 @@ impl WaxIndex {
         if let Some(WaxEntry {
             package_name,
             last_updated,
-        }) = self.index.get(&entry)
+            last_used,
+        }) = self.index.get_mut(&entry)
         {
-            let location = self.base_path().join(package_name);
+            let location = self.base_dir.join(&package_name);
             // check if entry still exists and if not remove it
             if location.exists() {
                 trace!("Wax entry found and it still exists!");
@@ -118,11 +182,12 @@ impl WaxIndex {
                         entry: entry.clone(),
                     })?;
                 let last_seen =
-                    time::strptime(&last_updated, RFC3339_FORMAT_STRING).map_err(|e| {
+                    time::strptime(last_updated, RFC3339_FORMAT_STRING).map_err(|e| {
                         WaxIndexError::EntryCorrupt {
                             entry: format!("{}", e),
                         }
                     })?;
+                *last_used = Some(now_string());
                 return Ok((package_name, version, last_seen.to_timespec()));
             }
             trace!("Wax entry found but it no longer exists, removing from registry!");
@@ -138,10 +203,8 @@ impl WaxIndex {
     ///
     /// Returns true if an existing entry was updated.
     pub fn insert_entry(&mut self, entry: String, version: Version, package_name: String) -> bool {
-        let now = time::now_utc();
-        let now_str = time::strftime(RFC3339_FORMAT_STRING, &now).expect("Format current time!");
         self.index
-            .insert(entry, WaxEntry::new(package_name, version, now_str))
+            .insert(entry, WaxEntry::new(package_name, version, now_string()))
             .is_some()
     }
 
@@ -149,6 +212,239 @@ impl WaxIndex {
     pub fn base_path(&self) -> &Path {
         &self.base_dir
     }
+
+    /// Move the installed packages to a new wax directory. The commands of packages that could
+    /// not be moved are dropped from the index, so they are installed again when next used.
+    fn move_to(&mut self, base_dir: PathBuf) -> Result<(), WaxIndexError> {
+        debug!(
+            "Moving wax packages from {} to {}",
+            self.base_dir.to_string_lossy(),
+            base_dir.to_string_lossy()
+        );
+        fs::create_dir_all(&base_dir)?;
+        let package_names: HashSet<String> = self
+            .index
+            .values()
+            .map(|entry| entry.package_name.clone())
+            .collect();
+        let old_base_dir = std::mem::replace(&mut self.base_dir, base_dir);
+        for package_name in package_names {
+            let location = old_base_dir.join(&package_name);
+            let new_location = self.base_dir.join(&package_name);
+            if !location.exists() || new_location.exists() {
+                continue;
+            }
+            if let Err(e) = move_directory(&location, &new_location) {
+                warn!(
+                    "Failed to move wax package {} to {}: {}",
+                    package_name,
+                    new_location.to_string_lossy(),
+                    e
+                );
+            }
+        }
+
+        let base_dir = &self.base_dir;
+        self.index
+            .retain(|_, entry| base_dir.join(&entry.package_name).exists());
+        self.save()
+    }
+
+    /// All package versions in the wax directory with the commands that run them, least
+    /// recently used first. Versions that no command runs anymore come first.
+    pub fn packages(&self) -> Result<Vec<WaxPackage>, WaxIndexError> {
+        let mut packages = BTreeMap::new();
+        for package_name in self.package_directories()? {
+            let size = directory_size(&self.base_dir.join(&package_name))?;
+            packages.insert(
+                package_name.clone(),
+                WaxPackage {
+                    package_name,
+                    commands: vec![],
+                    last_used: None,
+                    size,
+                },
+            );
+        }
+        for (command, entry) in self.index.iter() {
+            if let Some(package) = packages.get_mut(&entry.package_name) {
+                package.commands.push(command.clone());
+                package.last_used = package.last_used.max(entry.last_used());
+            }
+        }
+        let mut packages: Vec<WaxPackage> = packages.into_values().collect();
+        for package in packages.iter_mut() {
+            package.commands.sort();
+        }
+        packages.sort_by_key(|package| package.last_used);
+        Ok(packages)
+    }
+
+    /// Remove the package versions that were not used within `older_than`, or all of them if
+    /// it is not given. Versions that no command runs anymore are always removed.
+    pub fn prune(
+        &mut self,
+        older_than: Option<time::Duration>,
+    ) -> Result<Vec<WaxPackage>, WaxIndexError> {
+        let cutoff = older_than.map(|duration| time::now_utc().to_timespec() - duration);
+        let removed = self
+            .packages()?
+            .into_iter()
+            .filter(|package| match (package.last_used, cutoff) {
+                (Some(last_used), Some(cutoff)) => last_used < cutoff,
+                _ => true,
+            })
+            .collect();
+        self.remove_packages(removed)
+    }
+
+    /// Remove the least recently used package versions until the rest fit in `max_size` bytes.
+    /// The most recently used version is always kept.
+    pub fn evict_least_recently_used(
+        &mut self,
+        max_size: u64,
+    ) -> Result<Vec<WaxPackage>, WaxIndexError> {
+        let packages = self.packages()?;
+        let mut total_size: u64 = packages.iter().map(|package| package.size).sum();
+        let evictable = packages.len().saturating_sub(1);
+        let mut removed = vec![];
+        for package in packages.into_iter().take(evictable) {
+            if total_size <= max_size {
+                break;
+            }
+            total_size -= package.size;
+            removed.push(package);
+        }
+        self.remove_packages(removed)
+    }
+
+    /// Remove the directories of the packages and the index entries of their commands. The
+    /// directories are moved aside until the index is saved, and moved back if that fails.
+    fn remove_packages(
+        &mut self,
+        packages: Vec<WaxPackage>,
+    ) -> Result<Vec<WaxPackage>, WaxIndexError> {
+        if packages.is_empty() {
+            return Ok(packages);
+        }
+        let removing_dir = self.base_dir.join(REMOVING_DIR_NAME);
+        if removing_dir.exists() {
+            fs::remove_dir_all(&removing_dir)?;
+        }
+        fs::create_dir_all(&removing_dir)?;
+
+        let mut moved_directories = vec![];
+        for (idx, package) in packages.iter().enumerate() {
+            let location = self.base_dir.join(&package.package_name);
+            let removed_location = removing_dir.join(idx.to_string());
+            if let Err(e) = fs::rename(&location, &removed_location) {
+                restore_directories(&moved_directories);
+                return Err(e.into());
+            }
+            moved_directories.push((location, removed_location));
+        }
+
+        // this drops the entries of the moved packages and of packages deleted by other means
+        let old_index = self.index.clone();
+        let base_dir = &self.base_dir;
+        self.index
+            .retain(|_, entry| base_dir.join(&entry.package_name).exists());
+        if let Err(e) = self.save() {
+            self.index = old_index;
+            restore_directories(&moved_directories);
+            return Err(e);
+        }
+
+        if let Err(e) = fs::remove_dir_all(&removing_dir) {
+            warn!(
+                "Failed to delete removed wax packages in {}: {}",
+                removing_dir.to_string_lossy(),
+                e
+            );
+        }
+        Ok(packages)
+    }
+
+    /// The package versions in the wax directory, `namespace/name@version`
+    fn package_directories(&self) -> Result<Vec<String>, WaxIndexError> {
+        let mut package_names = vec![];
+        for namespace in fs::read_dir(&self.base_dir)? {
+            let namespace = namespace?;
+            let namespace_name = namespace.file_name().to_string_lossy().to_string();
+            if namespace_name.starts_with('.') || !namespace.file_type()?.is_dir() {
+                continue;
+            }
+            for package in fs::read_dir(namespace.path())? {
+                let package = package?;
+                let package_name = package.file_name().to_string_lossy().to_string();
+                if package_name.contains('@') && package.file_type()?.is_dir() {
+                    package_names.push(format!("{}/{}", namespace_name, package_name));
+                }
+            }
+        }
+        Ok(package_names)
+    }
+}
+
+fn now_string() -> String {
+    let now = time::now_utc();
+    time::strftime(RFC3339_FORMAT_STRING, &now).expect("Format current time!")
+}
+
+fn directory_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            directory_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+/// Move a directory, copying it when it is on another file system.
+fn move_directory(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    if let Err(e) = copy_directory(from, to) {
+        let _ = fs::remove_dir_all(to);
+        return Err(e);
+    }
+    fs::remove_dir_all(from)
+}
+
+fn copy_directory(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_directory(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+fn restore_directories(moved_directories: &[(PathBuf, PathBuf)]) {
+    for (location, removed_location) in moved_directories.iter().rev() {
+        if let Err(e) = fs::rename(removed_location, location) {
+            warn!(
+                "Failed to move {} back to {}: {}",
+                removed_location.to_string_lossy(),
+                location.to_string_lossy(),
+                e
+            );
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -165,6 +461,15 @@ pub enum WaxIndexError {
     EntryCorrupt { entry: String },
 }
 
+impl Classify for WaxIndexError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            WaxIndexError::ConfigError(_) => ErrorKind::Config,
+            _ => ErrorKind::Io,
+        }
+    }
+}
+
 impl From<config::GlobalConfigError> for WaxIndexError {
     fn from(other: config::GlobalConfigError) -> Self {
         WaxIndexError::ConfigError(other)
@@ -182,3 +487,106 @@ impl From<serde_json::error::Error> for WaxIndexError {
         WaxIndexError::SerdeError(other)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::constants::RFC3339_FORMAT_STRING;
+    use crate::data::wax_index::{WaxEntry, WaxIndex};
+    use std::fs;
+    use std::path::Path;
+
+    fn create_package(base_dir: &Path, package_name: &str, size: usize) {
+        let package_dir = base_dir.join(package_name);
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(package_dir.join("module.wasm"), vec![0u8; size]).unwrap();
+    }
+
+    fn entry(package_name: &str, days_ago: i64) -> WaxEntry {
+        let time = time::at_utc(time::now_utc().to_timespec() - time::Duration::days(days_ago));
+        WaxEntry {
+            package_name: package_name.to_string(),
+            last_updated: time::strftime(RFC3339_FORMAT_STRING, &time).unwrap(),
+            last_used: None,
+        }
+    }
+
+    #[test]
+    fn evict_and_prune_least_recently_used_packages() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let base_dir = tmp_dir.path().join("wax");
+        create_package(&base_dir, "_/foo@1.0.0", 100);
+        create_package(&base_dir, "_/foo@0.9.0", 50);
+        create_package(&base_dir, "_/bar@1.0.0", 10);
+        let mut wax_index = WaxIndex {
+            base_dir: base_dir.clone(),
+            index: Default::default(),
+            index_path: tmp_dir.path().join(".wax_index.json"),
+        };
+        wax_index
+            .index
+            .insert("foo".to_string(), entry("_/foo@1.0.0", 1));
+        wax_index
+            .index
+            .insert("bar".to_string(), entry("_/bar@1.0.0", 60));
+
+        let packages = wax_index.packages().unwrap();
+        let package_names: Vec<&str> = packages
+            .iter()
+            .map(|package| package.package_name.as_str())
+            .collect();
+        assert_eq!(
+            vec!["_/foo@0.9.0", "_/bar@1.0.0", "_/foo@1.0.0"],
+            package_names
+        );
+        assert!(packages[0].commands.is_empty());
+        assert_eq!(vec!["foo".to_string()], packages[2].commands);
+        assert_eq!(100, packages[2].size);
+
+        // the version no command runs anymore goes first
+        let evicted = wax_index.evict_least_recently_used(120).unwrap();
+        assert_eq!(1, evicted.len());
+        assert!(!base_dir.join("_/foo@0.9.0").exists());
+        assert!(base_dir.join("_/bar@1.0.0").exists());
+
+        let pruned = wax_index.prune(Some(time::Duration::days(30))).unwrap();
+        assert_eq!("_/bar@1.0.0", pruned[0].package_name);
+        assert_eq!(1, pruned.len());
+        assert!(!base_dir.join("_/bar@1.0.0").exists());
+        assert!(!wax_index.index.contains_key("bar"));
+        assert!(!base_dir.join(".removing").exists());
+
+        // the most recently used package is kept even if it does not fit
+        assert!(wax_index.evict_least_recently_used(0).unwrap().is_empty());
+        let saved_index = fs::read_to_string(tmp_dir.path().join(".wax_index.json")).unwrap();
+        assert!(saved_index.contains("_/foo@1.0.0"));
+        assert!(!saved_index.contains("_/bar@1.0.0"));
+    }
+
+    #[test]
+    fn move_packages_to_new_directory() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let old_base_dir = tmp_dir.path().join("tmp").join("wax");
+        let new_base_dir = tmp_dir.path().join("wasmer").join("wax");
+        create_package(&old_base_dir, "_/foo@1.0.0", 10);
+        let mut wax_index = WaxIndex {
+            base_dir: old_base_dir.clone(),
+            index: Default::default(),
+            index_path: tmp_dir.path().join(".wax_index.json"),
+        };
+        wax_index
+            .index
+            .insert("foo".to_string(), entry("_/foo@1.0.0", 1));
+        wax_index
+            .index
+            .insert("gone".to_string(), entry("_/gone@1.0.0", 1));
+
+        wax_index.move_to(new_base_dir.clone()).unwrap();
+        assert_eq!(new_base_dir, wax_index.base_path());
+        assert!(new_base_dir.join("_/foo@1.0.0/module.wasm").exists());
+        assert!(!old_base_dir.join("_/foo@1.0.0").exists());
+        assert!(wax_index.index.contains_key("foo"));
+        assert!(!wax_index.index.contains_key("gone"));
+        let saved_index = fs::read_to_string(tmp_dir.path().join(".wax_index.json")).unwrap();
+        assert!(saved_index.contains(new_base_dir.to_string_lossy().as_ref()));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::dataflow::source_packages::SourcePackages;
 use crate::dataflow::{normalize_global_namespace, PackageKey};
 use semver::Version;
 use std::collections::HashSet;
@@ -53,6 +54,37 @@ impl<'a> AddedPackages<'a> {
         Self { packages }
     }
 
+    /// Replace every added package with the exact version picked by the version solver.
+    pub fn pin_solved_versions(self, solved_keys: &HashSet<PackageKey<'a>>) -> Self {
+        let packages = self
+            .packages
+            .into_iter()
+            .map(|key| {
+                solved_keys
+                    .iter()
+                    .find(|solved_key| solved_key.satisfies(&key))
+                    .cloned()
+                    .unwrap_or(key)
+            })
+            .collect();
+        Self { packages }
+    }
+
+    /// Separate the packages from a path or a git repository, which are not installed from the
+    /// registry.
+    pub fn split_source_packages(self) -> (Self, SourcePackages<'a>) {
+        let (source_packages, packages) = self
+            .packages
+            .into_iter()
+            .partition(PackageKey::is_source_package);
+        (
+            Self { packages },
+            SourcePackages {
+                packages: source_packages,
+            },
+        )
+    }
+
     /// parse the version as semver, or error
     fn extract_name_and_version(pair: (&'a str, &'a str)) -> Result<(&'a str, Version), Error> {
         Version::parse(pair.1)
@@ -105,4 +137,39 @@ mod test {
             )));
         assert_eq!(1, pruned_packages.packages.len())
     }
+
+    #[test]
+    fn pin_solved_versions_test() {
+        let mut packages = HashSet::new();
+        packages.insert(PackageKey::new_registry_package_range(
+            "_/foo",
+            semver::VersionReq::parse("^1").unwrap(),
+        ));
+        packages.insert(PackageKey::new_registry_package(
+            "_/bar",
+            semver::Version::new(2, 0, 0),
+        ));
+        let added_packages = AddedPackages { packages };
+
+        let mut solved_keys = HashSet::new();
+        solved_keys.insert(PackageKey::new_registry_package(
+            "_/foo",
+            semver::Version::new(1, 4, 0),
+        ));
+
+        let pinned_packages = added_packages.pin_solved_versions(&solved_keys);
+        assert!(pinned_packages
+            .packages
+            .contains(&PackageKey::new_registry_package(
+                "_/foo",
+                semver::Version::new(1, 4, 0)
+            )));
+        assert!(pinned_packages
+            .packages
+            .contains(&PackageKey::new_registry_package(
+                "_/bar",
+                semver::Version::new(2, 0, 0)
+            )));
+        assert_eq!(2, pinned_packages.packages.len())
+    }
 }
@@ -21,6 +21,12 @@ impl<'a> ChangedManifestPackages<'a> {
             .into_iter()
             .filter(|package_key| {
                 match package_key {
+                    // a local directory may have changed since it was copied, always copy it again
+                    PackageKey::PathPackage(_) => true,
+                    // the repository is fetched again if the url or the revision has changed
+                    PackageKey::GitPackage(_) => !lockfile_keys
+                        .iter()
+                        .any(|k| lockfile_data.is_locked_for(k, package_key)),
                     // if the package version is exact, then do a contains check,
                     // the lockfile will always contain exact package keys
                     PackageKey::WapmPackage(_) => !lockfile_keys.contains(package_key),
@@ -39,6 +45,7 @@ impl<'a> ChangedManifestPackages<'a> {
 
 #[cfg(test)]
 mod test {
+    use crate::data::lock::lockfile_module::LockfileModule;
     use crate::dataflow::changed_manifest_packages::ChangedManifestPackages;
     use crate::dataflow::lockfile_packages::{LockfilePackage, LockfilePackages};
     use crate::dataflow::manifest_packages::ManifestPackages;
@@ -162,6 +169,44 @@ mod test {
         assert_eq!(1, changed_packages.packages.len());
     }
 
+    #[test]
+    fn git_package_changes_with_revision() {
+        let git_package = |rev: &str| {
+            PackageKey::new_git_package(
+                "_/foo",
+                "https://example.com/foo.git".to_string(),
+                Some(rev.to_string()),
+            )
+        };
+        let mut lockfile_packages = HashMap::new();
+        let mut module = LockfileModule::default();
+        module.resolved_source = "git+https://example.com/foo.git?rev=v1#abc123".to_string();
+        lockfile_packages.insert(
+            PackageKey::new_registry_package("_/foo", semver::Version::new(1, 0, 0)),
+            LockfilePackage {
+                modules: vec![module],
+                commands: vec![],
+            },
+        );
+        let lockfile_data = LockfilePackages {
+            packages: lockfile_packages,
+        };
+
+        for (rev, changed) in [("v1", 0), ("v2", 1)].iter() {
+            let mut manifest_package_keys = HashSet::new();
+            manifest_package_keys.insert(git_package(rev));
+            let manifest_data = ManifestPackages {
+                packages: manifest_package_keys,
+            };
+            let changed_packages =
+                ChangedManifestPackages::get_changed_packages_from_manifest_and_lockfile(
+                    &manifest_data,
+                    &lockfile_data,
+                );
+            assert_eq!(*changed, changed_packages.packages.len());
+        }
+    }
+
     #[test]
     fn one_shared_and_one_added() {
         let mut manifest_package_keys = HashSet::new();
@@ -3,6 +3,8 @@ use crate::data::lock::lockfile::{Lockfile, LockfileError};
 use crate::data::manifest::Manifest;
 use crate::dataflow::lockfile_packages::LockfileResult;
 use crate::dataflow::manifest_packages::ManifestResult;
+use crate::exit_code::{Classify, ErrorKind};
+use semver::Version;
 use std::path::{Path, PathBuf};
 use thiserror::Error;
 
@@ -71,6 +73,18 @@ pub enum Error {
     CouldNotOpenGlobalsDirectory(String, String),
 }
 
+impl Classify for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::CommandNotFound(_) => ErrorKind::CommandNotFound,
+            Error::CommandNotFoundInLocalDirectoryAndErrorReadingGlobalDirectory(..)
+            | Error::CommandFoundButCorrespondingModuleIsMissing(..) => ErrorKind::Lockfile,
+            Error::ErrorReadingLocalDirectory(..) => ErrorKind::Manifest,
+            Error::CouldNotOpenGlobalsDirectory(..) => ErrorKind::Io,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum FindCommandResult {
     CommandNotFound(String),
@@ -80,6 +94,8 @@ pub enum FindCommandResult {
         args: Option<String>,
         module_name: String,
         prehashed_cache_key: Option<String>,
+        package_name: String,
+        package_version: Version,
     },
     Error(anyhow::Error),
 }
@@ -117,6 +133,8 @@ impl FindCommandResult {
                             module_name: module.name.clone(),
                             // don't use prehashed cache key for local modules
                             prehashed_cache_key: None,
+                            package_name: lockfile_command.package_name.clone(),
+                            package_version: lockfile_command.package_version.clone(),
                         },
                         None => FindCommandResult::Error(
                             Error::CommandFoundButCorrespondingModuleIsMissing(
@@ -152,6 +170,8 @@ impl FindCommandResult {
                                 module_name: lockfile_module.name.clone(),
                                 prehashed_cache_key: lockfile
                                     .get_prehashed_cache_key_from_command(&lockfile_command),
+                                package_name: lockfile_command.package_name.clone(),
+                                package_version: lockfile_command.package_version.clone(),
                             }
                         }
                         Err(e) => FindCommandResult::Error(e),
@@ -185,6 +205,8 @@ impl FindCommandResult {
                             module_name: lockfile_module.name.clone(),
                             prehashed_cache_key: lockfile
                                 .get_prehashed_cache_key_from_command(&lockfile_command),
+                            package_name: lockfile_command.package_name.clone(),
+                            package_version: lockfile_command.package_version.clone(),
                         }
                     }
                     Err(_e) => {
@@ -207,12 +229,10 @@ impl FindCommandResult {
                 debug!("Looking for local command in the lockfile");
                 return Self::find_command_in_lockfile(command_name, l, directory);
             }
-            // the edge case of a manifest, but no lockfile would an invalid state. This function
-            // should always be run after updating the lockfile with the latest manifest changes.
-            // If that function were to fail so horribly that it did not error, and no lockfile was
-            // generated, then we will get this panic.
+            // nothing is installed until the lockfile is generated, e.g. when the command is only
+            // looked up with `wapm which`, which does not update the lockfile
             (ManifestResult::Manifest(_m), LockfileResult::NoLockfile) => {
-                panic!("Manifest exists, but lockfile not found!")
+                debug!("Manifest exists, but lockfile not found");
             }
             (ManifestResult::Manifest(m), LockfileResult::Lockfile(l)) => {
                 debug!("Looking for local command in the manifest and lockfile");
@@ -234,6 +254,9 @@ pub struct Command {
     pub is_global: bool,
     /// the prehashed module key
     pub prehashed_cache_key: Option<String>,
+    /// the package of the command
+    pub package_name: String,
+    pub package_version: Version,
 }
 
 /// Get a command from anywhere, where anywhere is the set of packages in the local lockfile and the global lockfile.
@@ -252,6 +275,8 @@ pub fn get_command_from_anywhere<S: AsRef<str>>(command_name: S) -> Result<Comma
             args,
             module_name,
             prehashed_cache_key,
+            package_name,
+            package_version,
         } => {
             return Ok(Command {
                 source,
@@ -260,6 +285,8 @@ pub fn get_command_from_anywhere<S: AsRef<str>>(command_name: S) -> Result<Comma
                 module_name,
                 is_global: false,
                 prehashed_cache_key,
+                package_name,
+                package_version,
             });
         }
         FindCommandResult::Error(e) => {
@@ -286,6 +313,8 @@ pub fn get_command_from_anywhere<S: AsRef<str>>(command_name: S) -> Result<Comma
             args,
             module_name,
             prehashed_cache_key,
+            package_name,
+            package_version,
         } => {
             return Ok(Command {
                 source,
@@ -294,6 +323,8 @@ pub fn get_command_from_anywhere<S: AsRef<str>>(command_name: S) -> Result<Comma
                 module_name,
                 is_global: true,
                 prehashed_cache_key,
+                package_name,
+                package_version,
             });
         }
         FindCommandResult::Error(e) => {
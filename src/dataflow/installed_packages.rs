@@ -2,12 +2,18 @@
     not(feature = "full"),
     allow(dead_code, unused_imports, unused_variables)
 )]
+use crate::constants::{DEFAULT_INSTALL_JOBS, WAPM_INSTALL_JOBS_ENV_KEY};
 use crate::data::manifest::Manifest;
 #[cfg(feature = "full")]
 use crate::database;
+use crate::dataflow::lockfile_packages::LockfilePackages;
 use crate::dataflow::manifest_packages::ManifestResult;
-use crate::dataflow::resolved_packages::ResolvedPackages;
+use crate::dataflow::package_cache::{
+    content_hash, CacheEntry, NotCachedError, PackageCache, PackageSignature,
+};
+use crate::dataflow::resolved_packages::{PackageRegistries, ResolvedPackages};
 use crate::dataflow::WapmPackageKey;
+use crate::exit_code::{Classify, ErrorKind};
 use crate::graphql::VERSION;
 #[allow(unused_imports)]
 use crate::keys;
@@ -18,10 +24,19 @@ use crate::util::{
     get_package_namespace_and_name,
 };
 use flate2::read::GzDecoder;
+#[cfg(feature = "full")]
+use lazy_static::lazy_static;
+#[cfg(feature = "full")]
+use rusqlite::Connection;
+use std::cmp::Ordering;
+use std::env;
 use std::fs::{self, OpenOptions};
 use std::io;
 use std::io::{Seek, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Mutex;
+use std::thread;
 use tar::Archive;
 use thiserror::Error;
 #[cfg(not(target_os = "wasi"))]
@@ -51,74 +66,215 @@ pub enum Error {
     IoConnectionError(String),
     #[error("Failed to validate package {0} with key {1}: {2}")]
     FailedToValidateSignature(String, String, String),
+    #[error(transparent)]
+    PackageNotCached(#[from] NotCachedError),
+    #[error("Could not open the local package cache. {0}")]
+    PackageCacheError(String),
+    #[error("The archive of package \"{0}\" does not match the integrity hash in wapm.lock, expected {1} but got {2}.")]
+    IntegrityMismatch(String, String, String),
+    #[error(
+        "The cached archive of package \"{0}\" has been changed. Remove {1} to download it again."
+    )]
+    CorruptCachedArchive(String, String),
+    #[error("The package \"{0}\" is locked with the key {1}, but the key {2} is trusted for its publisher. Run `wapm install` to review the key.")]
+    LockedKeyMismatch(String, String, String),
+    #[error("{} packages failed to install:\n{}", .0.len(), list_errors(.0))]
+    MultipleInstallErrors(Vec<Error>),
+}
+
+impl Classify for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::InstalledDependencyIsMissingManifest(..) => ErrorKind::Manifest,
+            Error::FailedToParsePackageName(..) => ErrorKind::Dependency,
+            Error::DecompressionError(..)
+            | Error::IoErrorCreatingDirectory(..)
+            | Error::IoCopyError(..)
+            | Error::PackageCacheError(_) => ErrorKind::Io,
+            Error::DownloadError(..) | Error::IoConnectionError(_) | Error::PackageNotCached(_) => {
+                ErrorKind::Registry
+            }
+            Error::InstallAborted(_)
+            | Error::KeyManagementError(..)
+            | Error::FailedToValidateSignature(..)
+            | Error::IntegrityMismatch(..)
+            | Error::CorruptCachedArchive(..)
+            | Error::LockedKeyMismatch(..) => ErrorKind::Verification,
+            Error::MultipleInstallErrors(errors) => {
+                ErrorKind::combine(errors.iter().map(Classify::kind))
+            }
+        }
+    }
 }
 
-/// A structure containing installed packages. Currently contains the key, the deserialized
-/// manifest, and the download url.
+fn list_errors(errors: &[Error]) -> String {
+    let messages: Vec<String> = errors.iter().map(|e| format!("  - {}", e)).collect();
+    messages.join("\n")
+}
+
+/// The key, the deserialized manifest, the download url, the integrity hash of the package archive
+/// and the signature it was verified with.
+pub type InstalledPackage<'a> = (
+    WapmPackageKey<'a>,
+    Manifest,
+    String,
+    Option<String>,
+    Option<PackageSignature>,
+);
+
+/// A structure containing installed packages.
 #[derive(Clone, Debug)]
 pub struct InstalledPackages<'a> {
-    pub packages: Vec<(WapmPackageKey<'a>, Manifest, String)>,
+    pub packages: Vec<InstalledPackage<'a>>,
+}
+
+/// How the key of a package signature is checked before the package is installed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyCheck {
+    /// Ask the user whether to trust a key that is new or changed
+    Prompt,
+    /// Trust the key recorded in `wapm.lock` without asking, unless another key is trusted for
+    /// the publisher, for installs that must not prompt
+    Locked,
+    /// Install without checking keys or signatures
+    Insecure,
 }
 
 impl<'a> InstalledPackages<'a> {
-    /// Will install the resolved manifest packages into the specified directory.
+    /// Will install the resolved manifest packages into the specified directory. Packages that are
+    /// locked with an integrity hash must match it, packages without a signature from the registry
+    /// are verified with the signature in the lockfile, and packages are cached apart for each of
+    /// the `registries`. Up to `WAPM_INSTALL_JOBS` packages are downloaded and unpacked at the same
+    /// time. Every package is attempted, and failures are reported in the order of the package
+    /// names.
     pub fn install<Installer: Install<'a>>(
         directory: &Path,
         resolve_packages: ResolvedPackages<'a>,
-        force_insecure_install: bool,
+        lockfile_packages: &LockfilePackages<'a>,
+        registries: &PackageRegistries,
+        key_check: KeyCheck,
     ) -> Result<Self, Error> {
-        let packages_result: Result<Vec<(WapmPackageKey, PathBuf, String)>, Error> =
-            resolve_packages
-                .packages
-                .into_iter()
-                .map(|(key, (download_url, signature))| {
-                    info!("Installing {}@{}", key.name, key.version);
-                    Installer::install_package(
-                        &directory,
-                        key,
-                        download_url.as_str(),
-                        #[cfg(feature = "full")]
-                        signature,
-                        force_insecure_install,
-                    )
-                })
-                .collect();
-        let packages_result: Result<Vec<(WapmPackageKey, Manifest, String)>, Error> =
-            packages_result?
-                .into_iter()
-                .map(|(key, dir, download_url)| {
-                    let manifest = match ManifestResult::find_in_directory(&dir) {
-                        ManifestResult::ManifestError(e) => {
-                            return Err(Error::InstalledDependencyIsMissingManifest(
-                                key.clone().to_string(),
-                                e.to_string(),
-                            ));
-                        }
-                        ManifestResult::Manifest(m) => m,
-                        ManifestResult::NoManifest => {
-                            return Err(Error::InstalledDependencyIsMissingManifest(
-                                key.clone().to_string(),
-                                "Manifest was not found.".to_string(),
-                            ));
-                        }
-                    };
-                    Ok((key.clone(), manifest, download_url))
-                })
-                .collect();
-        let packages = packages_result?;
-        Ok(Self { packages })
+        let mut resolved_packages = resolve_packages.packages;
+        resolved_packages.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let total = resolved_packages.len();
+        let jobs = Mutex::new(resolved_packages.into_iter().enumerate());
+        let results = Mutex::new(Vec::with_capacity(total));
+        let finished = AtomicUsize::new(0);
+
+        let worker = || loop {
+            let next_job = jobs.lock().unwrap().next();
+            let (index, (key, (download_url, signature))) = match next_job {
+                Some(job) => job,
+                None => break,
+            };
+            info!("Installing {}@{}", key.name, key.version);
+            let expected_integrity = lockfile_packages.integrity(&key);
+            let signature = signature
+                .map(PackageSignature::from)
+                .or_else(|| lockfile_packages.signature(&key).cloned());
+            let registry = registries.registry(&key.name);
+            let result = Installer::install_package(
+                &directory,
+                key,
+                download_url.as_str(),
+                registry,
+                expected_integrity,
+                signature,
+                key_check,
+            )
+            .and_then(|(key, dir, download_url, integrity, signature)| {
+                let manifest = find_installed_manifest(&key, &dir)?;
+                Ok((key, manifest, download_url, integrity, signature))
+            });
+            if let Ok((ref key, ..)) = result {
+                let finished = finished.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+                info!(
+                    "[{}/{}] Installed {}@{}",
+                    finished, total, key.name, key.version
+                );
+            }
+            results.lock().unwrap().push((index, result));
+        };
+        let workers = install_jobs().min(total);
+        if workers > 1 {
+            thread::scope(|scope| {
+                for _ in 0..workers {
+                    scope.spawn(&worker);
+                }
+            });
+        } else {
+            worker();
+        }
+
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(index, _)| *index);
+        let mut packages = vec![];
+        let mut errors = vec![];
+        for (_, result) in results {
+            match result {
+                Ok(package) => packages.push(package),
+                Err(e) => errors.push(e),
+            }
+        }
+        match errors.len() {
+            0 => Ok(Self { packages }),
+            1 => Err(errors.remove(0)),
+            _ => Err(Error::MultipleInstallErrors(errors)),
+        }
+    }
+}
+
+/// The number of packages to install at the same time, `WAPM_INSTALL_JOBS` or a default.
+fn install_jobs() -> usize {
+    if cfg!(target_os = "wasi") {
+        return 1;
     }
+    env::var(WAPM_INSTALL_JOBS_ENV_KEY)
+        .ok()
+        .and_then(|jobs| jobs.parse::<usize>().ok())
+        .filter(|jobs| *jobs > 0)
+        .unwrap_or(DEFAULT_INSTALL_JOBS)
 }
 
+fn find_installed_manifest(key: &WapmPackageKey, directory: &Path) -> Result<Manifest, Error> {
+    match ManifestResult::find_in_directory(directory) {
+        ManifestResult::Manifest(manifest) => Ok(manifest),
+        ManifestResult::ManifestError(e) => Err(Error::InstalledDependencyIsMissingManifest(
+            key.to_string(),
+            e.to_string(),
+        )),
+        ManifestResult::NoManifest => Err(Error::InstalledDependencyIsMissingManifest(
+            key.to_string(),
+            "Manifest was not found.".to_string(),
+        )),
+    }
+}
+
+/// The key, directory, download url, integrity hash and signature of a package unpacked by an
+/// installer.
+pub type UnpackedPackage<'a> = (
+    WapmPackageKey<'a>,
+    PathBuf,
+    String,
+    Option<String>,
+    Option<PackageSignature>,
+);
+
 /// A trait for injecting an installer for installing wapm packages.
 pub trait Install<'a> {
+    /// Install a package from the named registry, `None` for the default registry, and return its
+    /// directory, download url, the integrity hash of its archive and the signature it was
+    /// verified with. A package with an `expected_integrity` must fail to install if its archive
+    /// differs.
     fn install_package(
         directory: &Path,
         key: WapmPackageKey<'a>,
         download_url: &str,
-        #[cfg(feature = "full")] signature: Option<keys::WapmPackageSignature>,
-        force_insecure_install: bool,
-    ) -> Result<(WapmPackageKey<'a>, PathBuf, String), Error>;
+        registry: Option<&str>,
+        expected_integrity: Option<&str>,
+        signature: Option<PackageSignature>,
+        key_check: KeyCheck,
+    ) -> Result<UnpackedPackage<'a>, Error>;
 }
 
 pub struct RegistryInstaller;
@@ -136,30 +292,361 @@ impl RegistryInstaller {
         archive.unpack(&pkg_name)?;
         Ok(())
     }
+
+    fn download_archive(
+        key: &WapmPackageKey,
+        download_url: &str,
+        dest: &mut fs::File,
+    ) -> Result<(), Error> {
+        let client = {
+            let builder = ClientBuilder::new().gzip(false);
+            #[cfg(target_os = "wasi")]
+            let builder = builder.cors_proxy("tokera.sh");
+            #[cfg(not(target_os = "wasi"))]
+            let builder = if let Some(proxy) = proxy::maybe_set_up_proxy()
+                .map_err(|e| Error::IoConnectionError(format!("{}", e)))?
+            {
+                builder.proxy(proxy)
+            } else {
+                builder
+            };
+
+            builder.build().unwrap()
+        };
+        let user_agent = format!(
+            "wapm/{} {} {}",
+            VERSION,
+            whoami::platform(),
+            whoami_distro(),
+        );
+        let mut response = client
+            .get(download_url)
+            .header(header::USER_AGENT, user_agent)
+            .send()
+            .map_err(|e| {
+                let error_message = e.to_string();
+                #[cfg(feature = "telemetry")]
+                {
+                    let e = e.into();
+                    sentry::integrations::anyhow::capture_anyhow(&e);
+                }
+                Error::DownloadError(key.to_string(), error_message)
+            })?;
+        io::copy(&mut response, dest)
+            .map_err(|e| Error::DownloadError(key.to_string(), e.to_string()))?;
+        Ok(())
+    }
+
+    /// Install a package from the package cache, or from the archive written by `download` when
+    /// it is not cached. Either way the archive must match the integrity in the lockfile and, if
+    /// there is one, the signature.
+    fn install_package_with<'a, F>(
+        cache: Option<&PackageCache>,
+        directory: &Path,
+        key: WapmPackageKey<'a>,
+        download_url: &str,
+        expected_integrity: Option<&str>,
+        signature: Option<PackageSignature>,
+        download: F,
+    ) -> Result<UnpackedPackage<'a>, Error>
+    where
+        F: FnOnce(&WapmPackageKey, &mut fs::File) -> Result<(), Error>,
+    {
+        let package_dir = installed_package_directory(directory, &key)?;
+        if let Some(cache) = cache {
+            if let Some(integrity) = install_from_cache(
+                cache,
+                &key,
+                &package_dir,
+                expected_integrity,
+                signature.as_ref(),
+            )? {
+                let download_url = download_url.to_string();
+                return Ok((key, package_dir, download_url, Some(integrity), signature));
+            }
+        }
+
+        let temp_dir =
+            create_temp_dir().map_err(|e| Error::DownloadError(key.to_string(), e.to_string()))?;
+        fs::create_dir_all(temp_dir.join("wapm_package_install"))
+            .map_err(|e| Error::IoErrorCreatingDirectory(key.to_string(), e.to_string()))?;
+        let temp_tar_gz_path = temp_dir.join("wapm_package_install").join("package.tar.gz");
+        let mut dest = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp_tar_gz_path)
+            .map_err(|e| Error::IoCopyError(key.to_string(), e.to_string()))?;
+        download(&key, &mut dest)?;
+
+        // verify the exact bytes of the archive before anything is unpacked
+        let hash = archive_hash(&key, &mut dest)?;
+        let integrity = check_archive_integrity(&key, expected_integrity, &hash)?;
+        if let Some(ref signature) = signature {
+            verify_archive_signature(&key, signature, &mut dest)?;
+        }
+
+        if let Some(cache) = cache {
+            let entry = CacheEntry {
+                hash,
+                download_url: download_url.to_string(),
+                signature: signature.clone(),
+            };
+            if let Err(e) = cache.insert(&key, entry, &mut dest) {
+                warn!("Could not add {} to the local package cache: {}", key, e);
+            }
+        }
+        Self::decompress_and_extract_archive(dest, &package_dir, &key)
+            .map_err(|e| Error::DecompressionError(key.to_string(), e.to_string()))?;
+        let download_url = download_url.to_string();
+        Ok((key, package_dir, download_url, Some(integrity), signature))
+    }
+}
+
+/// Create the directory a package is installed into.
+fn installed_package_directory(directory: &Path, key: &WapmPackageKey) -> Result<PathBuf, Error> {
+    let (namespace, pkg_name) = get_package_namespace_and_name(&key.name)
+        .map_err(|e| Error::FailedToParsePackageName(key.to_string(), e.to_string()))?;
+    let fully_qualified_package_name: String =
+        fully_qualified_package_display_name(pkg_name, &key.version);
+    create_package_dir(directory, namespace, &fully_qualified_package_name)
+        .map_err(|err| Error::IoErrorCreatingDirectory(key.to_string(), err.to_string()))
+}
+
+/// The sha256 hash of a package archive.
+fn archive_hash(key: &WapmPackageKey, archive: &mut fs::File) -> Result<String, Error> {
+    archive
+        .seek(SeekFrom::Start(0))
+        .and_then(|_| content_hash(archive))
+        .map_err(|e| Error::IoCopyError(key.to_string(), e.to_string()))
+}
+
+/// Check the sha256 hash of a package archive against the integrity recorded in the lockfile, and
+/// return the integrity to record for it.
+fn check_archive_integrity(
+    key: &WapmPackageKey,
+    expected_integrity: Option<&str>,
+    hash: &str,
+) -> Result<String, Error> {
+    let integrity = format!("sha256-{}", hash);
+    match expected_integrity {
+        Some(expected_integrity) if expected_integrity != integrity => Err(
+            Error::IntegrityMismatch(key.to_string(), expected_integrity.to_string(), integrity),
+        ),
+        _ => Ok(integrity),
+    }
+}
+
+/// Unpack the cached archive of a package into the package directory, if there is one, and return
+/// its integrity. The cached archive is checked like a downloaded one.
+fn install_from_cache(
+    cache: &PackageCache,
+    key: &WapmPackageKey,
+    package_dir: &Path,
+    expected_integrity: Option<&str>,
+    signature: Option<&PackageSignature>,
+) -> Result<Option<String>, Error> {
+    let (entry, archive_path) = match cache.find(key) {
+        Some(cached) => cached,
+        None => return Ok(None),
+    };
+    let mut archive = fs::File::open(&archive_path)
+        .map_err(|e| Error::IoCopyError(key.to_string(), e.to_string()))?;
+    let hash = archive_hash(key, &mut archive)?;
+    if hash != entry.hash {
+        return Err(Error::CorruptCachedArchive(
+            key.to_string(),
+            archive_path.to_string_lossy().to_string(),
+        ));
+    }
+    let integrity = check_archive_integrity(key, expected_integrity, &hash)?;
+    if let Some(signature) = signature {
+        verify_archive_signature(key, signature, &mut archive)?;
+    }
+    info!("Using cached copy of {}", key);
+    RegistryInstaller::decompress_and_extract_archive(archive, package_dir, key)
+        .map_err(|e| Error::DecompressionError(key.to_string(), e.to_string()))?;
+    Ok(Some(integrity))
 }
 
-#[derive(Debug, Clone)]
-struct PackageSignatureVerificationData {
-    insecure_install: bool,
-    key_to_verify_package_with: Option<(String, String)>,
-    signature_to_use: Option<String>,
+/// Installs packages from the local package cache only, without connecting to the registry.
+pub struct CacheInstaller;
+
+impl<'a> Install<'a> for CacheInstaller {
+    fn install_package(
+        directory: &Path,
+        key: WapmPackageKey<'a>,
+        download_url: &str,
+        registry: Option<&str>,
+        expected_integrity: Option<&str>,
+        signature: Option<PackageSignature>,
+        key_check: KeyCheck,
+    ) -> Result<UnpackedPackage<'a>, Error> {
+        let cache =
+            PackageCache::open(registry).map_err(|e| Error::PackageCacheError(e.to_string()))?;
+        // without the registry, the archive is checked against the signature it was cached with
+        let (entry, _) = cache
+            .find(&key)
+            .ok_or_else(|| NotCachedError(key.to_string()))?;
+        let signature = trusted_signature(&key, entry.signature.or(signature), key_check)?;
+        let package_dir = installed_package_directory(directory, &key)?;
+        match install_from_cache(
+            &cache,
+            &key,
+            &package_dir,
+            expected_integrity,
+            signature.as_ref(),
+        )? {
+            Some(integrity) => {
+                let download_url = download_url.to_string();
+                Ok((key, package_dir, download_url, Some(integrity), signature))
+            }
+            None => Err(NotCachedError(key.to_string()).into()),
+        }
+    }
+}
+
+impl From<keys::WapmPackageSignature> for PackageSignature {
+    fn from(signature: keys::WapmPackageSignature) -> Self {
+        Self {
+            public_key_id: signature.public_key_id,
+            public_key: signature.public_key,
+            signature_data: signature.signature_data,
+            owner: signature.owner,
+        }
+    }
+}
+
+/// The signature to verify the archive of a package with, after checking that its key is trusted.
+/// `None` means that the package is installed without verifying a signature.
+fn trusted_signature(
+    key: &WapmPackageKey,
+    signature: Option<PackageSignature>,
+    key_check: KeyCheck,
+) -> Result<Option<PackageSignature>, Error> {
+    if key_check == KeyCheck::Insecure {
+        return Ok(None);
+    }
+    let (namespace, pkg_name) = get_package_namespace_and_name(&key.name)
+        .map_err(|e| Error::FailedToParsePackageName(key.to_string(), e.to_string()))?;
+    let fully_qualified_package_name = fully_qualified_package_display_name(pkg_name, &key.version);
+    #[cfg(feature = "full")]
+    let signature = verify_integrity_of_package(
+        namespace,
+        fully_qualified_package_name,
+        signature,
+        key_check,
+    )?;
+    #[cfg(not(feature = "full"))]
+    let signature = None;
+    Ok(signature)
 }
 
 #[cfg(feature = "full")]
 fn verify_integrity_of_package(
     namespace: &str,
     fully_qualified_package_name: String,
-    #[cfg(feature = "full")] signature: Option<keys::WapmPackageSignature>,
-) -> Result<PackageSignatureVerificationData, Error> {
-    let mut keys_db = database::open_db().map_err(|e| {
+    signature: Option<PackageSignature>,
+    key_check: KeyCheck,
+) -> Result<Option<PackageSignature>, Error> {
+    if key_check == KeyCheck::Locked {
+        return check_locked_package_key(
+            database::open_db,
+            namespace,
+            fully_qualified_package_name,
+            signature,
+        );
+    }
+    check_package_key(
+        database::open_db,
+        util::prompt_user_for_yes,
+        namespace,
+        fully_qualified_package_name,
+        signature,
+    )
+}
+
+/// Check the key of a signature recorded in `wapm.lock` without asking the user. The recorded key
+/// must be the latest key trusted for the publisher, if one is trusted at all, and it is not
+/// imported. A package locked without a signature is installed without verification, its archive
+/// is still checked against the integrity in the lockfile.
+#[cfg(feature = "full")]
+fn check_locked_package_key<D>(
+    open_db: D,
+    namespace: &str,
+    fully_qualified_package_name: String,
+    signature: Option<PackageSignature>,
+) -> Result<Option<PackageSignature>, Error>
+where
+    D: Fn() -> anyhow::Result<Connection>,
+{
+    let keys_db = open_db().map_err(|e| {
+        Error::KeyManagementError(fully_qualified_package_name.clone(), e.to_string())
+    })?;
+    let owner = signature
+        .as_ref()
+        .map(|signature| signature.owner.clone())
+        .unwrap_or_else(|| namespace.to_string());
+    let latest_public_key =
+        keys::get_latest_public_key_for_user(&keys_db, &owner).map_err(|e| {
+            Error::KeyManagementError(fully_qualified_package_name.clone(), e.to_string())
+        })?;
+    match (signature, latest_public_key) {
+        (Some(signature), Some(latest_local_key))
+            if signature.public_key_id != latest_local_key.public_key_id
+                || signature.public_key != latest_local_key.public_key_value =>
+        {
+            Err(Error::LockedKeyMismatch(
+                fully_qualified_package_name,
+                signature.public_key_id,
+                latest_local_key.public_key_id,
+            ))
+        }
+        (Some(signature), _) => Ok(Some(signature)),
+        (None, Some(latest_local_key)) => {
+            warn!(
+                "The package {} is locked without a signature but a public key for {} is known locally ({}), installing it without verifying a signature.",
+                &fully_qualified_package_name, owner, &latest_local_key.public_key_id
+            );
+            Ok(None)
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+#[cfg(feature = "full")]
+lazy_static! {
+    /// Held while the key of a package is checked, so that packages installed at the same time by
+    /// the same publisher ask to trust a new key only once and import it only once.
+    static ref KEY_CHECK_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Check the key of a package signature against the keys trusted locally, asking the user whether
+/// to trust a new or changed key. Returns the signature to verify the package with, `None` when the
+/// package is installed without verification.
+#[cfg(feature = "full")]
+fn check_package_key<D, P>(
+    open_db: D,
+    prompt: P,
+    namespace: &str,
+    fully_qualified_package_name: String,
+    signature: Option<PackageSignature>,
+) -> Result<Option<PackageSignature>, Error>
+where
+    D: Fn() -> anyhow::Result<Connection>,
+    P: Fn(&str) -> anyhow::Result<bool>,
+{
+    let _key_check_guard = KEY_CHECK_LOCK.lock().unwrap();
+    let mut keys_db = open_db().map_err(|e| {
         Error::KeyManagementError(fully_qualified_package_name.clone(), e.to_string())
     })?;
     // get the latest key for the given namespace first. If the server claims that the owner
     // is someone else, then we'll search the local database for a key from that user
     // this is required for how globally namespaced packages work and also allows transfer
     // of ownership
-    let mut latest_public_key = keys::get_latest_public_key_for_user(&keys_db, &namespace)
-        .map_err(|e| {
+    let latest_public_key =
+        keys::get_latest_public_key_for_user(&keys_db, namespace).map_err(|e| {
             Error::KeyManagementError(fully_qualified_package_name.clone(), e.to_string())
         })?;
 
@@ -172,22 +659,17 @@ fn verify_integrity_of_package(
         })
     };
 
-    let mut insecure_install = false;
-    let mut key_to_verify_package_with = None;
-    let mut signature_to_use = None;
-
-    if let Some(keys::WapmPackageSignature {
-        public_key_id,
-        public_key,
-        signature_data,
-        owner,
-        ..
-    }) = signature
-    {
+    if let Some(signature) = signature {
+        let PackageSignature {
+            public_key_id,
+            public_key,
+            owner,
+            ..
+        } = &signature;
         // Cases 1-X:
         // get key for owner as identified by the server
-        latest_public_key = if owner != namespace {
-            keys::get_latest_public_key_for_user(&keys_db, &owner).map_err(|e| {
+        let latest_public_key = if owner != namespace {
+            keys::get_latest_public_key_for_user(&keys_db, owner).map_err(|e| {
                 Error::KeyManagementError(fully_qualified_package_name.clone(), e.to_string())
             })?
         } else {
@@ -201,29 +683,21 @@ fn verify_integrity_of_package(
         if let Some(latest_local_key) = latest_public_key {
             // Case 1-1: server has key and client has key
             // verify or prompt and store
-            if public_key_id == latest_local_key.public_key_id
-                && public_key == latest_local_key.public_key_value
+            if *public_key_id == latest_local_key.public_key_id
+                && *public_key == latest_local_key.public_key_value
             {
                 // keys match
                 trace!("Public key from server matches latest key locally");
-                key_to_verify_package_with = Some((
-                    latest_local_key.public_key_id,
-                    latest_local_key.public_key_value,
-                ));
-
-                signature_to_use = Some(signature_data);
             } else {
                 // mismatch, prompt user
                 let user_trusts_new_key =
-                        util::prompt_user_for_yes(&format!(
+                        prompt(&format!(
                             "The keys {:?} and {:?} do not match. Do you want to trust the new key ({:?} {:?})?",
-                            &latest_local_key.public_key_id, &public_key_id, &public_key_id, &public_key
+                            &latest_local_key.public_key_id, public_key_id, public_key_id, public_key
                         )).expect("Could not read input from user");
 
                 if user_trusts_new_key {
-                    import_public_key(&mut keys_db, &public_key_id, &public_key, owner)?;
-                    key_to_verify_package_with = Some((public_key_id, public_key));
-                    signature_to_use = Some(signature_data);
+                    import_public_key(&mut keys_db, public_key_id, public_key, owner.clone())?;
                 } else {
                     return Err(Error::InstallAborted(format!(
                         "Mismatching key on package {} was not trusted by user",
@@ -234,16 +708,14 @@ fn verify_integrity_of_package(
         } else {
             // Case 1-0: server has key and client does not have key
             // prompt and store
-            let user_trusts_new_key = util::prompt_user_for_yes(&format!(
+            let user_trusts_new_key = prompt(&format!(
                 "New public key encountered for user {}: {} {} while installing {}.
 Would you like to trust this key?",
-                &owner, &public_key_id, &public_key, &fully_qualified_package_name
+                owner, public_key_id, public_key, &fully_qualified_package_name
             ))
             .expect("Could not read input from user");
             if user_trusts_new_key {
-                import_public_key(&mut keys_db, &public_key_id, &public_key, owner)?;
-                key_to_verify_package_with = Some((public_key_id, public_key));
-                signature_to_use = Some(signature_data);
+                import_public_key(&mut keys_db, public_key_id, public_key, owner.clone())?;
             } else {
                 return Err(Error::InstallAborted(format!(
                     "User did not trust key from registry for package {}",
@@ -251,6 +723,7 @@ Would you like to trust this key?",
                 )));
             }
         }
+        Ok(Some(signature))
     } else {
         // Cases 0-X:
         // server does not have key
@@ -262,30 +735,21 @@ Would you like to trust this key?",
                     &namespace, &fully_qualified_package_name, &namespace, &latest_local_key.public_key_id
                 );
 
-            let user_wants_to_do_insecure_install = util::prompt_user_for_yes(
-                "Would you like to proceed with an unverified installation?",
-            )
-            .expect("Could not read input from user");
+            let user_wants_to_do_insecure_install =
+                prompt("Would you like to proceed with an unverified installation?")
+                    .expect("Could not read input from user");
 
-            if user_wants_to_do_insecure_install {
-                insecure_install = true;
-            } else {
+            if !user_wants_to_do_insecure_install {
                 return Err(Error::InstallAborted(format!(
                     "User did not trust unsigned package {}",
                     &fully_qualified_package_name
                 )));
             }
-        } else {
-            // Case 0-0: server does not have key and client does not have key
-            // silently proceed to insecure install for now
-            insecure_install = true;
         }
+        // Case 0-0: server does not have key and client does not have key
+        // silently proceed to insecure install for now
+        Ok(None)
     }
-    Ok(PackageSignatureVerificationData {
-        insecure_install,
-        key_to_verify_package_with,
-        signature_to_use,
-    })
 }
 
 /// This impl will install packages from a wapm registry.
@@ -294,122 +758,48 @@ impl<'a> Install<'a> for RegistryInstaller {
         directory: &Path,
         key: WapmPackageKey<'a>,
         download_url: &str,
-        #[cfg(feature = "full")] signature: Option<keys::WapmPackageSignature>,
-        force_insecure_install: bool,
-    ) -> Result<(WapmPackageKey<'a>, PathBuf, String), Error> {
-        let (namespace, pkg_name) = get_package_namespace_and_name(&key.name)
-            .map_err(|e| Error::FailedToParsePackageName(key.to_string(), e.to_string()))?;
-        let fully_qualified_package_name: String =
-            fully_qualified_package_display_name(pkg_name, &key.version);
-        let package_dir = create_package_dir(&directory, namespace, &fully_qualified_package_name)
-            .map_err(|err| Error::IoErrorCreatingDirectory(key.to_string(), err.to_string()))?;
-        let client = {
-            let builder = ClientBuilder::new().gzip(false);
-            #[cfg(target_os = "wasi")]
-            let builder = builder.cors_proxy("tokera.sh");
-            #[cfg(not(target_os = "wasi"))]
-            let builder = if let Some(proxy) = proxy::maybe_set_up_proxy()
-                .map_err(|e| Error::IoConnectionError(format!("{}", e)))?
-            {
-                builder.proxy(proxy)
-            } else {
-                builder
-            };
+        registry: Option<&str>,
+        expected_integrity: Option<&str>,
+        signature: Option<PackageSignature>,
+        key_check: KeyCheck,
+    ) -> Result<UnpackedPackage<'a>, Error> {
+        // the key is checked before the package is installed, whether it is cached or not
+        let signature = trusted_signature(&key, signature, key_check)?;
 
-            builder.build().unwrap()
-        };
-        let user_agent = format!(
-            "wapm/{} {} {}",
-            VERSION,
-            whoami::platform(),
-            whoami_distro(),
-        );
-        let mut response = client
-            .get(download_url)
-            .header(header::USER_AGENT, user_agent)
-            .send()
-            .map_err(|e| {
-                let error_message = e.to_string();
-                #[cfg(feature = "telemetry")]
-                {
-                    let e = e.into();
-                    sentry::integrations::anyhow::capture_anyhow(&e);
-                }
-                Error::DownloadError(key.to_string(), error_message)
-            })?;
-
-        // step to perform after package is decompressed: may be a no-op or may
-        // execute side effects such as logging to the user.
-        #[cfg(feature = "full")]
-        let mut key_sign_end_step: Box<dyn FnMut(&mut fs::File) -> Result<(), Error>> =
-            if !force_insecure_install {
-                let PackageSignatureVerificationData {
-                    insecure_install,
-                    key_to_verify_package_with,
-                    signature_to_use,
-                } = verify_integrity_of_package(
-                    namespace,
-                    fully_qualified_package_name.clone(),
-                    signature,
-                )?;
-
-                if insecure_install {
-                    Box::new(|_dest| Ok(()))
-                } else {
-                    Box::new(move |mut dest| {
-                        let (pk_id, pkv) = key_to_verify_package_with
-                            .clone()
-                            .expect("Critical internal logic error");
-                        let signature_to_use = signature_to_use
-                            .clone()
-                            .expect("Critical internal logic error");
-                        verify_signature_on_package(&pkv, &signature_to_use, &mut dest).map_err(
-                            |e| {
-                                Error::FailedToValidateSignature(
-                                    fully_qualified_package_name.clone(),
-                                    pk_id,
-                                    e.to_string(),
-                                )
-                            },
-                        )?;
-                        info!(
-                            "Signature of package {} verified!",
-                            &fully_qualified_package_name
-                        );
-                        Ok(())
-                    })
-                }
-            } else {
-                Box::new(|_dest| Ok(()))
-            };
-
-        #[cfg(not(feature = "full"))]
-        let mut key_sign_end_step: Box<dyn FnMut(&mut fs::File) -> Result<(), Error>> =
-            Box::new(|_dest| Ok(()));
-
-        let temp_dir =
-            create_temp_dir().map_err(|e| Error::DownloadError(key.to_string(), e.to_string()))?;
-        fs::create_dir(temp_dir.join("wapm_package_install"))
-            .map_err(|e| Error::IoErrorCreatingDirectory(key.to_string(), e.to_string()))?;
-        let temp_tar_gz_path = temp_dir.join("wapm_package_install").join("package.tar.gz");
-        let mut dest = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&temp_tar_gz_path)
-            .map_err(|e| Error::IoCopyError(key.to_string(), e.to_string()))?;
-
-        io::copy(&mut response, &mut dest)
-            .map_err(|e| Error::DownloadError(key.to_string(), e.to_string()))?;
-
-        key_sign_end_step(&mut dest)?;
-
-        Self::decompress_and_extract_archive(dest, &package_dir, &key)
-            .map_err(|e| Error::DecompressionError(key.to_string(), e.to_string()))?;
-        Ok((key, package_dir, download_url.to_string()))
+        // the cache is only a shortcut, installing still works when it cannot be used
+        let cache = PackageCache::open(registry)
+            .map_err(|e| warn!("Could not open the local package cache: {}", e))
+            .ok();
+        Self::install_package_with(
+            cache.as_ref(),
+            directory,
+            key,
+            download_url,
+            expected_integrity,
+            signature,
+            |key, dest| Self::download_archive(key, download_url, dest),
+        )
     }
 }
 
+/// Verify the signature of a package archive.
+fn verify_archive_signature(
+    key: &WapmPackageKey,
+    signature: &PackageSignature,
+    archive: &mut fs::File,
+) -> Result<(), Error> {
+    verify_signature_on_package(&signature.public_key, &signature.signature_data, archive)
+        .map_err(|e| {
+            Error::FailedToValidateSignature(
+                key.to_string(),
+                signature.public_key_id.clone(),
+                e.to_string(),
+            )
+        })?;
+    info!("Signature of package {} verified!", key);
+    Ok(())
+}
+
 /// Verifies the signature of a downloaded package archive
 fn verify_signature_on_package(
     pkv: &str,
@@ -426,3 +816,372 @@ fn verify_signature_on_package(
     minisign::verify(&public_key, &sig_box, dest, true, false)
         .map_err(|e| anyhow!("Could not validate signature: {}", e.to_string()))
 }
+
+#[cfg(test)]
+mod test {
+    use crate::data::manifest::{MANIFEST_FILE_NAME, PACKAGES_DIR_NAME};
+    use crate::dataflow::installed_packages::{
+        install_from_cache, Error, Install, InstalledPackages, KeyCheck, RegistryInstaller,
+        UnpackedPackage,
+    };
+    use crate::dataflow::lockfile_packages::LockfilePackages;
+    use crate::dataflow::package_cache::{
+        content_hash, CacheEntry, PackageCache, PackageSignature,
+    };
+    use crate::dataflow::resolved_packages::{PackageRegistries, ResolvedPackages};
+    use crate::dataflow::WapmPackageKey;
+    use crate::exit_code::{Classify, ErrorKind};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::fs;
+    use std::io::{self, Seek, SeekFrom, Write};
+    use std::path::Path;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Fails to download packages whose name starts with "_/bad", the others finish in reverse
+    /// order of their names.
+    struct TestInstaller;
+
+    impl<'a> Install<'a> for TestInstaller {
+        fn install_package(
+            directory: &Path,
+            key: WapmPackageKey<'a>,
+            download_url: &str,
+            _registry: Option<&str>,
+            _expected_integrity: Option<&str>,
+            _signature: Option<PackageSignature>,
+            _key_check: KeyCheck,
+        ) -> Result<UnpackedPackage<'a>, Error> {
+            let delay = 10 * (b'z' - key.name.as_bytes()[2]) as u64;
+            thread::sleep(Duration::from_millis(delay));
+            if key.name.starts_with("_/bad") {
+                return Err(Error::DownloadError(
+                    key.to_string(),
+                    "connection reset".to_string(),
+                ));
+            }
+            let package_dir = directory
+                .join(PACKAGES_DIR_NAME)
+                .join(format!("{}@{}", key.name, key.version));
+            fs::create_dir_all(&package_dir).unwrap();
+            let manifest = format!(
+                "[package]\nname = \"{}\"\nversion = \"{}\"\ndescription = \"test package\"\n",
+                key.name, key.version
+            );
+            fs::write(package_dir.join(MANIFEST_FILE_NAME), manifest).unwrap();
+            Ok((key, package_dir, download_url.to_string(), None, None))
+        }
+    }
+
+    fn resolved_packages(names: &[&str]) -> ResolvedPackages<'static> {
+        let packages = names
+            .iter()
+            .map(|name| {
+                let key = WapmPackageKey {
+                    name: name.to_string().into(),
+                    version: semver::Version::new(1, 0, 0),
+                };
+                (key, ("url".to_string(), None))
+            })
+            .collect();
+        ResolvedPackages { packages }
+    }
+
+    #[test]
+    fn install_packages_concurrently_in_a_stable_order() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let installed_packages = InstalledPackages::install::<TestInstaller>(
+            tmp_dir.path(),
+            resolved_packages(&["_/c", "_/a", "_/d", "_/b"]),
+            &LockfilePackages::default(),
+            &PackageRegistries::default(),
+            KeyCheck::Prompt,
+        )
+        .unwrap();
+        let names: Vec<&str> = installed_packages
+            .packages
+            .iter()
+            .map(|(key, ..)| key.name.as_ref())
+            .collect();
+        assert_eq!(vec!["_/a", "_/b", "_/c", "_/d"], names);
+    }
+
+    #[test]
+    fn report_every_failed_package() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let result = InstalledPackages::install::<TestInstaller>(
+            tmp_dir.path(),
+            resolved_packages(&["_/bad-two", "_/a", "_/bad-one"]),
+            &LockfilePackages::default(),
+            &PackageRegistries::default(),
+            KeyCheck::Prompt,
+        );
+        match result {
+            Err(error @ Error::MultipleInstallErrors(_)) => {
+                let messages = error.to_string();
+                assert!(messages.starts_with("2 packages failed"), "{}", messages);
+                // the kind of the failures is kept
+                assert_eq!(ErrorKind::Registry, error.kind());
+                let bad_one = messages.find("_/bad-one").unwrap();
+                let bad_two = messages.find("_/bad-two").unwrap();
+                assert!(bad_one < bad_two, "{}", messages);
+            }
+            _ => panic!("Expected both failed packages to be reported."),
+        }
+        // the other packages are still installed
+        assert!(tmp_dir
+            .path()
+            .join(PACKAGES_DIR_NAME)
+            .join("_/a@1.0.0")
+            .is_dir());
+    }
+
+    /// A package archive holding a single `foo.wasm`, and its hash.
+    fn package_archive() -> (fs::File, String) {
+        let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "foo.wasm", &b"\0asm"[..])
+            .unwrap();
+        let bytes = builder.into_inner().unwrap().finish().unwrap();
+        let mut archive = tempfile::tempfile().unwrap();
+        archive.write_all(&bytes).unwrap();
+        (archive, content_hash(&mut &bytes[..]).unwrap())
+    }
+
+    fn cached_package(cache: &PackageCache, key: &WapmPackageKey) -> String {
+        let (mut archive, hash) = package_archive();
+        let entry = CacheEntry {
+            hash: hash.clone(),
+            download_url: "https://example.com/foo-1.0.0.tar.gz".to_string(),
+            signature: None,
+        };
+        cache.insert(key, entry, &mut archive).unwrap();
+        hash
+    }
+
+    fn foo_key() -> WapmPackageKey<'static> {
+        WapmPackageKey {
+            name: "_/foo".into(),
+            version: semver::Version::new(1, 0, 0),
+        }
+    }
+
+    #[test]
+    fn cached_package_must_match_locked_integrity() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let cache = PackageCache::new(tmp_dir.path().join("cache"));
+        let key = foo_key();
+        let integrity = format!("sha256-{}", cached_package(&cache, &key));
+        let package_dir = tmp_dir.path().join("project");
+
+        let installed_integrity =
+            install_from_cache(&cache, &key, &package_dir, Some(&integrity), None).unwrap();
+        assert_eq!(Some(integrity.clone()), installed_integrity);
+        // the installed files are not shared with the cache
+        fs::write(package_dir.join("foo.wasm"), b"changed").unwrap();
+        let other_package_dir = tmp_dir.path().join("other-project");
+        install_from_cache(&cache, &key, &other_package_dir, None, None).unwrap();
+        assert_eq!(
+            b"\0asm".to_vec(),
+            fs::read(other_package_dir.join("foo.wasm")).unwrap()
+        );
+
+        match install_from_cache(&cache, &key, &package_dir, Some("sha256-def"), None) {
+            Err(Error::IntegrityMismatch(_, expected, actual)) => {
+                assert_eq!("sha256-def", expected);
+                assert_eq!(integrity, actual);
+            }
+            _ => panic!("Expected the integrity check to fail."),
+        }
+    }
+
+    #[test]
+    fn cached_package_must_match_its_signature() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let cache = PackageCache::new(tmp_dir.path().join("cache"));
+        let key = foo_key();
+        cached_package(&cache, &key);
+        let package_dir = tmp_dir.path().join("project");
+        let signature = PackageSignature {
+            public_key_id: "key".to_string(),
+            public_key: "public key".to_string(),
+            signature_data: "not a signature".to_string(),
+            owner: "foo".to_string(),
+        };
+
+        match install_from_cache(&cache, &key, &package_dir, None, Some(&signature)) {
+            Err(Error::FailedToValidateSignature(_, public_key_id, _)) => {
+                assert_eq!("key", public_key_id)
+            }
+            _ => panic!("Expected the signature check to fail."),
+        }
+        assert!(!package_dir.join("foo.wasm").exists());
+    }
+
+    /// Install `_/foo` with the registry installer, "downloading" the given archive.
+    fn install_foo(
+        cache: &PackageCache,
+        directory: &Path,
+        expected_integrity: Option<&str>,
+        archive: &mut fs::File,
+    ) -> Result<Option<String>, Error> {
+        RegistryInstaller::install_package_with(
+            Some(cache),
+            directory,
+            foo_key(),
+            "https://example.com/foo-1.0.0.tar.gz",
+            expected_integrity,
+            None,
+            |_, dest| {
+                archive.seek(SeekFrom::Start(0)).unwrap();
+                io::copy(archive, dest).unwrap();
+                Ok(())
+            },
+        )
+        .map(|(_, _, _, integrity, _)| integrity)
+    }
+
+    #[test]
+    fn mismatched_archives_are_rejected_when_installing() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let cache = PackageCache::new(tmp_dir.path().join("cache"));
+        let project = tmp_dir.path().join("project");
+        let package_dir = project.join(PACKAGES_DIR_NAME).join("_/foo@1.0.0");
+        let (mut archive, hash) = package_archive();
+        let integrity = format!("sha256-{}", hash);
+
+        // a downloaded archive that differs from wapm.lock is neither cached nor unpacked
+        match install_foo(&cache, &project, Some("sha256-def"), &mut archive) {
+            Err(Error::IntegrityMismatch(..)) => (),
+            other => panic!("Expected the integrity check to fail, got {:?}", other),
+        }
+        assert!(cache.find(&foo_key()).is_none());
+        assert!(!package_dir.join("foo.wasm").exists());
+
+        let installed = install_foo(&cache, &project, Some(&integrity), &mut archive).unwrap();
+        assert_eq!(Some(integrity.clone()), installed);
+        assert!(package_dir.join("foo.wasm").is_file());
+        fs::remove_dir_all(&package_dir).unwrap();
+
+        // a cached archive that differs from wapm.lock is rejected instead of downloaded again
+        let (_, archive_path) = cache.find(&foo_key()).unwrap();
+        match install_foo(&cache, &project, Some("sha256-def"), &mut archive) {
+            Err(Error::IntegrityMismatch(..)) => (),
+            other => panic!("Expected the integrity check to fail, got {:?}", other),
+        }
+
+        // so is a cached archive that was changed after it was cached
+        fs::write(&archive_path, b"tampered").unwrap();
+        match install_foo(&cache, &project, Some(&integrity), &mut archive) {
+            Err(Error::CorruptCachedArchive(..)) => (),
+            other => panic!(
+                "Expected the cached archive to be rejected, got {:?}",
+                other
+            ),
+        }
+        assert!(!package_dir.join("foo.wasm").exists());
+    }
+
+    #[cfg(feature = "full")]
+    #[test]
+    fn new_key_of_a_namespace_is_trusted_once() {
+        use crate::database;
+        use crate::dataflow::installed_packages::check_package_key;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("wapm.sqlite");
+        let open_db = || {
+            let mut conn = rusqlite::Connection::open(&db_path)?;
+            database::apply_migrations(&mut conn)?;
+            Ok(conn)
+        };
+        let prompts = AtomicUsize::new(0);
+        let prompt = |_: &str| {
+            prompts.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            Ok(true)
+        };
+        let signature = PackageSignature {
+            public_key_id: "key".to_string(),
+            public_key: "public key".to_string(),
+            signature_data: "signature".to_string(),
+            owner: "ns".to_string(),
+        };
+
+        let results: Vec<_> = thread::scope(|scope| {
+            let checks: Vec<_> = ["a", "b"]
+                .iter()
+                .map(|name| {
+                    let signature = signature.clone();
+                    let (open_db, prompt) = (&open_db, &prompt);
+                    scope.spawn(move || {
+                        check_package_key(open_db, prompt, "ns", name.to_string(), Some(signature))
+                    })
+                })
+                .collect();
+            checks.into_iter().map(|c| c.join().unwrap()).collect()
+        });
+        for result in results {
+            assert_eq!(Some(signature.clone()), result.unwrap());
+        }
+        assert_eq!(1, prompts.load(Ordering::SeqCst));
+    }
+
+    #[cfg(feature = "full")]
+    #[test]
+    fn locked_keys_are_checked_without_prompting() {
+        use crate::database;
+        use crate::dataflow::installed_packages::check_locked_package_key;
+        use crate::keys;
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let db_path = tmp_dir.path().join("wapm.sqlite");
+        let open_db = || {
+            let mut conn = rusqlite::Connection::open(&db_path)?;
+            database::apply_migrations(&mut conn)?;
+            Ok(conn)
+        };
+        let signature = PackageSignature {
+            public_key_id: "key".to_string(),
+            public_key: "public key".to_string(),
+            signature_data: "signature".to_string(),
+            owner: "ns".to_string(),
+        };
+
+        // the locked key is used, but not trusted from then on
+        let checked =
+            check_locked_package_key(open_db, "ns", "a".to_string(), Some(signature.clone()));
+        assert_eq!(Some(signature.clone()), checked.unwrap());
+        let keys_db = open_db().unwrap();
+        assert!(keys::get_latest_public_key_for_user(&keys_db, "ns")
+            .unwrap()
+            .is_none());
+
+        let mut keys_db = open_db().unwrap();
+        keys::import_public_key(
+            &mut keys_db,
+            "other-key",
+            "other public key",
+            "ns".to_string(),
+        )
+        .unwrap();
+        match check_locked_package_key(open_db, "ns", "a".to_string(), Some(signature)) {
+            Err(Error::LockedKeyMismatch(_, locked_key, trusted_key)) => {
+                assert_eq!("key", locked_key);
+                assert_eq!("other-key", trusted_key);
+            }
+            other => panic!("Expected the locked key to be rejected, got {:?}", other),
+        }
+        // a package locked without a signature is installed unverified, without asking
+        assert_eq!(
+            None,
+            check_locked_package_key(open_db, "ns", "a".to_string(), None).unwrap()
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use crate::dataflow::changed_manifest_packages::ChangedManifestPackages;
+use crate::dataflow::lockfile_packages::LockfilePackages;
+use crate::dataflow::manifest_packages::ManifestPackages;
+use crate::dataflow::PackageKey;
+use std::fmt;
+
+/// The differences between the dependencies of a manifest and the packages of a lockfile, i.e.
+/// what a regular install would change in the lockfile.
+#[derive(Clone, Debug, Default)]
+pub struct LockfileDrift<'a> {
+    /// Dependencies of the manifest that no locked package fulfills.
+    pub unlocked_packages: Vec<PackageKey<'a>>,
+    /// Locked top-level dependencies that the manifest no longer requires.
+    pub unused_packages: Vec<PackageKey<'a>>,
+}
+
+impl<'a> LockfileDrift<'a> {
+    pub fn from_manifest_and_lockfile(
+        manifest_packages: &ManifestPackages<'a>,
+        lockfile_packages: &LockfilePackages<'a>,
+    ) -> Self {
+        let lockfile_keys = lockfile_packages.package_keys();
+        let changed_packages =
+            ChangedManifestPackages::get_changed_packages_from_manifest_and_lockfile(
+                manifest_packages,
+                lockfile_packages,
+            );
+        // path packages always count as changed, but they are locked if the path is the same
+        let mut unlocked_packages: Vec<PackageKey<'a>> = changed_packages
+            .packages
+            .into_iter()
+            .filter(|manifest_key| match manifest_key {
+                PackageKey::PathPackage(_) => !lockfile_keys
+                    .iter()
+                    .any(|key| lockfile_packages.is_locked_for(key, manifest_key)),
+                _ => true,
+            })
+            .collect();
+        // dependencies of dependencies and the local package are not listed in the manifest
+        let mut unused_packages: Vec<PackageKey<'a>> = lockfile_packages
+            .packages
+            .iter()
+            .filter(|(_, data)| {
+                data.resolved_source() != Some("local")
+                    && data
+                        .commands
+                        .iter()
+                        .any(|command| command.is_top_level_dependency)
+            })
+            .map(|(key, _)| key)
+            .filter(|key| {
+                !manifest_packages
+                    .packages
+                    .iter()
+                    .any(|manifest_key| lockfile_packages.is_locked_for(key, manifest_key))
+            })
+            .cloned()
+            .collect();
+        unlocked_packages.sort_by_key(|key| key.to_string());
+        unused_packages.sort_by_key(|key| key.to_string());
+        Self {
+            unlocked_packages,
+            unused_packages,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unlocked_packages.is_empty() && self.unused_packages.is_empty()
+    }
+}
+
+impl<'a> fmt::Display for LockfileDrift<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for key in self.unlocked_packages.iter() {
+            writeln!(f, "  - {} is not locked", key)?;
+        }
+        for key in self.unused_packages.iter() {
+            writeln!(f, "  - {} is locked but no longer a dependency", key)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::lock::lockfile_command::LockfileCommand;
+    use crate::dataflow::lockfile_drift::LockfileDrift;
+    use crate::dataflow::lockfile_packages::{LockfilePackage, LockfilePackages};
+    use crate::dataflow::manifest_packages::ManifestPackages;
+    use crate::dataflow::PackageKey;
+    use std::collections::hash_map::HashMap;
+    use std::collections::hash_set::HashSet;
+
+    fn lockfile_package(name: &str, version: semver::Version, top_level: bool) -> LockfilePackage {
+        LockfilePackage {
+            modules: vec![],
+            commands: vec![LockfileCommand {
+                name: name.to_string(),
+                package_name: format!("_/{}", name),
+                package_version: version,
+                module: name.to_string(),
+                is_top_level_dependency: top_level,
+                main_args: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn lockfile_in_sync_with_manifest() {
+        let mut manifest_keys = HashSet::new();
+        manifest_keys.insert(PackageKey::new_registry_package_range(
+            "_/foo",
+            semver::VersionReq::parse("^1").unwrap(),
+        ));
+        let manifest_packages = ManifestPackages {
+            packages: manifest_keys,
+        };
+        let mut packages = HashMap::new();
+        packages.insert(
+            PackageKey::new_registry_package("_/foo", semver::Version::new(1, 2, 0)),
+            lockfile_package("foo", semver::Version::new(1, 2, 0), true),
+        );
+        // a dependency of a dependency is not required by the manifest
+        packages.insert(
+            PackageKey::new_registry_package("_/bar", semver::Version::new(2, 0, 0)),
+            lockfile_package("bar", semver::Version::new(2, 0, 0), false),
+        );
+        let lockfile_packages = LockfilePackages { packages };
+
+        let drift =
+            LockfileDrift::from_manifest_and_lockfile(&manifest_packages, &lockfile_packages);
+        assert!(drift.is_empty());
+    }
+
+    #[test]
+    fn report_changed_dependencies() {
+        let mut manifest_keys = HashSet::new();
+        manifest_keys.insert(PackageKey::new_registry_package_range(
+            "_/foo",
+            semver::VersionReq::parse("^2").unwrap(),
+        ));
+        let manifest_packages = ManifestPackages {
+            packages: manifest_keys,
+        };
+        let mut packages = HashMap::new();
+        packages.insert(
+            PackageKey::new_registry_package("_/foo", semver::Version::new(1, 2, 0)),
+            lockfile_package("foo", semver::Version::new(1, 2, 0), true),
+        );
+        packages.insert(
+            PackageKey::new_registry_package("_/baz", semver::Version::new(0, 1, 0)),
+            lockfile_package("baz", semver::Version::new(0, 1, 0), true),
+        );
+        let lockfile_packages = LockfilePackages { packages };
+
+        let drift =
+            LockfileDrift::from_manifest_and_lockfile(&manifest_packages, &lockfile_packages);
+        assert_eq!(1, drift.unlocked_packages.len());
+        assert_eq!(2, drift.unused_packages.len());
+        let report = drift.to_string();
+        assert!(report.contains("_/foo >=2.0.0, <3.0.0 is not locked"));
+        assert!(report.contains("_/baz 0.1.0 is locked but no longer a dependency"));
+    }
+}
@@ -6,7 +6,9 @@ use crate::data::lock::migrate::{
 };
 use crate::data::lock::LOCKFILE_NAME;
 use crate::dataflow::installed_packages::InstalledPackages;
+use crate::dataflow::package_cache::PackageSignature;
 use crate::dataflow::removed_packages::RemovedPackages;
+use crate::dataflow::resolved_packages::PackageRegistries;
 use crate::dataflow::{PackageKey, WapmPackageKey};
 use std::collections::hash_map::HashMap;
 use std::collections::hash_set::HashSet;
@@ -26,6 +28,8 @@ pub enum LockfileError {
     InvalidOrMissingVersion,
     #[error("Lockfile version is too high, update wapm or delete `wapm.lock` and try again.")]
     VersionTooHigh,
+    #[error("The package \"{0}\" in the lockfile has no modules to install it from. Delete `wapm.lock` and run `wapm install`.")]
+    PackageWithoutModules(String),
 }
 
 /// A ternary for a lockfile: Some, None, Error.
@@ -89,6 +93,47 @@ pub struct LockfilePackage {
     pub commands: Vec<LockfileCommand>,
 }
 
+impl LockfilePackage {
+    /// The source the package was installed from, e.g. `registry+<module>` or `path+<path>`.
+    pub fn resolved_source(&self) -> Option<&str> {
+        self.modules
+            .first()
+            .map(|module| module.resolved_source.as_str())
+    }
+
+    /// The integrity hash of the package archive, if it was recorded.
+    pub fn integrity(&self) -> Option<&str> {
+        self.modules
+            .first()
+            .and_then(|module| module.integrity.as_deref())
+    }
+
+    /// The signature the package archive was verified with, if it was recorded.
+    pub fn signature(&self) -> Option<&PackageSignature> {
+        self.modules
+            .first()
+            .and_then(|module| module.signature.as_ref())
+    }
+
+    /// The named registry the package was installed from, `None` for the default registry.
+    pub fn registry(&self) -> Option<&str> {
+        self.modules
+            .first()
+            .and_then(|module| module.registry.as_deref())
+    }
+
+    /// Record the registry the package was installed from. Only packages from a registry have one.
+    pub fn set_registry(&mut self, registry: Option<&str>) {
+        for module in self.modules.iter_mut() {
+            module.registry = if module.resolved_source.starts_with("registry+") {
+                registry.map(str::to_string)
+            } else {
+                None
+            };
+        }
+    }
+}
+
 /// A wrapper around a map of key -> lockfile package.
 #[derive(Clone, Debug, Default)]
 pub struct LockfilePackages<'a> {
@@ -97,10 +142,12 @@ pub struct LockfilePackages<'a> {
 
 impl<'a> LockfilePackages<'a> {
     pub fn from_installed_packages(
-        installed_manifest_packages: &'a InstalledPackages<'a>,
+        installed_manifest_packages: &InstalledPackages<'a>,
     ) -> Result<Self, LockfileError> {
         let mut packages = HashMap::default();
-        for (k, manifest, download_url) in installed_manifest_packages.packages.iter() {
+        for (k, manifest, download_url, integrity, signature) in
+            installed_manifest_packages.packages.iter()
+        {
             let modules: Vec<LockfileModule> = match manifest.module {
                 Some(ref modules) => modules
                     .iter()
@@ -111,6 +158,8 @@ impl<'a> LockfilePackages<'a> {
                             &k.version,
                             module,
                             download_url,
+                            integrity.clone(),
+                            signature.clone(),
                         )
                     })
                     .collect(),
@@ -217,10 +266,7 @@ impl<'a> LockfilePackages<'a> {
                     .iter()
                     .map(|(package_key, _)| package_key)
                     .cloned()
-                    .filter(|package_key| match package_key {
-                        PackageKey::WapmPackage(WapmPackageKey { name, .. }) => name == &pkg_name,
-                        _ => false,
-                    })
+                    .filter(|package_key| package_key.name() == pkg_name)
                     .collect::<Vec<_>>()
             })
             .collect::<Vec<_>>();
@@ -230,7 +276,64 @@ impl<'a> LockfilePackages<'a> {
         }
     }
 
+    /// The integrity hash recorded for a locked package version.
+    pub fn integrity(&self, key: &WapmPackageKey<'a>) -> Option<&str> {
+        self.packages
+            .get(&PackageKey::WapmPackage(key.clone()))
+            .and_then(LockfilePackage::integrity)
+    }
+
+    pub fn signature(&self, key: &WapmPackageKey<'a>) -> Option<&PackageSignature> {
+        self.packages
+            .get(&PackageKey::WapmPackage(key.clone()))
+            .and_then(LockfilePackage::signature)
+    }
+
+    /// Returns true if the locked package with this key fulfills the requirement of the manifest.
+    /// Packages from a path or a git repository are matched by name and the recorded source.
+    pub fn is_locked_for(&self, key: &PackageKey, requirement: &PackageKey) -> bool {
+        let resolved_source = self
+            .packages
+            .get(key)
+            .and_then(LockfilePackage::resolved_source);
+        if requirement.is_source_package() {
+            key.name() == requirement.name()
+                && resolved_source
+                    .map(|resolved_source| requirement.matches_source(resolved_source))
+                    .unwrap_or(false)
+        } else {
+            // a package that was installed from a path or a git repository must be replaced
+            let from_source = resolved_source
+                .map(|resolved_source| {
+                    resolved_source.starts_with("path+") || resolved_source.starts_with("git+")
+                })
+                .unwrap_or(false);
+            !from_source && key.satisfies(requirement)
+        }
+    }
+
+    /// The registries the locked packages were installed from.
+    pub fn registries(&self) -> PackageRegistries {
+        let mut registries = PackageRegistries::default();
+        for (key, package) in self.packages.iter() {
+            if let Some(registry) = package.registry() {
+                registries.insert(key.name(), registry.to_string());
+            }
+        }
+        registries
+    }
+
     pub fn extend(&mut self, other_packages: LockfilePackages<'a>) {
         self.packages.extend(other_packages.packages);
     }
+
+    /// Flag the commands of these packages as belonging to dependencies of dependencies, i.e.
+    /// packages that were not requested directly.
+    pub fn mark_as_transitive(&mut self) {
+        for package in self.packages.values_mut() {
+            for command in package.commands.iter_mut() {
+                command.is_top_level_dependency = false;
+            }
+        }
+    }
 }
@@ -1,7 +1,7 @@
-use crate::data::manifest::{Manifest, MANIFEST_FILE_NAME};
+use crate::data::manifest::{Dependency, DependencySource, Manifest, MANIFEST_FILE_NAME};
 use crate::dataflow::added_packages::AddedPackages;
 use crate::dataflow::removed_packages::RemovedPackages;
-use crate::dataflow::{normalize_global_namespace, PackageKey, WapmPackageKey};
+use crate::dataflow::{normalize_global_namespace, PackageKey};
 use semver::{Version, VersionReq};
 use std::collections::hash_set::HashSet;
 use std::fs;
@@ -18,6 +18,8 @@ pub enum Error {
         "Version {0} for package {1} must be a semantic version or a semantic version requirement."
     )]
     SemVerError(String, String),
+    #[error("Dependency {0} must have either a `version`, a `path` or a `git` source, `registry` is only allowed with `version` and `rev` only with `git`.")]
+    InvalidDependencySource(String),
 }
 
 /// A ternary for a manifest: Some, None, Error.
@@ -79,6 +81,16 @@ impl<'a> ManifestPackages<'a> {
         Ok(Self { packages })
     }
 
+    /// Construct package keys from the dependencies of a single manifest, e.g. the manifest of an
+    /// installed package. Short-hand package names are transformed.
+    pub fn new_from_manifest(manifest: &'a Manifest) -> Result<Self, Error> {
+        let packages = Self::extract_package_keys(&manifest)?
+            .into_iter()
+            .map(normalize_global_namespace)
+            .collect();
+        Ok(Self { packages })
+    }
+
     pub fn keys(&self) -> HashSet<PackageKey<'a>> {
         self.packages.iter().cloned().collect()
     }
@@ -92,10 +104,7 @@ impl<'a> ManifestPackages<'a> {
                 self.packages
                     .iter()
                     .cloned()
-                    .filter(|package_key| match package_key {
-                        PackageKey::WapmPackage(WapmPackageKey { name, .. }) => name == &pkg_name,
-                        _ => false,
-                    })
+                    .filter(|package_key| package_key.name() == pkg_name)
                     .collect::<Vec<_>>()
             })
             .flatten()
@@ -112,8 +121,12 @@ impl<'a> ManifestPackages<'a> {
             Some(ref dependencies) => {
                 let result = dependencies
                     .iter()
-                    .map(|(name, value)| (name.as_str(), value.as_str()))
-                    .map(Self::parse_wapm_package_key)
+                    .map(|(name, dependency)| match dependency {
+                        Dependency::Version(version) => {
+                            Self::parse_wapm_package_key((name.as_str(), version.as_str()))
+                        }
+                        Dependency::Source(source) => Self::parse_source_package_key(name, source),
+                    })
                     .collect::<Result<Vec<_>, Error>>()?;
                 Ok(result)
             }
@@ -121,6 +134,37 @@ impl<'a> ManifestPackages<'a> {
         }
     }
 
+    /// Parse a dependency on a package in a named registry, a local directory or a git repository.
+    fn parse_source_package_key(
+        name: &'a str,
+        source: &'a DependencySource,
+    ) -> Result<PackageKey<'a>, Error> {
+        match source {
+            DependencySource {
+                version: Some(version),
+                path: None,
+                git: None,
+                rev: None,
+                ..
+            } => Self::parse_wapm_package_key((name, version.as_str())),
+            DependencySource {
+                version: None,
+                registry: None,
+                path: Some(path),
+                git: None,
+                rev: None,
+            } => Ok(PackageKey::new_path_package(name, path.clone())),
+            DependencySource {
+                version: None,
+                registry: None,
+                path: None,
+                git: Some(url),
+                rev,
+            } => Ok(PackageKey::new_git_package(name, url.clone(), rev.clone())),
+            _ => Err(Error::InvalidDependencySource(name.to_string())),
+        }
+    }
+
     /// Parse a raw pair of strings as an exact wapm package or a range. May fail with a semver
     /// error.
     fn parse_wapm_package_key(
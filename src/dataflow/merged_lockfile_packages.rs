@@ -1,6 +1,7 @@
 use crate::data::lock::lockfile::{CommandMap, Lockfile, ModuleMap};
 use crate::dataflow::bin_script::save_bin_script;
 use crate::dataflow::lockfile_packages::{LockfilePackage, LockfilePackages};
+use crate::dataflow::resolved_packages::PackageRegistries;
 use crate::dataflow::retained_lockfile_packages::RetainedLockfilePackages;
 use crate::dataflow::{PackageKey, WapmPackageKey};
 use std::collections::btree_map::BTreeMap;
@@ -49,7 +50,19 @@ impl<'a> MergedLockfilePackages<'a> {
         Self { packages }
     }
 
-    pub fn generate_lockfile(self, directory: &'a Path) -> Result<(), Error> {
+    /// Record the registry each package was installed from.
+    pub fn set_registries(&mut self, registries: &PackageRegistries) {
+        for (key, package) in self.packages.iter_mut() {
+            package.set_registry(registries.registry(key.name()));
+        }
+    }
+
+    /// Write the lockfile, recording the hash of the manifest it was generated from, if any.
+    pub fn generate_lockfile(
+        self,
+        directory: &'a Path,
+        manifest_hash: Option<String>,
+    ) -> Result<(), Error> {
         let mut modules: ModuleMap = BTreeMap::new();
         let mut commands: CommandMap = BTreeMap::new();
         for (key, package) in self.packages {
@@ -68,6 +81,11 @@ impl<'a> MergedLockfilePackages<'a> {
                     }
                     for command in package.commands {
                         if let Some(module) = modules.get(&command.module) {
+                            // commands of dependencies of dependencies never shadow other commands
+                            if !command.is_top_level_dependency {
+                                commands.entry(command.name.clone()).or_insert(command);
+                                continue;
+                            }
                             let name = command.name.clone();
                             let script_name = command.name.clone();
                             let module_path = format!("{}/{}", module.package_path, module.source);
@@ -81,10 +99,17 @@ impl<'a> MergedLockfilePackages<'a> {
                 PackageKey::WapmPackageRange(_) => {
                     unreachable!("Attempting to generate with lockfile with package version range.")
                 }
+                PackageKey::PathPackage(_) | PackageKey::GitPackage(_) => {
+                    unreachable!("Packages from a source are locked with their installed version.")
+                }
             }
         }
 
-        let lockfile = Lockfile { modules, commands };
+        let lockfile = Lockfile {
+            manifest_hash,
+            modules,
+            commands,
+        };
 
         lockfile
             .save(directory)
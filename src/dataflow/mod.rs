@@ -1,20 +1,27 @@
-use crate::data::manifest::Manifest;
+use crate::data::manifest::{Manifest, MANIFEST_FILE_NAME};
 use crate::dataflow::added_packages::AddedPackages;
 use crate::dataflow::changed_manifest_packages::ChangedManifestPackages;
-use crate::dataflow::installed_packages::{InstalledPackages, RegistryInstaller};
+use crate::dataflow::installed_packages::{
+    CacheInstaller, Install, InstalledPackages, KeyCheck, RegistryInstaller,
+};
 use crate::dataflow::local_package::LocalPackage;
+use crate::dataflow::lockfile_drift::LockfileDrift;
 use crate::dataflow::lockfile_packages::{LockfileError, LockfilePackages, LockfileResult};
 use crate::dataflow::manifest_packages::{ManifestPackages, ManifestResult};
 use crate::dataflow::merged_lockfile_packages::MergedLockfilePackages;
 use crate::dataflow::removed_lockfile_packages::RemovedLockfilePackages;
 use crate::dataflow::removed_packages::RemovedPackages;
-use crate::dataflow::resolved_packages::{RegistryResolver, ResolvedPackages};
+use crate::dataflow::resolved_packages::{
+    CacheResolver, PackageRegistries, RegistryResolver, Resolve, ResolvedPackages,
+};
 use crate::dataflow::retained_lockfile_packages::RetainedLockfilePackages;
+use crate::dataflow::source_packages::SourcePackages;
+use crate::dataflow::transitive_packages::TransitivePackages;
 use semver::{Version, VersionReq};
 use std::borrow::{Borrow, Cow};
 use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub mod added_packages;
 pub mod bin_script;
@@ -23,13 +30,19 @@ pub mod find_command_result;
 pub mod installed_packages;
 pub mod interfaces;
 pub mod local_package;
+pub mod lockfile_drift;
 pub mod lockfile_packages;
 pub mod manifest_packages;
 pub mod merged_lockfile_packages;
+pub mod package_cache;
 pub mod removed_lockfile_packages;
 pub mod removed_packages;
 pub mod resolved_packages;
 pub mod retained_lockfile_packages;
+pub mod source_packages;
+pub mod transitive_packages;
+pub mod version_solver;
+use crate::exit_code::{Classify, ErrorKind};
 use thiserror::Error;
 
 #[derive(Clone, Debug, Error)]
@@ -52,10 +65,53 @@ pub enum Error {
     LocalPackageError(local_package::Error),
     #[error("Could not cleanup old artifacts. {0}")]
     CleanupError(removed_lockfile_packages::Error),
+    #[error("Could not install dependencies of package(s). {0}")]
+    TransitiveDependencyError(transitive_packages::Error),
+    #[error("Could not resolve package versions. {0}")]
+    SolverError(version_solver::Error),
+    #[error("Could not install package from its source. {0}")]
+    SourcePackageError(source_packages::Error),
+    #[error("Could not find wapm.lock. Installing with a frozen lockfile requires an existing lockfile.")]
+    MissingLockfile,
+    #[error(
+        "wapm.lock is out of date with wapm.toml:\n{0}Run `wapm install` to update the lockfile."
+    )]
+    LockfileDrift(String),
+    #[error("Could not recreate command \"{0}\". {1}")]
+    BinScriptError(String, bin_script::Error),
+    #[error("Could not find wapm.toml. Only the dependencies of a manifest can be updated.")]
+    UpdateWithoutManifest,
+    #[error("Package {0} is not a dependency of this project.")]
+    UnknownDependency(String),
     #[error("Attempting to install multiple versions of package {0} ({1} and {2})")]
     DuplicatePackage(String, String, String),
 }
 
+impl Classify for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::ManifestError(_) | Error::SaveError(_) | Error::UpdateWithoutManifest => {
+                ErrorKind::Manifest
+            }
+            Error::LockfileError(_)
+            | Error::GenerateLockfileError(_)
+            | Error::MissingLockfile
+            | Error::LockfileDrift(_) => ErrorKind::Lockfile,
+            Error::InstallError(e) => e.kind(),
+            Error::ResolveError(e) => e.kind(),
+            Error::SolverError(e) => e.kind(),
+            Error::AddError(_)
+            | Error::TransitiveDependencyError(_)
+            | Error::SourcePackageError(_)
+            | Error::DuplicatePackage(..) => ErrorKind::Dependency,
+            Error::LocalPackageError(_) | Error::CleanupError(_) | Error::BinScriptError(..) => {
+                ErrorKind::Io
+            }
+            Error::UnknownDependency(_) => ErrorKind::Usage,
+        }
+    }
+}
+
 /// A package key for a package in the wapm.io registry.
 /// This Is currently defined as name and a version.
 #[derive(Clone, Debug, Eq, Hash, PartialOrd, PartialEq)]
@@ -71,6 +127,39 @@ pub struct WapmPackageRange<'a> {
     pub version_req: VersionReq,
 }
 
+/// A package in a directory on the local file system. The path is relative to the manifest.
+#[derive(Clone, Debug, Eq, Hash, PartialOrd, PartialEq)]
+pub struct PathPackageKey<'a> {
+    pub name: Cow<'a, str>,
+    pub path: PathBuf,
+}
+
+impl<'a> PathPackageKey<'a> {
+    /// The source recorded in the lockfile for a package installed from this path.
+    pub fn resolved_source(&self) -> String {
+        format!("path+{}", self.path.to_string_lossy())
+    }
+}
+
+/// A package at the root of a git repository, optionally at a branch, tag or commit.
+#[derive(Clone, Debug, Eq, Hash, PartialOrd, PartialEq)]
+pub struct GitPackageKey<'a> {
+    pub name: Cow<'a, str>,
+    pub url: String,
+    pub rev: Option<String>,
+}
+
+impl<'a> GitPackageKey<'a> {
+    /// The source recorded in the lockfile for a package installed from this repository, up to
+    /// the hash of the commit that was checked out.
+    pub fn resolved_source_prefix(&self) -> String {
+        match self.rev {
+            Some(ref rev) => format!("git+{}?rev={}#", self.url, rev),
+            None => format!("git+{}#", self.url),
+        }
+    }
+}
+
 impl<'a> fmt::Display for WapmPackageKey<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         write!(f, "{} {}", self.name, self.version)
@@ -102,13 +191,16 @@ pub fn detect_duplicate_packages(packages: &HashSet<PackageKey>) -> Result<(), E
     Ok(())
 }
 
-/// A package key can be anything reference to a package, be it a wapm.io registry, a local directory.
-/// Currently, only wapm.io keys are supported.
+/// A package key can be anything reference to a package, be it a wapm.io registry, a local
+/// directory or a git repository. Packages from a path or a git repository are locked as regular
+/// wapm.io keys once installed, with their origin stored as the `resolved_source` of the modules.
 #[allow(dead_code)]
 #[derive(Clone, Debug, Eq, Hash, PartialOrd, PartialEq)]
 pub enum PackageKey<'a> {
     WapmPackage(WapmPackageKey<'a>),
     WapmPackageRange(WapmPackageRange<'a>),
+    PathPackage(PathPackageKey<'a>),
+    GitPackage(GitPackageKey<'a>),
 }
 
 impl<'a> PackageKey<'a> {
@@ -132,6 +224,77 @@ impl<'a> PackageKey<'a> {
         })
     }
 
+    pub fn new_path_package<S>(name: S, path: PathBuf) -> Self
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        PackageKey::PathPackage(PathPackageKey {
+            name: name.into(),
+            path,
+        })
+    }
+    pub fn new_git_package<S>(name: S, url: String, rev: Option<String>) -> Self
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        PackageKey::GitPackage(GitPackageKey {
+            name: name.into(),
+            url,
+            rev,
+        })
+    }
+
+    /// The name of the package, regardless of the kind of key.
+    pub fn name(&self) -> &str {
+        match self {
+            PackageKey::WapmPackage(WapmPackageKey { name, .. }) => name,
+            PackageKey::WapmPackageRange(WapmPackageRange { name, .. }) => name,
+            PackageKey::PathPackage(PathPackageKey { name, .. }) => name,
+            PackageKey::GitPackage(GitPackageKey { name, .. }) => name,
+        }
+    }
+
+    /// Returns true if the package is installed from a path or a git repository instead of the
+    /// registry.
+    pub fn is_source_package(&self) -> bool {
+        match self {
+            PackageKey::PathPackage(_) | PackageKey::GitPackage(_) => true,
+            PackageKey::WapmPackage(_) | PackageKey::WapmPackageRange(_) => false,
+        }
+    }
+
+    /// Returns true if a package with the given `resolved_source` in the lockfile was installed
+    /// from the path or git repository of this key.
+    pub fn matches_source(&self, resolved_source: &str) -> bool {
+        match self {
+            PackageKey::PathPackage(key) => resolved_source == key.resolved_source(),
+            PackageKey::GitPackage(key) => {
+                resolved_source.starts_with(&key.resolved_source_prefix())
+            }
+            PackageKey::WapmPackage(_) | PackageKey::WapmPackageRange(_) => false,
+        }
+    }
+
+    /// The key that installs a locked package from its `resolved_source` again, if it came from a
+    /// path or a git repository. A git package is checked out at the locked commit.
+    pub fn from_resolved_source(name: &str, resolved_source: &str) -> Option<PackageKey<'static>> {
+        if let Some(path) = resolved_source.strip_prefix("path+") {
+            return Some(PackageKey::new_path_package(
+                name.to_string(),
+                PathBuf::from(path),
+            ));
+        }
+        let source = resolved_source.strip_prefix("git+")?;
+        let commit_start = source.rfind('#')?;
+        let (url, commit) = (&source[..commit_start], &source[commit_start + 1..]);
+        let url = url.rfind("?rev=").map(|i| &url[..i]).unwrap_or(url);
+        Some(PackageKey::new_git_package(
+            name.to_string(),
+            url.to_string(),
+            Some(commit.to_string()),
+        ))
+    }
+
     pub fn matches(&self, range: &WapmPackageRange) -> bool {
         match self {
             PackageKey::WapmPackage(key) => {
@@ -140,13 +303,52 @@ impl<'a> PackageKey<'a> {
             _ => false,
         }
     }
+
+    /// Returns true if this key can be used to fulfill the given requirement, either because the
+    /// keys are identical or because this exact version is within the required range.
+    /// Path and git requirements can only be checked against the lockfile, see
+    /// `LockfilePackages::is_locked_for`.
+    pub fn satisfies(&self, requirement: &PackageKey) -> bool {
+        match requirement {
+            PackageKey::WapmPackage(_) => self == requirement,
+            PackageKey::WapmPackageRange(range) => self.matches(range),
+            PackageKey::PathPackage(_) | PackageKey::GitPackage(_) => false,
+        }
+    }
+
+    /// Convert into a key that owns the package name.
+    pub fn into_owned(self) -> PackageKey<'static> {
+        match self {
+            PackageKey::WapmPackage(WapmPackageKey { name, version }) => {
+                PackageKey::new_registry_package(name.into_owned(), version)
+            }
+            PackageKey::WapmPackageRange(WapmPackageRange { name, version_req }) => {
+                PackageKey::new_registry_package_range(name.into_owned(), version_req)
+            }
+            PackageKey::PathPackage(PathPackageKey { name, path }) => {
+                PackageKey::new_path_package(name.into_owned(), path)
+            }
+            PackageKey::GitPackage(GitPackageKey { name, url, rev }) => {
+                PackageKey::new_git_package(name.into_owned(), url, rev)
+            }
+        }
+    }
 }
 
 impl<'a> fmt::Display for PackageKey<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
             PackageKey::WapmPackage(wapm_package_key) => write!(f, "{}", wapm_package_key),
-            _ => panic!("Non wapm registry keys are unsupported."),
+            PackageKey::WapmPackageRange(WapmPackageRange { name, version_req }) => {
+                write!(f, "{} {}", name, version_req)
+            }
+            PackageKey::PathPackage(PathPackageKey { name, path }) => {
+                write!(f, "{} (path {})", name, path.to_string_lossy())
+            }
+            PackageKey::GitPackage(GitPackageKey { name, url, rev }) => match rev {
+                Some(rev) => write!(f, "{} (git {} at {})", name, url, rev),
+                None => write!(f, "{} (git {})", name, url),
+            },
         }
     }
 }
@@ -176,6 +378,18 @@ pub fn normalize_global_namespace(key: PackageKey) -> PackageKey {
             let name = format!("_/{}", name);
             PackageKey::new_registry_package_range(name, version_req.clone())
         }
+        PackageKey::PathPackage(PathPackageKey { ref name, ref path }) if !name.contains('/') => {
+            let name = format!("_/{}", name);
+            PackageKey::new_path_package(name, path.clone())
+        }
+        PackageKey::GitPackage(GitPackageKey {
+            ref name,
+            ref url,
+            ref rev,
+        }) if !name.contains('/') => {
+            let name = format!("_/{}", name);
+            PackageKey::new_git_package(name, url.clone(), rev.clone())
+        }
         key => key,
     };
     new_key
@@ -184,17 +398,23 @@ pub fn normalize_global_namespace(key: PackageKey) -> PackageKey {
 /// If there is no mainfest, then this is a non-manifest project. All installations are retained
 /// in the lockfile, and installs are additive.
 /// This function returns a bool on success indicating if any changes were applied
-pub fn update_with_no_manifest<P: AsRef<Path>>(
+pub fn update_with_no_manifest<Resolver, Installer, P: AsRef<Path>>(
     directory: P,
     added_packages: AddedPackages,
     removed_packages: RemovedPackages,
-) -> Result<bool, Error> {
+) -> Result<bool, Error>
+where
+    Resolver: for<'a> Resolve<'a>,
+    Installer: for<'a> Install<'a>,
+{
     let directory = directory.as_ref();
     // get lockfile data
     let lockfile_result = LockfileResult::find_in_directory(&directory);
     let mut lockfile_packages =
         LockfilePackages::new_from_result(lockfile_result).map_err(Error::LockfileError)?;
     detect_duplicate_packages(&added_packages.packages)?;
+    // without a manifest, packages stay with the registry they were installed from
+    let mut registries = lockfile_packages.registries();
 
     // capture the initial lockfile keys before any modifications
     let initial_package_keys: HashSet<_> = lockfile_packages.package_keys();
@@ -220,25 +440,68 @@ pub fn update_with_no_manifest<P: AsRef<Path>>(
     let missing_packages = lockfile_packages.find_missing_packages(&directory);
     let added_packages = added_packages.add_missing_packages(missing_packages);
 
+    // pick versions for the added packages and their dependencies that fit the installed packages
+    let solved_keys = if added_packages.packages.is_empty() {
+        HashSet::new()
+    } else {
+        let mut requirements = added_packages.packages.clone();
+        requirements.extend(lockfile_packages.package_keys().into_iter().filter(|key| {
+            !added_packages
+                .packages
+                .iter()
+                .any(|added_key| added_key.name() == key.name())
+        }));
+        version_solver::solve::<Resolver>(
+            "wapm install",
+            &requirements,
+            &lockfile_packages.package_keys(),
+            &registries,
+        )
+        .map_err(Error::SolverError)?
+    };
+    let added_packages = added_packages.pin_solved_versions(&solved_keys);
+
     let resolved_packages =
-        ResolvedPackages::new_from_added_packages::<RegistryResolver>(added_packages)
+        ResolvedPackages::new_from_added_packages::<Resolver>(added_packages, &registries)
             .map_err(Error::ResolveError)?;
-    let installed_packages =
-        InstalledPackages::install::<RegistryInstaller>(&directory, resolved_packages, false)
-            .map_err(Error::InstallError)?;
-    let added_lockfile_data = LockfilePackages::from_installed_packages(&installed_packages)
+    let installed_packages = InstalledPackages::install::<Installer>(
+        &directory,
+        resolved_packages,
+        &lockfile_packages,
+        &registries,
+        KeyCheck::Prompt,
+    )
+    .map_err(Error::InstallError)?;
+    let mut added_lockfile_data = LockfilePackages::from_installed_packages(&installed_packages)
         .map_err(Error::LockfileError)?;
 
+    // install the dependencies of every package, reusing whatever is already in the lockfile
+    let mut top_level_keys = added_lockfile_data.package_keys();
+    top_level_keys.extend(lockfile_packages.package_keys());
+    let transitive_packages = TransitivePackages::install::<Resolver, Installer>(
+        &directory,
+        top_level_keys.clone(),
+        &lockfile_packages,
+        &solved_keys,
+        &mut registries,
+        KeyCheck::Prompt,
+    )
+    .map_err(Error::TransitiveDependencyError)?;
+    top_level_keys.extend(transitive_packages.packages.package_keys());
+    detect_duplicate_packages(&top_level_keys)?;
+    added_lockfile_data.extend(transitive_packages.packages);
+
     let retained_lockfile_packages =
         RetainedLockfilePackages::from_lockfile_packages(lockfile_packages);
 
     // merge the lockfile data, and generate the new lockfile
-    let final_lockfile_data =
+    let mut final_lockfile_data =
         MergedLockfilePackages::merge(added_lockfile_data, retained_lockfile_packages);
+    final_lockfile_data.set_registries(&registries);
     let final_package_keys: HashSet<_> = final_lockfile_data.packages.keys().cloned().collect();
     if final_package_keys != initial_package_keys {
         final_lockfile_data
-            .generate_lockfile(&directory)
+            .generate_lockfile(&directory, None)
             .map_err(Error::GenerateLockfileError)?;
         Ok(true)
     } else {
@@ -249,12 +512,19 @@ pub fn update_with_no_manifest<P: AsRef<Path>>(
 /// If there is a manifest, then we construct lockfile data from manifest dependencies, and merge
 /// with existing lockfile data.
 /// This function returns a bool on success indicating if any changes were applied
-pub fn update_with_manifest<P: AsRef<Path>>(
+/// The locked versions of the packages named in `unlocked_package_names` are ignored, so that
+/// they move to the newest versions allowed by the manifest.
+pub fn update_with_manifest<Resolver, Installer, P: AsRef<Path>>(
     directory: P,
     manifest: Manifest,
     added_packages: AddedPackages,
     removed_packages: RemovedPackages,
-) -> Result<bool, Error> {
+    unlocked_package_names: &HashSet<String>,
+) -> Result<bool, Error>
+where
+    Resolver: for<'a> Resolve<'a>,
+    Installer: for<'a> Install<'a>,
+{
     let directory = directory.as_ref();
 
     let mut manifest_packages =
@@ -262,6 +532,7 @@ pub fn update_with_manifest<P: AsRef<Path>>(
             .map_err(Error::ManifestError)?;
 
     detect_duplicate_packages(&manifest_packages.packages)?;
+    let mut registries = PackageRegistries::from_manifest(&manifest);
 
     // remove/uninstall packages
     manifest_packages.remove_packages(&removed_packages);
@@ -287,47 +558,138 @@ pub fn update_with_manifest<P: AsRef<Path>>(
         packages: changed_manifest_data.packages,
     };
 
-    let missing_lockfile_packages = lockfile_packages.find_missing_packages(&directory);
-    let new_added_packages = packages_to_install.add_missing_packages(missing_lockfile_packages);
+    // packages from a path or a git repository are installed separately, including missing ones
+    let (packages_to_install, mut source_packages) = packages_to_install.split_source_packages();
 
-    let removed_lockfile_packages =
-        RemovedLockfilePackages::from_manifest_and_lockfile(&manifest_packages, &lockfile_packages);
+    // missing dependencies of dependencies are reinstalled when walking the dependency graph
+    let mut missing_lockfile_packages = HashSet::new();
+    for key in lockfile_packages.find_missing_packages(&directory) {
+        for manifest_key in manifest_packages.packages.iter() {
+            if !lockfile_packages.is_locked_for(&key, manifest_key) {
+                continue;
+            }
+            if manifest_key.is_source_package() {
+                source_packages.packages.insert(manifest_key.clone());
+            } else {
+                missing_lockfile_packages.insert(key.clone());
+            }
+        }
+    }
+    let new_added_packages =
+        packages_to_install.add_missing_packages(missing_lockfile_packages.clone());
 
-    // cleanup any old artifacts
-    removed_lockfile_packages
-        .cleanup_old_packages(&directory)
-        .map_err(Error::CleanupError)?;
+    // pick a version for every package in the dependency graph if anything has to be installed,
+    // keeping the locked versions wherever possible
+    let solved_keys = if new_added_packages.packages.is_empty() && unlocked_package_names.is_empty()
+    {
+        HashSet::new()
+    } else {
+        let preferred_keys = lockfile_packages
+            .package_keys()
+            .into_iter()
+            .filter(|key| !unlocked_package_names.contains(key.name()))
+            .collect();
+        version_solver::solve::<Resolver>(
+            MANIFEST_FILE_NAME,
+            &manifest_packages.packages,
+            &preferred_keys,
+            &registries,
+        )
+        .map_err(Error::SolverError)?
+    };
 
-    let retained_lockfile_packages =
+    // keep the old lockfile data around to reuse locked dependencies of dependencies
+    let old_lockfile_packages = lockfile_packages.clone();
+    let mut retained_lockfile_packages =
         RetainedLockfilePackages::from_manifest_and_lockfile(&manifest_packages, lockfile_packages);
 
+    // install the solved versions of manifest packages that are not locked or are missing
+    let new_added_packages = if solved_keys.is_empty() {
+        new_added_packages
+    } else {
+        retained_lockfile_packages.retain_solved_packages(&solved_keys);
+        let solved_manifest_packages = AddedPackages {
+            packages: manifest_packages
+                .keys()
+                .into_iter()
+                .filter(|key| !key.is_source_package())
+                .collect(),
+        }
+        .pin_solved_versions(&solved_keys);
+        let installed_package_keys = retained_lockfile_packages
+            .packages
+            .keys()
+            .filter(|key| !missing_lockfile_packages.contains(key))
+            .cloned()
+            .collect();
+        solved_manifest_packages.prune_already_installed_packages(installed_package_keys)
+    };
+
     let resolved_manifest_packages =
-        ResolvedPackages::new_from_added_packages::<RegistryResolver>(new_added_packages)
+        ResolvedPackages::new_from_added_packages::<Resolver>(new_added_packages, &registries)
             .map_err(Error::ResolveError)?;
-    let installed_manifest_packages = InstalledPackages::install::<RegistryInstaller>(
+    let installed_manifest_packages = InstalledPackages::install::<Installer>(
         &directory,
         resolved_manifest_packages,
-        false,
+        &old_lockfile_packages,
+        &registries,
+        KeyCheck::Prompt,
     )
     .map_err(Error::InstallError)?;
     let mut manifest_lockfile_data =
         LockfilePackages::from_installed_packages(&installed_manifest_packages)
             .map_err(Error::LockfileError)?;
+    let installed_source_packages = source_packages
+        .install(&directory)
+        .map_err(Error::SourcePackageError)?;
+    manifest_lockfile_data.extend(
+        LockfilePackages::from_installed_packages(&installed_source_packages)
+            .map_err(Error::LockfileError)?,
+    );
 
+    // install the dependencies of every package in the manifest
+    let mut top_level_keys = manifest_lockfile_data.package_keys();
+    top_level_keys.extend(retained_lockfile_packages.packages.keys().cloned());
+    let transitive_packages = TransitivePackages::install::<Resolver, Installer>(
+        &directory,
+        top_level_keys.clone(),
+        &old_lockfile_packages,
+        &solved_keys,
+        &mut registries,
+        KeyCheck::Prompt,
+    )
+    .map_err(Error::TransitiveDependencyError)?;
+    top_level_keys.extend(transitive_packages.packages.package_keys());
+    detect_duplicate_packages(&top_level_keys)?;
+
+    manifest_lockfile_data.extend(transitive_packages.packages);
     manifest_lockfile_data.extend(local_package.into());
 
     // merge the lockfile data, and generate the new lockfile
-    let final_lockfile_data =
+    let mut final_lockfile_data =
         MergedLockfilePackages::merge(manifest_lockfile_data, retained_lockfile_packages);
+    final_lockfile_data.set_registries(&registries);
     let final_package_keys: HashSet<_> = final_lockfile_data.packages.keys().cloned().collect();
+    let package_keys_changed = final_package_keys != initial_package_keys;
 
+    // cleanup any old artifacts
+    RemovedLockfilePackages::from_old_and_new_lockfile(&old_lockfile_packages, &final_package_keys)
+        .cleanup_old_packages(&directory)
+        .map_err(Error::CleanupError)?;
+
+    // the lockfile records the hash of the manifest as it is saved below
+    let manifest_hash = if package_keys_changed {
+        with_package_changes(manifest.clone(), &added_packages, &removed_packages).lockfile_hash()
+    } else {
+        manifest.lockfile_hash()
+    };
     final_lockfile_data
-        .generate_lockfile(&directory)
+        .generate_lockfile(&directory, Some(manifest_hash))
         .map_err(Error::GenerateLockfileError)?;
 
     // update the manifest, if applicable
-    if final_package_keys != initial_package_keys {
-        update_manifest(manifest.clone(), &added_packages, &removed_packages)?;
+    if package_keys_changed {
+        update_manifest(manifest, &added_packages, &removed_packages)?;
         Ok(true)
     } else {
         Ok(false)
@@ -341,20 +703,207 @@ pub fn update<P: AsRef<Path>>(
     removed_packages: Vec<&str>,
     directory: P,
 ) -> Result<bool, Error> {
+    update_using::<RegistryResolver, RegistryInstaller, P>(
+        added_packages,
+        removed_packages,
+        directory,
+    )
+}
+
+/// Like `update`, but packages are resolved and installed from the local package cache only.
+pub fn update_offline<P: AsRef<Path>>(
+    added_packages: Vec<(&str, &str)>,
+    removed_packages: Vec<&str>,
+    directory: P,
+) -> Result<bool, Error> {
+    update_using::<CacheResolver, CacheInstaller, P>(added_packages, removed_packages, directory)
+}
+
+fn update_using<Resolver, Installer, P: AsRef<Path>>(
+    added_packages: Vec<(&str, &str)>,
+    removed_packages: Vec<&str>,
+    directory: P,
+) -> Result<bool, Error>
+where
+    Resolver: for<'a> Resolve<'a>,
+    Installer: for<'a> Install<'a>,
+{
     let directory = directory.as_ref();
     let added_packages =
         AddedPackages::new_from_str_pairs(added_packages).map_err(Error::AddError)?;
     let removed_packages = RemovedPackages::new_from_package_names(removed_packages);
     let manifest_result = ManifestResult::find_in_directory(&directory);
     match manifest_result {
-        ManifestResult::NoManifest => {
-            update_with_no_manifest(directory, added_packages, removed_packages)
+        ManifestResult::NoManifest => update_with_no_manifest::<Resolver, Installer, _>(
+            directory,
+            added_packages,
+            removed_packages,
+        ),
+        ManifestResult::Manifest(manifest) => update_with_manifest::<Resolver, Installer, _>(
+            directory,
+            manifest,
+            added_packages,
+            removed_packages,
+            &HashSet::new(),
+        ),
+        ManifestResult::ManifestError(e) => return Err(Error::ManifestError(e)),
+    }
+}
+
+/// Move locked packages to the newest versions allowed by the manifest. Only the named packages
+/// are updated, or every package if no names are given.
+/// This function returns a bool on success indicating if any changes were applied
+pub fn update_locked_versions<P: AsRef<Path>>(
+    package_names: Vec<&str>,
+    directory: P,
+) -> Result<bool, Error> {
+    let directory = directory.as_ref();
+    let manifest = match ManifestResult::find_in_directory(directory) {
+        ManifestResult::Manifest(manifest) => manifest,
+        ManifestResult::NoManifest => return Err(Error::UpdateWithoutManifest),
+        ManifestResult::ManifestError(e) => return Err(Error::ManifestError(e)),
+    };
+    let lockfile_packages =
+        LockfilePackages::new_from_result(LockfileResult::find_in_directory(directory))
+            .map_err(Error::LockfileError)?;
+    let manifest_packages =
+        ManifestPackages::new_from_manifest(&manifest).map_err(Error::ManifestError)?;
+    let known_names: HashSet<String> = manifest_packages
+        .packages
+        .iter()
+        .chain(lockfile_packages.packages.keys())
+        .map(|key| key.name().to_string())
+        .collect();
+
+    let unlocked_package_names: HashSet<String> = if package_names.is_empty() {
+        known_names
+    } else {
+        let mut unlocked_package_names = HashSet::new();
+        for package_name in package_names {
+            let package_name =
+                normalize_global_namespace_package_name(Cow::Borrowed(package_name)).to_string();
+            if !known_names.contains(&package_name) {
+                return Err(Error::UnknownDependency(package_name));
+            }
+            unlocked_package_names.insert(package_name);
         }
+        unlocked_package_names
+    };
+
+    update_with_manifest::<RegistryResolver, RegistryInstaller, _>(
+        directory,
+        manifest,
+        AddedPackages::default(),
+        RemovedPackages::default(),
+        &unlocked_package_names,
+    )
+}
+
+/// Install exactly the packages in the lockfile without changing it. Nothing is resolved against
+/// the registry, the install fails if the dependencies in the manifest differ from the lockfile,
+/// and the keys of signatures are checked against the lockfile without prompting. This function returns a bool on success indicating if any packages were installed.
+pub fn install_frozen<P: AsRef<Path>>(directory: P, offline: bool) -> Result<bool, Error> {
+    if offline {
+        install_frozen_using::<CacheInstaller, P>(directory)
+    } else {
+        install_frozen_using::<RegistryInstaller, P>(directory)
+    }
+}
+
+fn install_frozen_using<Installer, P: AsRef<Path>>(directory: P) -> Result<bool, Error>
+where
+    Installer: for<'a> Install<'a>,
+{
+    let directory = directory.as_ref();
+    let lockfile_packages = match LockfileResult::find_in_directory(&directory) {
+        LockfileResult::NoLockfile => return Err(Error::MissingLockfile),
+        lockfile_result => {
+            LockfilePackages::new_from_result(lockfile_result).map_err(Error::LockfileError)?
+        }
+    };
+
+    match ManifestResult::find_in_directory(&directory) {
         ManifestResult::Manifest(manifest) => {
-            update_with_manifest(directory, manifest, added_packages, removed_packages)
+            let manifest_packages =
+                ManifestPackages::new_from_manifest(&manifest).map_err(Error::ManifestError)?;
+            let drift =
+                LockfileDrift::from_manifest_and_lockfile(&manifest_packages, &lockfile_packages);
+            if !drift.is_empty() {
+                return Err(Error::LockfileDrift(drift.to_string()));
+            }
         }
+        ManifestResult::NoManifest => {}
         ManifestResult::ManifestError(e) => return Err(Error::ManifestError(e)),
     }
+
+    // the modules of the local package are not installed into wapm_packages
+    let missing_packages: Vec<PackageKey> = lockfile_packages
+        .find_missing_packages(&directory)
+        .into_iter()
+        .filter(|key| lockfile_packages.packages[key].resolved_source() != Some("local"))
+        .collect();
+
+    // install every missing package from where the lockfile says it came from
+    let mut resolved_packages = ResolvedPackages::default();
+    let mut source_packages = SourcePackages::default();
+    for key in missing_packages.iter() {
+        let data = &lockfile_packages.packages[key];
+        let source_key = data.resolved_source().and_then(|resolved_source| {
+            PackageKey::from_resolved_source(key.name(), resolved_source)
+        });
+        match (key, source_key) {
+            (_, Some(source_key)) => {
+                source_packages.packages.insert(source_key);
+            }
+            (PackageKey::WapmPackage(wapm_package_key), None) => {
+                let download_url = data
+                    .modules
+                    .first()
+                    .map(|module| module.resolved.clone())
+                    .ok_or_else(|| {
+                        Error::LockfileError(LockfileError::PackageWithoutModules(key.to_string()))
+                    })?;
+                // the signature recorded in the lockfile is used by the installer
+                resolved_packages
+                    .packages
+                    .push((wapm_package_key.clone(), (download_url, None)));
+            }
+            _ => unreachable!("Lockfile should only contain exact wapm package versions."),
+        }
+    }
+    InstalledPackages::install::<Installer>(
+        &directory,
+        resolved_packages,
+        &lockfile_packages,
+        &lockfile_packages.registries(),
+        KeyCheck::Locked,
+    )
+    .map_err(Error::InstallError)?;
+    source_packages
+        .install(&directory)
+        .map_err(Error::SourcePackageError)?;
+
+    // recreate the scripts of the commands that were installed again
+    for key in missing_packages.iter() {
+        let data = &lockfile_packages.packages[key];
+        for command in data.commands.iter() {
+            if !command.is_top_level_dependency {
+                continue;
+            }
+            if let Some(module) = data.modules.iter().find(|m| m.name == command.module) {
+                let module_path = format!("{}/{}", module.package_path, module.source);
+                bin_script::save_bin_script(
+                    &directory,
+                    command.name.clone(),
+                    module.package_path.clone(),
+                    module_path,
+                )
+                .map_err(|e| Error::BinScriptError(command.name.clone(), e))?;
+            }
+        }
+    }
+
+    Ok(!missing_packages.is_empty())
 }
 
 /// Updates the manifest and saves it
@@ -367,6 +916,19 @@ pub fn update_manifest(
         return Ok(());
     }
 
+    with_package_changes(manifest, added_packages, removed_packages)
+        .save()
+        .map_err(|e| Error::SaveError(e.to_string()))?;
+
+    Ok(())
+}
+
+/// Add and remove the dependencies of the manifest
+fn with_package_changes(
+    manifest: Manifest,
+    added_packages: &AddedPackages,
+    removed_packages: &RemovedPackages,
+) -> Manifest {
     let mut manifest = manifest;
     for key in added_packages.packages.iter().cloned() {
         match key {
@@ -376,16 +938,14 @@ pub fn update_manifest(
             PackageKey::WapmPackage(WapmPackageKey { name, version }) => {
                 manifest.add_dependency(name.to_string(), version.to_string());
             }
+            PackageKey::PathPackage(_) | PackageKey::GitPackage(_) => {
+                unreachable!("Packages added from the command line come from the registry.")
+            }
         }
     }
 
     for package_name in removed_packages.packages.iter().cloned() {
         manifest.remove_dependency(package_name.borrow());
     }
-
     manifest
-        .save()
-        .map_err(|e| Error::SaveError(e.to_string()))?;
-
-    Ok(())
 }
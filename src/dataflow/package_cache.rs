@@ -0,0 +1,291 @@
+use crate::config::{Config, ConfigError, GlobalConfigError};
+use crate::data::manifest::MANIFEST_FILE_NAME;
+use crate::dataflow::WapmPackageKey;
+use crate::exit_code::{Classify, ErrorKind};
+use crate::util::{fully_qualified_package_display_name, get_package_namespace_and_name};
+use flate2::read::GzDecoder;
+use semver::Version;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tar::Archive;
+use thiserror::Error;
+
+pub static PACKAGE_CACHE_ENTRY_FILE_NAME: &str = "entry.toml";
+
+/// A package, or a version range of one, that is not in the local package cache.
+#[derive(Clone, Debug, Error)]
+#[error("Package \"{0}\" is not in the local package cache. Run the command again without `--offline` to download it.")]
+pub struct NotCachedError(pub String);
+
+impl Classify for NotCachedError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Registry
+    }
+}
+
+/// The package cache of a registry could not be opened.
+#[derive(Debug, Error)]
+pub enum OpenError {
+    #[error(transparent)]
+    GlobalConfig(#[from] GlobalConfigError),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+}
+
+impl Classify for OpenError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Config
+    }
+}
+
+/// Describes the cached copy of a package version.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct CacheEntry {
+    /// The sha256 hash of the package archive, which is also the name of the cached archive.
+    pub hash: String,
+    /// Where the package archive was downloaded from.
+    pub download_url: String,
+    /// The signature the archive was verified with when it was cached, `None` when it was cached
+    /// without verifying a signature.
+    #[serde(default)]
+    pub signature: Option<PackageSignature>,
+}
+
+/// A package signature and the public key that verified it.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct PackageSignature {
+    pub public_key_id: String,
+    pub public_key: String,
+    pub signature_data: String,
+    pub owner: String,
+}
+
+/// A content-addressed store of package archives shared by every project, located in
+/// `$WASMER_DIR/package_cache`. Every registry has its own cache, named after its URL, so that a
+/// package is never taken for a package with the same name from another registry. A package
+/// version is stored as `<registry>/<namespace>/<name>@<version>/<hash>.tar.gz` next to an
+/// `entry.toml` describing it. Archives are unpacked into each project, so that changing an
+/// installed package never changes the cache.
+#[derive(Clone, Debug)]
+pub struct PackageCache {
+    directory: PathBuf,
+}
+
+impl PackageCache {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    /// Open the cache of the named registry, or of the default registry, in the global config
+    /// folder.
+    pub fn open(registry: Option<&str>) -> Result<Self, OpenError> {
+        let config = Config::from_file()?;
+        let url = &config.registry(registry)?.url;
+        let directory = Config::get_package_cache_directory()?.join(registry_directory_name(url));
+        Ok(Self::new(directory))
+    }
+
+    fn version_directory(&self, name: &str, version: &Version) -> io::Result<PathBuf> {
+        let (namespace, name) = get_package_namespace_and_name(name)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        Ok(self
+            .directory
+            .join(namespace)
+            .join(fully_qualified_package_display_name(name, version)))
+    }
+
+    /// Find the cached copy of a package version, returning its entry and the path of the
+    /// archive.
+    pub fn find(&self, key: &WapmPackageKey) -> Option<(CacheEntry, PathBuf)> {
+        let version_directory = self.version_directory(&key.name, &key.version).ok()?;
+        let source =
+            fs::read_to_string(version_directory.join(PACKAGE_CACHE_ENTRY_FILE_NAME)).ok()?;
+        let entry: CacheEntry = toml::from_str(&source).ok()?;
+        let archive_path = version_directory.join(archive_file_name(&entry.hash));
+        if archive_path.is_file() {
+            Some((entry, archive_path))
+        } else {
+            None
+        }
+    }
+
+    /// All cached versions of a package.
+    pub fn versions(&self, name: &str) -> Vec<Version> {
+        let (namespace, name) = match get_package_namespace_and_name(name) {
+            Ok(namespace_and_name) => namespace_and_name,
+            Err(_) => return vec![],
+        };
+        let prefix = format!("{}@", name);
+        let entries = match fs::read_dir(self.directory.join(namespace)) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+        let mut versions: Vec<Version> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                let version = file_name.strip_prefix(&prefix)?;
+                Version::parse(version).ok()
+            })
+            .filter(|version| {
+                let key = WapmPackageKey {
+                    name: format!("{}/{}", namespace, name).into(),
+                    version: version.clone(),
+                };
+                self.find(&key).is_some()
+            })
+            .collect();
+        versions.sort();
+        versions
+    }
+
+    /// Copy a package archive into the cache under its hash. If the same content was cached in
+    /// the meantime, the existing copy is kept.
+    pub fn insert(
+        &self,
+        key: &WapmPackageKey,
+        entry: CacheEntry,
+        archive: &mut fs::File,
+    ) -> io::Result<PathBuf> {
+        let version_directory = self.version_directory(&key.name, &key.version)?;
+        fs::create_dir_all(&version_directory)?;
+        let archive_path = version_directory.join(archive_file_name(&entry.hash));
+        if !archive_path.is_file() {
+            // copy next to the final location first, so that a partial copy is never found
+            let mut staged_archive = tempfile::NamedTempFile::new_in(&version_directory)?;
+            archive.seek(SeekFrom::Start(0))?;
+            io::copy(archive, &mut staged_archive)?;
+            staged_archive.persist(&archive_path).map_err(|e| e.error)?;
+        }
+        let source = toml::to_string(&entry)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        // the entry is replaced in one step as well, so that it is never read half written
+        let mut staged_entry = tempfile::NamedTempFile::new_in(&version_directory)?;
+        staged_entry.write_all(source.as_bytes())?;
+        staged_entry
+            .persist(version_directory.join(PACKAGE_CACHE_ENTRY_FILE_NAME))
+            .map_err(|e| e.error)?;
+        Ok(archive_path)
+    }
+}
+
+/// The name of the cache directory of a registry: its URL without the scheme, with everything but
+/// letters, digits, dots and dashes replaced by underscores.
+fn registry_directory_name(url: &str) -> String {
+    let url = url.split("://").last().unwrap_or(url).trim_end_matches('/');
+    url.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn archive_file_name(hash: &str) -> String {
+    format!("{}.tar.gz", hash)
+}
+
+/// Read the manifest out of a package archive, `None` if the package has no manifest.
+pub fn archive_manifest(archive_path: &Path) -> io::Result<Option<String>> {
+    let mut archive = Archive::new(GzDecoder::new(fs::File::open(archive_path)?));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if path.strip_prefix(".").unwrap_or(&path) == Path::new(MANIFEST_FILE_NAME) {
+            let mut manifest = String::new();
+            entry.read_to_string(&mut manifest)?;
+            return Ok(Some(manifest));
+        }
+    }
+    Ok(None)
+}
+
+/// The sha256 hash of everything in the reader, hex encoded.
+pub fn content_hash<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dataflow::package_cache::{
+        archive_manifest, content_hash, registry_directory_name, CacheEntry, PackageCache,
+    };
+    use crate::dataflow::WapmPackageKey;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::fs;
+    use std::io::Write;
+
+    #[test]
+    fn insert_and_find_packages() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let cache = PackageCache::new(tmp_dir.path());
+        let key = WapmPackageKey {
+            name: "_/foo".into(),
+            version: semver::Version::new(1, 0, 0),
+        };
+        assert!(cache.find(&key).is_none());
+
+        let mut archive = tempfile::tempfile().unwrap();
+        archive.write_all(b"archive").unwrap();
+        let entry = CacheEntry {
+            hash: content_hash(&mut &b"archive"[..]).unwrap(),
+            download_url: "https://example.com/foo-1.0.0.tar.gz".to_string(),
+            signature: None,
+        };
+        cache.insert(&key, entry.clone(), &mut archive).unwrap();
+
+        let (found_entry, archive_path) = cache.find(&key).unwrap();
+        assert_eq!(entry, found_entry);
+        assert_eq!(b"archive".to_vec(), fs::read(archive_path).unwrap());
+        assert_eq!(vec![semver::Version::new(1, 0, 0)], cache.versions("_/foo"));
+        assert!(cache.versions("_/bar").is_empty());
+    }
+
+    #[test]
+    fn read_manifest_from_archive() {
+        let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+        for (path, contents) in &[
+            ("./foo.wasm", &b"\0asm"[..]),
+            ("./wapm.toml", &b"[package]"[..]),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *contents).unwrap();
+        }
+        let archive = tempfile::NamedTempFile::new().unwrap();
+        fs::write(
+            archive.path(),
+            builder.into_inner().unwrap().finish().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            Some("[package]".to_string()),
+            archive_manifest(archive.path()).unwrap()
+        );
+    }
+
+    #[test]
+    fn registries_have_their_own_directory() {
+        assert_eq!(
+            "registry.wapm.io_graphql",
+            registry_directory_name("https://registry.wapm.io/graphql")
+        );
+        assert_ne!(
+            registry_directory_name("https://registry.wapm.io/graphql"),
+            registry_directory_name("https://wapm.internal.example.com/graphql")
+        );
+    }
+}
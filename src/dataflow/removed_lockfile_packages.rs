@@ -1,10 +1,12 @@
+use crate::data::manifest::PACKAGES_DIR_NAME;
 use crate::dataflow::bin_script::delete_bin_script;
 use crate::dataflow::lockfile_packages::{LockfilePackage, LockfilePackages};
-use crate::dataflow::manifest_packages::ManifestPackages;
 use crate::dataflow::removed_packages::RemovedPackages;
 use crate::dataflow::{bin_script, PackageKey, WapmPackageKey};
+use crate::util::fully_qualified_package_display_name;
 use std::collections::hash_map::HashMap;
 use std::collections::hash_set::HashSet;
+use std::fs;
 use std::path::Path;
 use thiserror::Error;
 
@@ -12,6 +14,8 @@ use thiserror::Error;
 pub enum Error {
     #[error("Could not cleanup uninstalled command \"{0}\". {1}")]
     CommandCleanupError(String, bin_script::Error),
+    #[error("Could not remove the old files of package \"{0}\". {1}")]
+    PackageCleanupError(String, String),
 }
 
 #[derive(Clone, Debug)]
@@ -20,24 +24,6 @@ pub struct RemovedLockfilePackages<'a> {
 }
 
 impl<'a> RemovedLockfilePackages<'a> {
-    pub fn from_manifest_and_lockfile(
-        manifest_packages: &'a ManifestPackages<'a>,
-        lockfile_packages: &'a LockfilePackages<'a>,
-    ) -> Self {
-        // collect all removed packages
-        let old_package_keys: HashSet<_> = lockfile_packages.packages.keys().cloned().collect();
-        let packages = old_package_keys
-            .difference(&manifest_packages.packages)
-            .map(|key| {
-                (
-                    key.clone(),
-                    lockfile_packages.packages.get(&key).cloned().unwrap(),
-                )
-            })
-            .collect();
-        Self { packages }
-    }
-
     pub fn from_removed_packages_and_lockfile(
         removed_packages: &'a RemovedPackages<'a>,
         lockfile_packages: &'a LockfilePackages<'a>,
@@ -64,15 +50,39 @@ impl<'a> RemovedLockfilePackages<'a> {
         Self { packages }
     }
 
+    /// Packages of the old lockfile that are not part of the new lockfile.
+    pub fn from_old_and_new_lockfile(
+        old_lockfile_packages: &LockfilePackages<'a>,
+        new_package_keys: &HashSet<PackageKey<'a>>,
+    ) -> Self {
+        let packages = old_lockfile_packages
+            .packages
+            .iter()
+            .filter(|(key, _)| !new_package_keys.contains(key))
+            .map(|(key, data)| (key.clone(), data.clone()))
+            .collect();
+        Self { packages }
+    }
+
     /// This will do the required cleanup of old artifacts like bin scripts and wapm packages
     pub fn cleanup_old_packages<P: AsRef<Path>>(self, directory: P) -> Result<(), Error> {
         let directory = directory.as_ref();
-        for (_key, data) in self.packages {
+        for (key, data) in self.packages {
             for command in data.commands {
                 delete_bin_script(directory, command.name.clone())
                     .map_err(|e| Error::CommandCleanupError(command.name.clone(), e))?;
             }
-            // TODO cleanup wapm_packages
+            if let PackageKey::WapmPackage(WapmPackageKey { name, version }) = key {
+                let package_dir = directory
+                    .join(PACKAGES_DIR_NAME)
+                    .join(fully_qualified_package_display_name(&name, &version));
+                // the local package is not installed into wapm_packages
+                if package_dir.is_dir() {
+                    fs::remove_dir_all(&package_dir).map_err(|e| {
+                        Error::PackageCleanupError(format!("{}@{}", name, version), e.to_string())
+                    })?;
+                }
+            }
         }
         Ok(())
     }
@@ -80,57 +90,14 @@ impl<'a> RemovedLockfilePackages<'a> {
 
 #[cfg(test)]
 mod test {
+    use crate::data::manifest::PACKAGES_DIR_NAME;
     use crate::dataflow::lockfile_packages::{LockfilePackage, LockfilePackages};
-    use crate::dataflow::manifest_packages::ManifestPackages;
     use crate::dataflow::removed_lockfile_packages::RemovedLockfilePackages;
     use crate::dataflow::removed_packages::RemovedPackages;
     use crate::dataflow::PackageKey;
     use std::collections::hash_set::HashSet;
     use std::collections::HashMap;
-
-    #[test]
-    fn get_removed_lockfile_packages_from_manifest_and_lockfile() {
-        let mut manifest_packages = ManifestPackages::default();
-        let mut packages = HashSet::default();
-        packages.insert(PackageKey::new_registry_package(
-            "_/foo",
-            semver::Version::parse("1.0.0").unwrap(),
-        ));
-        packages.insert(PackageKey::new_registry_package(
-            "_/bar",
-            semver::Version::parse("2.0.0").unwrap(),
-        ));
-        manifest_packages.packages = packages;
-
-        let mut lockfile_packages = LockfilePackages::default();
-        let mut packages = HashMap::default();
-        packages.insert(
-            PackageKey::new_registry_package("_/foo", semver::Version::parse("1.0.0").unwrap()),
-            LockfilePackage::default(),
-        );
-        packages.insert(
-            PackageKey::new_registry_package("_/bar", semver::Version::parse("2.0.0").unwrap()),
-            LockfilePackage::default(),
-        );
-        packages.insert(
-            PackageKey::new_registry_package("_/baz", semver::Version::parse("3.0.0").unwrap()),
-            LockfilePackage::default(),
-        );
-        lockfile_packages.packages = packages;
-
-        let removed_lockfile_packages = RemovedLockfilePackages::from_manifest_and_lockfile(
-            &manifest_packages,
-            &lockfile_packages,
-        );
-        assert_eq!(1, removed_lockfile_packages.packages.len());
-        removed_lockfile_packages
-            .packages
-            .get(&PackageKey::new_registry_package(
-                "_/baz",
-                semver::Version::parse("3.0.0").unwrap(),
-            ))
-            .unwrap();
-    }
+    use std::fs;
 
     #[test]
     fn get_removed_lockfile_packages_from_removed_packages_and_lockfile() {
@@ -176,4 +143,32 @@ mod test {
             ))
             .unwrap();
     }
+
+    #[test]
+    fn cleanup_packages_missing_from_new_lockfile() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let old_key = PackageKey::new_registry_package("_/foo", semver::Version::new(1, 0, 0));
+        let new_key = PackageKey::new_registry_package("_/foo", semver::Version::new(1, 3, 0));
+        let mut packages = HashMap::default();
+        packages.insert(old_key, LockfilePackage::default());
+        packages.insert(new_key.clone(), LockfilePackage::default());
+        let old_lockfile_packages = LockfilePackages { packages };
+        let packages_dir = tmp_dir.path().join(PACKAGES_DIR_NAME);
+        fs::create_dir_all(packages_dir.join("_/foo@1.0.0")).unwrap();
+        fs::create_dir_all(packages_dir.join("_/foo@1.3.0")).unwrap();
+
+        let mut new_package_keys = HashSet::new();
+        new_package_keys.insert(new_key);
+        let removed_lockfile_packages = RemovedLockfilePackages::from_old_and_new_lockfile(
+            &old_lockfile_packages,
+            &new_package_keys,
+        );
+        assert_eq!(1, removed_lockfile_packages.packages.len());
+        removed_lockfile_packages
+            .cleanup_old_packages(tmp_dir.path())
+            .unwrap();
+
+        assert!(!packages_dir.join("_/foo@1.0.0").exists());
+        assert!(packages_dir.join("_/foo@1.3.0").is_dir());
+    }
 }
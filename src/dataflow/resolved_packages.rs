@@ -1,11 +1,18 @@
 use crate::constants::*;
+use crate::data::manifest::{Dependency, DependencySource, Manifest};
 use crate::dataflow::added_packages::AddedPackages;
-use crate::dataflow::{PackageKey, WapmPackageKey, WapmPackageRange};
-use crate::graphql::{execute_query, DateTime};
+use crate::dataflow::manifest_packages::ManifestPackages;
+use crate::dataflow::package_cache::{archive_manifest, NotCachedError, PackageCache};
+use crate::dataflow::{
+    normalize_global_namespace_package_name, PackageKey, WapmPackageKey, WapmPackageRange,
+};
+use crate::exit_code::{Classify, ErrorKind};
+use crate::graphql::{execute_query_on_registry, DateTime};
 use crate::keys;
 use graphql_client::*;
-use semver::Version;
-use std::borrow::Cow::Owned;
+use semver::{Version, VersionReq};
+use std::borrow::Cow::{self, Owned};
+use std::collections::btree_map::BTreeMap;
 use std::collections::hash_map::HashMap;
 use std::collections::hash_set::HashSet;
 use thiserror::Error;
@@ -18,10 +25,153 @@ use thiserror::Error;
 )]
 struct GetPackagesQuery;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/schema.graphql",
+    query_path = "graphql/queries/get_package_manifest.graphql",
+    response_derives = "Debug"
+)]
+struct GetPackageManifestQuery;
+
 #[derive(Clone, Debug, Error)]
 pub enum Error {
     #[error("There was a problem resolve dependencies. {0}")]
     CouldNotResolvePackages(String),
+    #[error(transparent)]
+    PackageNotCached(#[from] NotCachedError),
+    #[error("Package \"{package}\" is required from the {registry} registry by {required_by} and from the {other_registry} registry by {other_required_by}. Add it to the dependencies of the manifest to choose its registry.")]
+    AmbiguousRegistry {
+        package: String,
+        registry: String,
+        required_by: String,
+        other_registry: String,
+        other_required_by: String,
+    },
+}
+
+impl Classify for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::AmbiguousRegistry { .. } => ErrorKind::Dependency,
+            _ => ErrorKind::Registry,
+        }
+    }
+}
+
+/// The named registry each package is resolved from. The registries of the packages the project
+/// depends on are chosen by the project, those without one are resolved from the default
+/// registry. A dependency of a dependency is resolved from the registry of the package that
+/// requires it, so that a package from a private registry never pulls its dependencies from the
+/// public one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PackageRegistries {
+    registries: HashMap<String, String>,
+    /// The packages whose registry is chosen by the project, including the default registry.
+    declared: HashSet<String>,
+    /// The registry of each dependency of a dependency, and the package it was inherited from.
+    inherited: HashMap<String, (Option<String>, String)>,
+}
+
+impl PackageRegistries {
+    /// The registries named by the dependencies of the manifest, e.g.
+    /// `foo = { version = "^1", registry = "internal" }`.
+    pub fn from_manifest(manifest: &Manifest) -> Self {
+        let mut package_registries = Self::default();
+        for (name, dependency) in manifest.dependencies.iter().flatten() {
+            match dependency {
+                Dependency::Source(DependencySource {
+                    registry: Some(registry),
+                    ..
+                }) => package_registries.insert(name, registry.clone()),
+                _ => package_registries.declare(name),
+            }
+        }
+        package_registries
+    }
+
+    pub fn insert(&mut self, package_name: &str, registry: String) {
+        let package_name = normalize_global_namespace_package_name(Cow::Borrowed(package_name));
+        self.declared.insert(package_name.to_string());
+        self.registries.insert(package_name.to_string(), registry);
+    }
+
+    /// Mark a package as chosen by the project, so that it keeps its registry when another
+    /// package depends on it.
+    pub fn declare(&mut self, package_name: &str) {
+        let package_name = normalize_global_namespace_package_name(Cow::Borrowed(package_name));
+        self.declared.insert(package_name.to_string());
+    }
+
+    /// The name of the registry of the package, `None` for the default registry.
+    pub fn registry(&self, package_name: &str) -> Option<&str> {
+        match self.registries.get(package_name) {
+            Some(registry) => Some(registry),
+            None => self
+                .inherited
+                .get(package_name)
+                .and_then(|(registry, _)| registry.as_deref()),
+        }
+    }
+
+    /// The registry a dependency of a package from `parent_registry` is resolved from.
+    pub fn dependency_registry(
+        &self,
+        parent_registry: Option<&str>,
+        dependency: &str,
+    ) -> Option<String> {
+        if self.declared.contains(dependency) {
+            self.registry(dependency).map(str::to_string)
+        } else {
+            parent_registry.map(str::to_string)
+        }
+    }
+
+    /// Resolve a dependency of the `parent` package from the registry of the parent, unless the
+    /// project chose its registry. Fails if another package requires it from another registry.
+    pub fn inherit(&mut self, parent: &str, dependency: &str) -> Result<(), Error> {
+        let dependency = normalize_global_namespace_package_name(Cow::Borrowed(dependency));
+        if self.declared.contains(dependency.as_ref()) {
+            return Ok(());
+        }
+        let registry = self.registry(parent).map(str::to_string);
+        match self.inherited.get(dependency.as_ref()) {
+            Some((other_registry, other_parent)) if *other_registry != registry => {
+                Err(Error::AmbiguousRegistry {
+                    package: dependency.to_string(),
+                    registry: registry_display_name(registry.as_deref()),
+                    required_by: parent.to_string(),
+                    other_registry: registry_display_name(other_registry.as_deref()),
+                    other_required_by: other_parent.clone(),
+                })
+            }
+            Some(_) => Ok(()),
+            None => {
+                self.inherited
+                    .insert(dependency.to_string(), (registry, parent.to_string()));
+                Ok(())
+            }
+        }
+    }
+
+    /// Group the package names by the registry they are resolved from.
+    fn by_registry(&self, package_names: Vec<String>) -> BTreeMap<Option<&str>, Vec<String>> {
+        let mut by_registry: BTreeMap<Option<&str>, Vec<String>> = BTreeMap::new();
+        for package_name in package_names {
+            by_registry
+                .entry(self.registry(&package_name))
+                .or_default()
+                .push(package_name);
+        }
+        by_registry
+    }
+}
+
+/// How a registry is named in messages.
+pub fn registry_display_name(registry: Option<&str>) -> String {
+    match registry {
+        Some(registry) => format!("\"{}\"", registry),
+        None => "default".to_string(),
+    }
 }
 
 /// Struct containing wapm registry resolved packages. This is realized as a pairing of wapm.io keys
@@ -37,7 +187,10 @@ pub struct ResolvedPackages<'a> {
 impl<'a> ResolvedPackages<'a> {
     /// Consume changed manifest packages and produce keys with download urls. Will query the registry
     /// for the download urls.
-    fn new<Resolver>(packages: HashSet<PackageKey<'a>>) -> Result<Self, Error>
+    fn new<Resolver>(
+        packages: HashSet<PackageKey<'a>>,
+        registries: &PackageRegistries,
+    ) -> Result<Self, Error>
     where
         Resolver: Resolve<'a>,
     {
@@ -46,18 +199,19 @@ impl<'a> ResolvedPackages<'a> {
         if wapm_pkgs.is_empty() {
             return Ok(Self::default());
         }
-        let packages = Resolver::sync_packages(wapm_pkgs)
+        let packages = Resolver::sync_packages(wapm_pkgs, registries)
             .map_err(|e| Error::CouldNotResolvePackages(e.to_string()))?;
         Ok(Self { packages })
     }
 
     pub fn new_from_added_packages<Resolver>(
         added_packages: AddedPackages<'a>,
+        registries: &PackageRegistries,
     ) -> Result<Self, Error>
     where
         Resolver: Resolve<'a>,
     {
-        Self::new::<Resolver>(added_packages.packages)
+        Self::new::<Resolver>(added_packages.packages, registries)
     }
 }
 
@@ -65,6 +219,7 @@ impl<'a> ResolvedPackages<'a> {
 pub trait Resolve<'a> {
     fn sync_packages(
         added_packages: Vec<PackageKey<'a>>,
+        registries: &PackageRegistries,
     ) -> Result<
         Vec<(
             WapmPackageKey<'a>,
@@ -72,21 +227,75 @@ pub trait Resolve<'a> {
         )>,
         Error,
     >;
+
+    /// Fetch every published version of the named packages.
+    fn get_package_versions(
+        package_names: Vec<String>,
+        registries: &PackageRegistries,
+    ) -> Result<HashMap<String, Vec<Version>>, Error>;
+
+    /// Fetch the dependencies declared by a single version of a package. The version solver only
+    /// fetches them for the versions it tries.
+    fn get_package_dependencies(
+        package_name: &str,
+        version: &Version,
+        registries: &PackageRegistries,
+    ) -> Result<Vec<(String, VersionReq)>, Error>;
 }
 
 pub struct RegistryResolver;
 
 impl<'a> RegistryResolver {
-    fn get_response(added_pkgs: Vec<PackageKey<'a>>) -> get_packages_query::ResponseData {
-        let names = added_pkgs
+    /// Query the registry of each package, one request per registry.
+    fn get_responses(
+        names: Vec<String>,
+        registries: &PackageRegistries,
+    ) -> Result<Vec<get_packages_query::ResponseData>, Error> {
+        registries
+            .by_registry(names)
             .into_iter()
-            .map(|key| match key {
-                PackageKey::WapmPackageRange(WapmPackageRange { name, .. }) => name.to_string(),
-                PackageKey::WapmPackage(WapmPackageKey { name, .. }) => name.to_string(),
+            .map(|(registry, names)| {
+                let q = GetPackagesQuery::build_query(get_packages_query::Variables { names });
+                execute_query_on_registry(registry, &q).map_err(|e| {
+                    Error::CouldNotResolvePackages(format!(
+                        "querying the {} registry failed: {}",
+                        registry.unwrap_or("default"),
+                        e
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    /// Parse the dependencies out of the manifest that was published with a package version.
+    fn parse_dependencies(
+        name: &str,
+        version: &str,
+        manifest: &str,
+    ) -> Result<Vec<(String, VersionReq)>, Error> {
+        let manifest: Manifest = toml::from_str(manifest).map_err(|e| {
+            Error::CouldNotResolvePackages(format!(
+                "the manifest of {}@{} is invalid: {}",
+                name, version, e
+            ))
+        })?;
+        let manifest_packages = ManifestPackages::new_from_manifest(&manifest)
+            .map_err(|e| Error::CouldNotResolvePackages(e.to_string()))?;
+        let dependencies = manifest_packages
+            .packages
+            .into_iter()
+            .filter_map(|key| match key {
+                PackageKey::WapmPackage(WapmPackageKey { name, version }) => {
+                    Some((name.to_string(), VersionReq::exact(&version)))
+                }
+                PackageKey::WapmPackageRange(WapmPackageRange { name, version_req }) => {
+                    Some((name.to_string(), version_req))
+                }
+                // not supported for dependencies of dependencies, see `TransitivePackages`
+                PackageKey::PathPackage(_) | PackageKey::GitPackage(_) => None,
             })
             .collect();
-        let q = GetPackagesQuery::build_query(get_packages_query::Variables { names });
-        execute_query(&q).unwrap()
+        Ok(dependencies)
     }
 }
 
@@ -95,6 +304,7 @@ impl<'a> Resolve<'a> for RegistryResolver {
     /// This gross function queries the GraphQL server. See the schema in `/graphql/queries/get_packages.graphql`
     fn sync_packages(
         added_packages: Vec<PackageKey<'a>>,
+        registries: &PackageRegistries,
     ) -> Result<
         Vec<(
             WapmPackageKey<'a>,
@@ -103,15 +313,19 @@ impl<'a> Resolve<'a> for RegistryResolver {
         Error,
     > {
         // fetch data from graphql server
-        let response = Self::get_response(added_packages.clone());
+        let names = added_packages
+            .iter()
+            .map(|key| key.name().to_string())
+            .collect();
+        let responses = Self::get_responses(names, registries)?;
         let all_packages_and_download_urls: Vec<(
             String,
             Version,
             String,
             Option<keys::WapmPackageSignature>,
-        )> = response
-            .package
+        )> = responses
             .into_iter()
+            .flat_map(|response| response.package)
             .filter_map(|p| p)
             .map(|p| {
                 let versions = p.versions.unwrap_or_default();
@@ -212,19 +426,187 @@ impl<'a> Resolve<'a> for RegistryResolver {
                     });
                     key_and_data
                 }
+                // installed by `SourcePackages`, never resolved through the registry
+                PackageKey::PathPackage(_) | PackageKey::GitPackage(_) => None,
             })
             .collect();
         Ok(packages_and_download_urls)
     }
+
+    fn get_package_versions(
+        package_names: Vec<String>,
+        registries: &PackageRegistries,
+    ) -> Result<HashMap<String, Vec<Version>>, Error> {
+        let responses = Self::get_responses(package_names, registries)?;
+        let mut package_versions = HashMap::new();
+        for package in responses
+            .into_iter()
+            .flat_map(|response| response.package)
+            .flatten()
+        {
+            let versions = package
+                .versions
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|v| v)
+                .map(|v| {
+                    Version::parse(&v.version)
+                        .map_err(|e| Error::CouldNotResolvePackages(e.to_string()))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            package_versions.insert(package.name, versions);
+        }
+        Ok(package_versions)
+    }
+
+    fn get_package_dependencies(
+        package_name: &str,
+        version: &Version,
+        registries: &PackageRegistries,
+    ) -> Result<Vec<(String, VersionReq)>, Error> {
+        let registry = registries.registry(package_name);
+        let q = GetPackageManifestQuery::build_query(get_package_manifest_query::Variables {
+            name: package_name.to_string(),
+            version: Some(version.to_string()),
+        });
+        let response: get_package_manifest_query::ResponseData =
+            execute_query_on_registry(registry, &q).map_err(|e| {
+                Error::CouldNotResolvePackages(format!(
+                    "querying the {} registry failed: {}",
+                    registry.unwrap_or("default"),
+                    e
+                ))
+            })?;
+        let package_version = response.package_version.ok_or_else(|| {
+            Error::CouldNotResolvePackages(format!(
+                "{}@{} was not found in the {} registry",
+                package_name,
+                version,
+                registry.unwrap_or("default")
+            ))
+        })?;
+        Self::parse_dependencies(
+            package_name,
+            &version.to_string(),
+            &package_version.manifest,
+        )
+    }
+}
+
+/// The Cache Resolver resolves packages from the local package cache, without connecting to the
+/// registry. Version ranges resolve to the newest cached version. Every package is looked up in the
+/// cache of the registry it is resolved from.
+pub struct CacheResolver;
+
+impl CacheResolver {
+    fn open_cache(
+        registries: &PackageRegistries,
+        package_name: &str,
+    ) -> Result<PackageCache, Error> {
+        PackageCache::open(registries.registry(package_name))
+            .map_err(|e| Error::CouldNotResolvePackages(e.to_string()))
+    }
+}
+
+impl<'a> Resolve<'a> for CacheResolver {
+    fn sync_packages(
+        added_packages: Vec<PackageKey<'a>>,
+        registries: &PackageRegistries,
+    ) -> Result<
+        Vec<(
+            WapmPackageKey<'a>,
+            (String, Option<keys::WapmPackageSignature>),
+        )>,
+        Error,
+    > {
+        added_packages
+            .into_iter()
+            .map(|added_package| {
+                let cache = Self::open_cache(registries, added_package.name())?;
+                let key = match added_package {
+                    PackageKey::WapmPackage(wapm_package_key) => wapm_package_key,
+                    PackageKey::WapmPackageRange(range) => {
+                        let version = cache
+                            .versions(&range.name)
+                            .into_iter()
+                            .filter(|v| range.version_req.matches(v))
+                            .max()
+                            .ok_or_else(|| {
+                                NotCachedError(format!("{} {}", range.name, range.version_req))
+                            })?;
+                        WapmPackageKey {
+                            name: range.name,
+                            version,
+                        }
+                    }
+                    PackageKey::PathPackage(_) | PackageKey::GitPackage(_) => {
+                        unreachable!("Source packages are never resolved.")
+                    }
+                };
+                let (entry, _) = cache
+                    .find(&key)
+                    .ok_or_else(|| NotCachedError(key.to_string()))?;
+                Ok((key, (entry.download_url, None)))
+            })
+            .collect()
+    }
+
+    fn get_package_versions(
+        package_names: Vec<String>,
+        registries: &PackageRegistries,
+    ) -> Result<HashMap<String, Vec<Version>>, Error> {
+        let mut package_versions = HashMap::new();
+        for name in package_names {
+            let cache = Self::open_cache(registries, &name)?;
+            let versions = cache
+                .versions(&name)
+                .into_iter()
+                .filter(|version| {
+                    let key = WapmPackageKey {
+                        name: name.as_str().into(),
+                        version: version.clone(),
+                    };
+                    cache.find(&key).is_some()
+                })
+                .collect();
+            package_versions.insert(name, versions);
+        }
+        Ok(package_versions)
+    }
+
+    fn get_package_dependencies(
+        package_name: &str,
+        version: &Version,
+        registries: &PackageRegistries,
+    ) -> Result<Vec<(String, VersionReq)>, Error> {
+        let cache = Self::open_cache(registries, package_name)?;
+        let key = WapmPackageKey {
+            name: package_name.into(),
+            version: version.clone(),
+        };
+        let (_, archive_path) = cache
+            .find(&key)
+            .ok_or_else(|| NotCachedError(key.to_string()))?;
+        let manifest = archive_manifest(&archive_path)
+            .map_err(|e| Error::CouldNotResolvePackages(e.to_string()))?;
+        match manifest {
+            Some(manifest) => {
+                RegistryResolver::parse_dependencies(package_name, &version.to_string(), &manifest)
+            }
+            None => Ok(vec![]),
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::data::manifest::Manifest;
     use crate::dataflow::added_packages::AddedPackages;
-    use crate::dataflow::resolved_packages::{Error, Resolve, ResolvedPackages};
+    use crate::dataflow::resolved_packages::{Error, PackageRegistries, Resolve, ResolvedPackages};
     use crate::dataflow::{PackageKey, WapmPackageKey, WapmPackageRange};
     use crate::keys;
-    use std::collections::HashSet;
+    use semver::{Version, VersionReq};
+    use std::collections::{HashMap, HashSet};
 
     struct TestResolver;
 
@@ -232,6 +614,7 @@ mod test {
     impl<'a> Resolve<'a> for TestResolver {
         fn sync_packages(
             added_packages: Vec<PackageKey<'a>>,
+            _registries: &PackageRegistries,
         ) -> Result<
             Vec<(
                 WapmPackageKey<'a>,
@@ -251,6 +634,7 @@ mod test {
                             name != "_/bar" && // simulate non-existent packages
                                 name != "_/baz"
                         }
+                        _ => false,
                     }
                 })
                 .map(|k| match k {
@@ -268,9 +652,61 @@ mod test {
                         },
                         ("url".to_string(), None),
                     ),
+                    _ => unreachable!("Source packages are never resolved."),
                 })
                 .collect())
         }
+
+        fn get_package_versions(
+            _package_names: Vec<String>,
+            _registries: &PackageRegistries,
+        ) -> Result<HashMap<String, Vec<Version>>, Error> {
+            Ok(HashMap::new())
+        }
+
+        fn get_package_dependencies(
+            _package_name: &str,
+            _version: &Version,
+            _registries: &PackageRegistries,
+        ) -> Result<Vec<(String, VersionReq)>, Error> {
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn registries_of_manifest_dependencies() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+[package]
+name = "test"
+version = "1.0.0"
+description = "description"
+
+[dependencies]
+foo = "^1"
+bar = { version = "^2", registry = "internal" }
+"acme/baz" = { version = "1.0.0", registry = "acme" }
+"#,
+        )
+        .unwrap();
+        let registries = PackageRegistries::from_manifest(&manifest);
+        assert_eq!(None, registries.registry("_/foo"));
+        assert_eq!(Some("internal"), registries.registry("_/bar"));
+        assert_eq!(Some("acme"), registries.registry("acme/baz"));
+
+        let by_registry = registries.by_registry(vec![
+            "_/foo".to_string(),
+            "_/bar".to_string(),
+            "_/qux".to_string(),
+        ]);
+        assert_eq!(
+            Some(&vec!["_/foo".to_string(), "_/qux".to_string()]),
+            by_registry.get(&None)
+        );
+        assert_eq!(
+            Some(&vec!["_/bar".to_string()]),
+            by_registry.get(&Some("internal"))
+        );
     }
 
     #[test]
@@ -282,8 +718,11 @@ mod test {
         let added_packages = AddedPackages {
             packages: packages_set,
         };
-        let resolve_packages =
-            ResolvedPackages::new_from_added_packages::<TestResolver>(added_packages).unwrap();
+        let resolve_packages = ResolvedPackages::new_from_added_packages::<TestResolver>(
+            added_packages,
+            &PackageRegistries::default(),
+        )
+        .unwrap();
         assert_eq!(1, resolve_packages.packages.len());
     }
 
@@ -302,8 +741,11 @@ mod test {
         let added_packages = AddedPackages {
             packages: packages_set,
         };
-        let resolve_packages =
-            ResolvedPackages::new_from_added_packages::<TestResolver>(added_packages).unwrap();
+        let resolve_packages = ResolvedPackages::new_from_added_packages::<TestResolver>(
+            added_packages,
+            &PackageRegistries::default(),
+        )
+        .unwrap();
         assert_eq!(1, resolve_packages.packages.len());
     }
 
@@ -323,8 +765,11 @@ mod test {
         let added_packages = AddedPackages {
             packages: packages_set,
         };
-        let resolve_packages =
-            ResolvedPackages::new_from_added_packages::<TestResolver>(added_packages).unwrap();
+        let resolve_packages = ResolvedPackages::new_from_added_packages::<TestResolver>(
+            added_packages,
+            &PackageRegistries::default(),
+        )
+        .unwrap();
         assert_eq!(1, resolve_packages.packages.len());
         resolve_packages
             .packages
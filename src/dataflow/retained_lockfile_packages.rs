@@ -13,17 +13,23 @@ impl<'a> RetainedLockfilePackages<'a> {
         manifest_packages: &'a ManifestPackages<'a>,
         lockfile_packages: LockfilePackages<'a>,
     ) -> Self {
-        let manifest_keys = manifest_packages.keys();
-        let lockfile_keys: HashSet<_> = lockfile_packages.packages.keys().cloned().collect();
-        let keys: HashSet<_> = manifest_keys
-            .intersection(&lockfile_keys)
+        // a locked package is retained if it is an exact match or within a range of the manifest,
+        // or if it was installed from the same path or git repository
+        let retained_keys: Vec<_> = lockfile_packages
+            .packages
+            .keys()
+            .filter(|k| {
+                manifest_packages
+                    .packages
+                    .iter()
+                    .any(|manifest_key| lockfile_packages.is_locked_for(k, manifest_key))
+            })
             .cloned()
             .collect();
-
-        let packages: HashMap<_, _> = lockfile_packages
-            .packages
+        let mut lockfile_packages = lockfile_packages.packages;
+        let packages: HashMap<_, _> = retained_keys
             .into_iter()
-            .filter(|(k, _)| keys.contains(k))
+            .filter_map(|k| lockfile_packages.remove_entry(&k))
             .collect();
 
         RetainedLockfilePackages { packages }
@@ -34,6 +40,17 @@ impl<'a> RetainedLockfilePackages<'a> {
             packages: lockfile_packages.packages,
         }
     }
+
+    /// Drop locked packages that the version solver replaced with another version. Packages from a
+    /// path or a git repository are not part of the solve and are kept.
+    pub fn retain_solved_packages(&mut self, solved_keys: &HashSet<PackageKey<'a>>) {
+        self.packages.retain(|key, _| {
+            solved_keys.contains(key)
+                || !solved_keys
+                    .iter()
+                    .any(|solved_key| solved_key.name() == key.name())
+        });
+    }
 }
 
 #[cfg(test)]
@@ -119,4 +136,39 @@ mod retained_lockfile_packages_tests {
             &PackageKey::new_registry_package("_/bar", semver::Version::new(2, 2, 0))
         ));
     }
+
+    #[test]
+    fn retain_lockfile_packages_matching_range() {
+        let mut manifest_package_keys = HashSet::new();
+        manifest_package_keys.insert(PackageKey::new_registry_package_range(
+            "_/foo",
+            semver::VersionReq::parse("^1").unwrap(),
+        ));
+        let manifest_packages = ManifestPackages {
+            packages: manifest_package_keys,
+        };
+
+        let mut lockfile_package_map = HashMap::new();
+        lockfile_package_map.insert(
+            PackageKey::new_registry_package("_/foo", semver::Version::new(1, 2, 0)),
+            LockfilePackage::default(),
+        );
+        lockfile_package_map.insert(
+            PackageKey::new_registry_package("_/foo", semver::Version::new(2, 0, 0)),
+            LockfilePackage::default(),
+        );
+        let lockfile_packages = LockfilePackages {
+            packages: lockfile_package_map,
+        };
+
+        let retained_lockfile_packages = RetainedLockfilePackages::from_manifest_and_lockfile(
+            &manifest_packages,
+            lockfile_packages,
+        );
+
+        assert_eq!(1, retained_lockfile_packages.packages.len());
+        assert!(retained_lockfile_packages.packages.contains_key(
+            &PackageKey::new_registry_package("_/foo", semver::Version::new(1, 2, 0))
+        ));
+    }
 }
@@ -0,0 +1,338 @@
+use crate::data::manifest::{Manifest, PACKAGES_DIR_NAME};
+use crate::dataflow::installed_packages::InstalledPackages;
+use crate::dataflow::manifest_packages::ManifestResult;
+use crate::dataflow::{
+    normalize_global_namespace, GitPackageKey, PackageKey, PathPackageKey, WapmPackageKey,
+};
+use crate::util::{
+    create_package_dir, fully_qualified_package_display_name, get_package_namespace_and_name,
+};
+use std::collections::hash_set::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use thiserror::Error;
+
+#[derive(Clone, Debug, Error)]
+pub enum Error {
+    #[error("Could not find a package manifest for dependency {0}. {1}")]
+    MissingManifest(String, String),
+    #[error("Dependency {0} points to package \"{1}\".")]
+    NameMismatch(String, String),
+    #[error("There was an IO error copying package data for dependency {0}. {1}")]
+    IoCopyError(String, String),
+    #[error("Could not fetch dependency {0} with git. {1}")]
+    GitError(String, String),
+}
+
+/// Packages from a local directory or a git repository. They bypass the registry and are copied
+/// into `wapm_packages` as they are, e.g. `wapm_packages/_/foo@1.0.0`.
+#[derive(Clone, Debug, Default)]
+pub struct SourcePackages<'a> {
+    pub packages: HashSet<PackageKey<'a>>,
+}
+
+impl<'a> SourcePackages<'a> {
+    /// Copy every package into the `wapm_packages` directory of the project. The download url of
+    /// each installed package is the source that is recorded in the lockfile.
+    pub fn install(self, directory: &Path) -> Result<InstalledPackages<'static>, Error> {
+        let mut packages = vec![];
+        for key in self.packages {
+            let (key, manifest, download_url, integrity) = match key {
+                PackageKey::PathPackage(ref path_key) => {
+                    Self::install_from_path(directory, &key, path_key)?
+                }
+                PackageKey::GitPackage(ref git_key) => {
+                    Self::install_from_git(directory, &key, git_key)?
+                }
+                PackageKey::WapmPackage(_) | PackageKey::WapmPackageRange(_) => {
+                    unreachable!("Registry packages are not installed from a source.")
+                }
+            };
+            // source packages are not signed
+            packages.push((key, manifest, download_url, integrity, None));
+        }
+        Ok(InstalledPackages { packages })
+    }
+
+    fn install_from_path(
+        directory: &Path,
+        key: &PackageKey,
+        path_key: &PathPackageKey,
+    ) -> Result<(WapmPackageKey<'static>, Manifest, String, Option<String>), Error> {
+        let source_dir = directory.join(&path_key.path);
+        info!("Installing {} from {}", path_key.name, source_dir.display());
+        Self::install_from_directory(directory, key, &source_dir, path_key.resolved_source())
+    }
+
+    fn install_from_git(
+        directory: &Path,
+        key: &PackageKey,
+        git_key: &GitPackageKey,
+    ) -> Result<(WapmPackageKey<'static>, Manifest, String, Option<String>), Error> {
+        info!("Installing {} from {}", git_key.name, git_key.url);
+        // the url and rev come from the manifest and must never be read as options of git
+        if let Some(ref rev) = git_key.rev {
+            if rev.starts_with('-') {
+                return Err(Error::GitError(
+                    key.to_string(),
+                    format!("The rev \"{}\" is not a git revision.", rev),
+                ));
+            }
+        }
+        let checkout_dir = tempfile::TempDir::new()
+            .map_err(|e| Error::GitError(key.to_string(), e.to_string()))?;
+        let checkout_path = checkout_dir.path().to_string_lossy().to_string();
+        Self::git(
+            key,
+            &["clone", "--quiet", "--", &git_key.url, &checkout_path],
+        )?;
+        if let Some(ref rev) = git_key.rev {
+            Self::git(
+                key,
+                &[
+                    "-C",
+                    &checkout_path,
+                    "checkout",
+                    "--quiet",
+                    "--detach",
+                    rev,
+                    "--",
+                ],
+            )?;
+        }
+        let commit = Self::git(key, &["-C", &checkout_path, "rev-parse", "HEAD"])?;
+        let resolved_source = format!("{}{}", git_key.resolved_source_prefix(), commit);
+        Self::install_from_directory(directory, key, checkout_dir.path(), resolved_source)
+    }
+
+    /// Run git and return its trimmed standard output.
+    fn git(key: &PackageKey, args: &[&str]) -> Result<String, Error> {
+        let output = Command::new("git")
+            .args(args)
+            .output()
+            .map_err(|e| Error::GitError(key.to_string(), e.to_string()))?;
+        if !output.status.success() {
+            return Err(Error::GitError(
+                key.to_string(),
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Check the manifest of the package in `source_dir` and replace any previous copy in
+    /// `wapm_packages` with the contents of the directory.
+    fn install_from_directory(
+        directory: &Path,
+        key: &PackageKey,
+        source_dir: &Path,
+        resolved_source: String,
+    ) -> Result<(WapmPackageKey<'static>, Manifest, String, Option<String>), Error> {
+        let manifest = Self::find_manifest(key, source_dir)?;
+        let package_name = match normalize_global_namespace(PackageKey::new_registry_package(
+            manifest.package.name.clone(),
+            manifest.package.version.clone(),
+        )) {
+            PackageKey::WapmPackage(WapmPackageKey { name, .. }) => name.to_string(),
+            _ => unreachable!("An exact registry key stays exact."),
+        };
+        if package_name != key.name() {
+            return Err(Error::NameMismatch(key.to_string(), package_name));
+        }
+
+        let (namespace, name) = get_package_namespace_and_name(&package_name)
+            .map_err(|e| Error::NameMismatch(key.to_string(), e.to_string()))?;
+        let fully_qualified_package_name =
+            fully_qualified_package_display_name(name, &manifest.package.version);
+        let package_dir = directory
+            .join(PACKAGES_DIR_NAME)
+            .join(namespace)
+            .join(&fully_qualified_package_name);
+        let copy_error = |e: io::Error| Error::IoCopyError(key.to_string(), e.to_string());
+        if package_dir.exists() {
+            fs::remove_dir_all(&package_dir).map_err(copy_error)?;
+        }
+        let package_dir = create_package_dir(directory, namespace, &fully_qualified_package_name)
+            .map_err(copy_error)?;
+        copy_dir(source_dir, &package_dir).map_err(copy_error)?;
+
+        let manifest = Self::find_manifest(key, &package_dir)?;
+        let wapm_package_key = WapmPackageKey {
+            name: package_name.into(),
+            version: manifest.package.version.clone(),
+        };
+        // the source itself pins the content, there is no archive to hash
+        Ok((wapm_package_key, manifest, resolved_source, None))
+    }
+
+    fn find_manifest(key: &PackageKey, directory: &Path) -> Result<Manifest, Error> {
+        match ManifestResult::find_in_directory(directory) {
+            ManifestResult::Manifest(manifest) => Ok(manifest),
+            ManifestResult::NoManifest => Err(Error::MissingManifest(
+                key.to_string(),
+                format!("Manifest was not found in {}.", directory.display()),
+            )),
+            ManifestResult::ManifestError(e) => {
+                Err(Error::MissingManifest(key.to_string(), e.to_string()))
+            }
+        }
+    }
+}
+
+/// Recursively copy a package directory, leaving out installed packages and git metadata.
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        if file_name == PACKAGES_DIR_NAME || file_name == ".git" {
+            continue;
+        }
+        let target: PathBuf = to.join(&file_name);
+        if entry.file_type()?.is_dir() {
+            fs::create_dir_all(&target)?;
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::manifest::{MANIFEST_FILE_NAME, PACKAGES_DIR_NAME};
+    use crate::dataflow::source_packages::{Error, SourcePackages};
+    use crate::dataflow::PackageKey;
+    use std::collections::hash_set::HashSet;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    fn write_package(directory: &Path, name: &str, version: &str) {
+        fs::create_dir_all(directory).unwrap();
+        let manifest = format!(
+            r#"
+[package]
+name = "{}"
+version = "{}"
+description = "test package"
+
+[[module]]
+name = "{}"
+source = "{}.wasm"
+abi = "wasi"
+"#,
+            name, version, name, name
+        );
+        fs::write(directory.join(MANIFEST_FILE_NAME), manifest).unwrap();
+        fs::write(directory.join(format!("{}.wasm", name)), b"\0asm").unwrap();
+    }
+
+    fn git(directory: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(&["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(directory)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn install_from_path() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let project_dir = tmp_dir.path().join("project");
+        fs::create_dir_all(&project_dir).unwrap();
+        write_package(&tmp_dir.path().join("foo"), "foo", "1.2.0");
+
+        let mut packages = HashSet::new();
+        packages.insert(PackageKey::new_path_package(
+            "_/foo",
+            PathBuf::from("../foo"),
+        ));
+        let installed_packages = SourcePackages { packages }.install(&project_dir).unwrap();
+
+        assert_eq!(1, installed_packages.packages.len());
+        let (key, _manifest, resolved_source, ..) = &installed_packages.packages[0];
+        assert_eq!("_/foo", key.name);
+        assert_eq!(semver::Version::new(1, 2, 0), key.version);
+        assert_eq!("path+../foo", resolved_source);
+        assert!(project_dir
+            .join(PACKAGES_DIR_NAME)
+            .join("_/foo@1.2.0/foo.wasm")
+            .is_file());
+    }
+
+    #[test]
+    fn reject_package_with_other_name() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        write_package(&tmp_dir.path().join("bar"), "bar", "1.0.0");
+
+        let mut packages = HashSet::new();
+        packages.insert(PackageKey::new_path_package("_/foo", PathBuf::from("bar")));
+        let result = SourcePackages { packages }.install(tmp_dir.path());
+
+        match result {
+            Err(Error::NameMismatch(_, name)) => assert_eq!("_/bar", name),
+            _ => panic!("Expected the package name to mismatch."),
+        }
+    }
+
+    #[test]
+    fn install_from_git() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let repo_dir = tmp_dir.path().join("repo");
+        write_package(&repo_dir, "foo", "0.1.0");
+        git(&repo_dir, &["init", "--quiet"]);
+        git(&repo_dir, &["add", "."]);
+        git(&repo_dir, &["commit", "--quiet", "-m", "initial"]);
+        git(&repo_dir, &["tag", "v0.1.0"]);
+        let url = repo_dir.to_string_lossy().to_string();
+
+        let mut packages = HashSet::new();
+        packages.insert(PackageKey::new_git_package(
+            "_/foo",
+            url.clone(),
+            Some("v0.1.0".to_string()),
+        ));
+        let installed_packages = SourcePackages { packages }.install(tmp_dir.path()).unwrap();
+
+        let (key, _manifest, resolved_source, ..) = &installed_packages.packages[0];
+        assert_eq!(semver::Version::new(0, 1, 0), key.version);
+        let prefix = format!("git+{}?rev=v0.1.0#", url);
+        assert!(resolved_source.starts_with(&prefix));
+        assert_eq!(40, resolved_source[prefix.len()..].len());
+        let package_dir = tmp_dir.path().join(PACKAGES_DIR_NAME).join("_/foo@0.1.0");
+        assert!(package_dir.join("foo.wasm").is_file());
+        assert!(!package_dir.join(".git").exists());
+    }
+
+    #[test]
+    fn git_options_in_the_manifest_are_rejected() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let marker = tmp_dir.path().join("marker");
+        let url = format!("--upload-pack=touch {}", marker.display());
+
+        let mut packages = HashSet::new();
+        packages.insert(PackageKey::new_git_package("_/foo", url, None));
+        let result = SourcePackages { packages }.install(tmp_dir.path());
+        assert!(matches!(result, Err(Error::GitError(_, _))));
+        assert!(!marker.exists());
+
+        let repo_dir = tmp_dir.path().join("repo");
+        write_package(&repo_dir, "foo", "0.1.0");
+        git(&repo_dir, &["init", "--quiet"]);
+        git(&repo_dir, &["add", "."]);
+        git(&repo_dir, &["commit", "--quiet", "-m", "initial"]);
+        let mut packages = HashSet::new();
+        packages.insert(PackageKey::new_git_package(
+            "_/foo",
+            repo_dir.to_string_lossy().to_string(),
+            Some("--orphan=evil".to_string()),
+        ));
+        let result = SourcePackages { packages }.install(tmp_dir.path());
+        assert!(matches!(result, Err(Error::GitError(_, _))));
+    }
+}
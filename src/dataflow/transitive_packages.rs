@@ -0,0 +1,447 @@
+use crate::data::manifest::PACKAGES_DIR_NAME;
+use crate::dataflow::added_packages::AddedPackages;
+use crate::dataflow::installed_packages::{self, Install, InstalledPackages, KeyCheck};
+use crate::dataflow::lockfile_packages::{LockfileError, LockfilePackages};
+use crate::dataflow::manifest_packages::{self, ManifestPackages, ManifestResult};
+use crate::dataflow::resolved_packages::{self, PackageRegistries, Resolve, ResolvedPackages};
+use crate::dataflow::{PackageKey, WapmPackageKey};
+use crate::util::fully_qualified_package_display_name;
+use std::cmp::Ordering;
+use std::collections::hash_set::HashSet;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Clone, Debug, Error)]
+pub enum Error {
+    #[error("Could not read the dependencies of installed package \"{0}\". {1}")]
+    DependencyManifestError(String, manifest_packages::Error),
+    #[error("Could not resolve dependencies of dependencies. {0}")]
+    ResolveError(resolved_packages::Error),
+    #[error("Could not install dependencies of dependencies. {0}")]
+    InstallError(installed_packages::Error),
+    #[error("Could not generate lockfile data for dependencies of dependencies. {0}")]
+    LockfileError(LockfileError),
+    #[error("Package \"{0}\" depends on {1}. Path and git dependencies are only supported in the manifest of the project.")]
+    UnsupportedSourceDependency(String, String),
+}
+
+/// Packages that are not requested directly, but are required by the dependencies of the
+/// manifest (or of globally installed packages). The commands of these packages are flagged as
+/// not being top-level dependencies.
+#[derive(Clone, Debug, Default)]
+pub struct TransitivePackages<'a> {
+    pub packages: LockfilePackages<'a>,
+}
+
+impl<'a> TransitivePackages<'a> {
+    /// Walk the dependency graph starting from the installed top-level packages. Dependencies that
+    /// are already part of the graph, or that are locked in the existing lockfile and still
+    /// installed, are reused. Everything else is resolved and installed into the directory.
+    /// Versions picked by the version solver are passed in as `solved_keys` and take precedence
+    /// over both the lockfile and the registry. A dependency is resolved from the registry of the
+    /// package that requires it, unless the project chose its registry, and the inherited
+    /// registries are added to `registries`. The registries named in the manifests of dependencies
+    /// are not used.
+    pub fn install<Resolver, Installer>(
+        directory: &Path,
+        top_level_keys: HashSet<PackageKey<'a>>,
+        lockfile_packages: &LockfilePackages<'a>,
+        solved_keys: &HashSet<PackageKey<'a>>,
+        registries: &mut PackageRegistries,
+        key_check: KeyCheck,
+    ) -> Result<Self, Error>
+    where
+        Resolver: Resolve<'a>,
+        Installer: Install<'a>,
+    {
+        for key in top_level_keys.iter() {
+            registries.declare(key.name());
+        }
+        let missing_packages = lockfile_packages.find_missing_packages(directory);
+        let mut packages = LockfilePackages::default();
+        let mut visited_keys = top_level_keys.clone();
+        let mut frontier = top_level_keys;
+
+        while !frontier.is_empty() {
+            let mut next_frontier = HashSet::new();
+            let mut packages_to_install = HashSet::new();
+
+            for dependency in Self::dependencies_of(directory, &frontier, registries)? {
+                if visited_keys.iter().any(|key| key.satisfies(&dependency)) {
+                    continue;
+                }
+                // prefer the solved version, then the newest version that is already locked
+                let chosen_key = solved_keys
+                    .iter()
+                    .find(|key| key.satisfies(&dependency))
+                    .or_else(|| {
+                        lockfile_packages
+                            .packages
+                            .keys()
+                            .filter(|key| key.satisfies(&dependency))
+                            .max_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+                    });
+                let locked_package = chosen_key
+                    .filter(|key| !missing_packages.contains(key))
+                    .and_then(|key| lockfile_packages.packages.get(key));
+                match (chosen_key, locked_package) {
+                    (Some(key), Some(data)) => {
+                        packages.packages.insert(key.clone(), data.clone());
+                        visited_keys.insert(key.clone());
+                        next_frontier.insert(key.clone());
+                    }
+                    // install the exact version, e.g. if the locked version was deleted
+                    (Some(key), None) => {
+                        packages_to_install.insert(key.clone());
+                    }
+                    (None, _) => {
+                        packages_to_install.insert(dependency);
+                    }
+                }
+            }
+
+            if !packages_to_install.is_empty() {
+                let resolved_packages = ResolvedPackages::new_from_added_packages::<Resolver>(
+                    AddedPackages {
+                        packages: packages_to_install,
+                    },
+                    registries,
+                )
+                .map_err(Error::ResolveError)?;
+                let installed_packages = InstalledPackages::install::<Installer>(
+                    directory,
+                    resolved_packages,
+                    lockfile_packages,
+                    registries,
+                    key_check,
+                )
+                .map_err(Error::InstallError)?;
+                let installed_lockfile_packages =
+                    LockfilePackages::from_installed_packages(&installed_packages)
+                        .map_err(Error::LockfileError)?;
+                for key in installed_lockfile_packages.package_keys() {
+                    visited_keys.insert(key.clone());
+                    next_frontier.insert(key);
+                }
+                packages.extend(installed_lockfile_packages);
+            }
+
+            frontier = next_frontier;
+        }
+
+        packages.mark_as_transitive();
+        Ok(Self { packages })
+    }
+
+    /// Collect the dependencies listed in the manifests of the given installed packages, which
+    /// inherit the registry of the package. Packages without a manifest in `wapm_packages`, such
+    /// as the local package, have no dependencies here.
+    fn dependencies_of(
+        directory: &Path,
+        package_keys: &HashSet<PackageKey<'a>>,
+        registries: &mut PackageRegistries,
+    ) -> Result<HashSet<PackageKey<'a>>, Error> {
+        let mut dependencies = HashSet::new();
+        for package_key in package_keys {
+            let key = match package_key {
+                PackageKey::WapmPackage(key) => key,
+                _ => continue,
+            };
+            let WapmPackageKey { name, version } = key;
+            let package_dir = directory
+                .join(PACKAGES_DIR_NAME)
+                .join(fully_qualified_package_display_name(name, version));
+            let manifest = match ManifestResult::find_in_directory(&package_dir) {
+                ManifestResult::Manifest(manifest) => manifest,
+                ManifestResult::NoManifest => continue,
+                ManifestResult::ManifestError(e) => {
+                    return Err(Error::DependencyManifestError(key.to_string(), e));
+                }
+            };
+            let manifest_packages = ManifestPackages::new_from_manifest(&manifest)
+                .map_err(|e| Error::DependencyManifestError(key.to_string(), e))?;
+            for dependency in manifest_packages.packages {
+                if dependency.is_source_package() {
+                    return Err(Error::UnsupportedSourceDependency(
+                        key.to_string(),
+                        dependency.to_string(),
+                    ));
+                }
+                registries
+                    .inherit(name, dependency.name())
+                    .map_err(Error::ResolveError)?;
+                dependencies.insert(dependency.into_owned());
+            }
+        }
+        Ok(dependencies)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::manifest::{MANIFEST_FILE_NAME, PACKAGES_DIR_NAME};
+    use crate::dataflow::installed_packages::{
+        Error as InstallError, Install, KeyCheck, UnpackedPackage,
+    };
+    use crate::dataflow::lockfile_packages::{LockfilePackage, LockfilePackages};
+    use crate::dataflow::package_cache::PackageSignature;
+    use crate::dataflow::resolved_packages::{Error as ResolveError, PackageRegistries, Resolve};
+    use crate::dataflow::transitive_packages::{Error, TransitivePackages};
+    use crate::dataflow::{PackageKey, WapmPackageKey, WapmPackageRange};
+    use crate::keys;
+    use crate::util::create_temp_dir;
+    use std::collections::hash_set::HashSet;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+
+    fn write_manifest(directory: &Path, name: &str, version: &str, dependencies: &str) {
+        let package_dir = directory
+            .join(PACKAGES_DIR_NAME)
+            .join(format!("{}@{}", name, version));
+        fs::create_dir_all(&package_dir).unwrap();
+        let manifest = format!(
+            r#"
+[package]
+name = "{}"
+version = "{}"
+description = "test package"
+
+[[module]]
+name = "{}"
+source = "{}.wasm"
+abi = "wasi"
+
+[[command]]
+name = "{}"
+module = "{}"
+
+[dependencies]
+{}
+"#,
+            name, version, name, name, name, name, dependencies
+        );
+        fs::write(package_dir.join(MANIFEST_FILE_NAME), manifest).unwrap();
+    }
+
+    /// Resolves every range to version 1.5.0 of the package. Packages named "_/private..." are only
+    /// in the "internal" registry.
+    struct TestResolver;
+
+    impl<'a> Resolve<'a> for TestResolver {
+        fn sync_packages(
+            added_packages: Vec<PackageKey<'a>>,
+            registries: &PackageRegistries,
+        ) -> Result<
+            Vec<(
+                WapmPackageKey<'a>,
+                (String, Option<keys::WapmPackageSignature>),
+            )>,
+            ResolveError,
+        > {
+            for key in added_packages.iter() {
+                if key.name().starts_with("_/private")
+                    && registries.registry(key.name()) != Some("internal")
+                {
+                    return Err(ResolveError::CouldNotResolvePackages(key.to_string()));
+                }
+            }
+            Ok(added_packages
+                .into_iter()
+                .map(|k| match k {
+                    PackageKey::WapmPackage(key) => (key, ("url".to_string(), None)),
+                    PackageKey::WapmPackageRange(WapmPackageRange { name, .. }) => (
+                        WapmPackageKey {
+                            name,
+                            version: semver::Version::new(1, 5, 0),
+                        },
+                        ("url".to_string(), None),
+                    ),
+                    _ => unreachable!("Source packages are never resolved."),
+                })
+                .collect())
+        }
+
+        fn get_package_versions(
+            _package_names: Vec<String>,
+            _registries: &PackageRegistries,
+        ) -> Result<HashMap<String, Vec<semver::Version>>, ResolveError> {
+            Ok(HashMap::new())
+        }
+
+        fn get_package_dependencies(
+            _package_name: &str,
+            _version: &semver::Version,
+            _registries: &PackageRegistries,
+        ) -> Result<Vec<(String, semver::VersionReq)>, ResolveError> {
+            Ok(vec![])
+        }
+    }
+
+    /// Installs "_/bar" which depends on "baz" and "_/private" which depends on "_/private-util",
+    /// all other packages have no dependencies.
+    struct TestInstaller;
+
+    impl<'a> Install<'a> for TestInstaller {
+        fn install_package(
+            directory: &Path,
+            key: WapmPackageKey<'a>,
+            download_url: &str,
+            _registry: Option<&str>,
+            _expected_integrity: Option<&str>,
+            _signature: Option<PackageSignature>,
+            _key_check: KeyCheck,
+        ) -> Result<UnpackedPackage<'a>, InstallError> {
+            let version = key.version.to_string();
+            let dependencies = match key.name.as_ref() {
+                "_/bar" => r#"baz = "2.0.0""#,
+                "_/private" => r#""_/private-util" = "^1""#,
+                _ => "",
+            };
+            write_manifest(directory, &key.name, &version, dependencies);
+            let package_dir = directory
+                .join(PACKAGES_DIR_NAME)
+                .join(format!("{}@{}", key.name, version));
+            Ok((key, package_dir, download_url.to_string(), None, None))
+        }
+    }
+
+    #[test]
+    fn install_dependencies_of_dependencies() {
+        let tmp_dir = create_temp_dir().unwrap();
+        write_manifest(&tmp_dir, "_/foo", "1.0.0", r#""_/bar" = "^1""#);
+
+        let mut top_level_keys = HashSet::new();
+        top_level_keys.insert(PackageKey::new_registry_package(
+            "_/foo",
+            semver::Version::new(1, 0, 0),
+        ));
+
+        let transitive_packages = TransitivePackages::install::<TestResolver, TestInstaller>(
+            &tmp_dir,
+            top_level_keys,
+            &LockfilePackages::default(),
+            &HashSet::new(),
+            &mut PackageRegistries::default(),
+            KeyCheck::Prompt,
+        )
+        .unwrap();
+
+        let packages = transitive_packages.packages.packages;
+        assert_eq!(2, packages.len());
+        let bar = packages
+            .get(&PackageKey::new_registry_package(
+                "_/bar",
+                semver::Version::new(1, 5, 0),
+            ))
+            .unwrap();
+        assert_eq!(1, bar.commands.len());
+        assert!(!bar.commands[0].is_top_level_dependency);
+        assert!(packages.contains_key(&PackageKey::new_registry_package(
+            "_/baz",
+            semver::Version::new(2, 0, 0)
+        )));
+    }
+
+    #[test]
+    fn reuse_locked_dependencies() {
+        let tmp_dir = create_temp_dir().unwrap();
+        write_manifest(&tmp_dir, "_/foo", "1.0.0", r#""_/bar" = "^1""#);
+        write_manifest(&tmp_dir, "_/bar", "1.1.0", "");
+
+        let mut top_level_keys = HashSet::new();
+        top_level_keys.insert(PackageKey::new_registry_package(
+            "_/foo",
+            semver::Version::new(1, 0, 0),
+        ));
+        let mut locked_packages = HashMap::new();
+        locked_packages.insert(
+            PackageKey::new_registry_package("_/bar", semver::Version::new(1, 1, 0)),
+            LockfilePackage::default(),
+        );
+        let lockfile_packages = LockfilePackages {
+            packages: locked_packages,
+        };
+
+        let transitive_packages = TransitivePackages::install::<TestResolver, TestInstaller>(
+            &tmp_dir,
+            top_level_keys,
+            &lockfile_packages,
+            &HashSet::new(),
+            &mut PackageRegistries::default(),
+            KeyCheck::Prompt,
+        )
+        .unwrap();
+
+        let packages = transitive_packages.packages.packages;
+        assert_eq!(1, packages.len());
+        assert!(packages.contains_key(&PackageKey::new_registry_package(
+            "_/bar",
+            semver::Version::new(1, 1, 0)
+        )));
+    }
+
+    #[test]
+    fn install_dependencies_from_the_registry_of_their_package() {
+        let tmp_dir = create_temp_dir().unwrap();
+        write_manifest(&tmp_dir, "_/foo", "1.0.0", r#""_/private" = "^1""#);
+
+        let mut top_level_keys = HashSet::new();
+        top_level_keys.insert(PackageKey::new_registry_package(
+            "_/foo",
+            semver::Version::new(1, 0, 0),
+        ));
+        let mut registries = PackageRegistries::default();
+        registries.insert("_/foo", "internal".to_string());
+
+        let transitive_packages = TransitivePackages::install::<TestResolver, TestInstaller>(
+            &tmp_dir,
+            top_level_keys,
+            &LockfilePackages::default(),
+            &HashSet::new(),
+            &mut registries,
+            KeyCheck::Prompt,
+        )
+        .unwrap();
+
+        assert!(transitive_packages.packages.packages.contains_key(
+            &PackageKey::new_registry_package("_/private-util", semver::Version::new(1, 5, 0))
+        ));
+        assert_eq!(Some("internal"), registries.registry("_/private"));
+        assert_eq!(Some("internal"), registries.registry("_/private-util"));
+    }
+
+    #[test]
+    fn reject_dependencies_required_from_two_registries() {
+        let tmp_dir = create_temp_dir().unwrap();
+        write_manifest(&tmp_dir, "_/foo", "1.0.0", r#""_/baz" = "^1""#);
+        write_manifest(&tmp_dir, "_/qux", "1.0.0", r#""_/baz" = "^1""#);
+
+        let mut top_level_keys = HashSet::new();
+        top_level_keys.insert(PackageKey::new_registry_package(
+            "_/foo",
+            semver::Version::new(1, 0, 0),
+        ));
+        top_level_keys.insert(PackageKey::new_registry_package(
+            "_/qux",
+            semver::Version::new(1, 0, 0),
+        ));
+        let mut registries = PackageRegistries::default();
+        registries.insert("_/foo", "internal".to_string());
+
+        let result = TransitivePackages::install::<TestResolver, TestInstaller>(
+            &tmp_dir,
+            top_level_keys,
+            &LockfilePackages::default(),
+            &HashSet::new(),
+            &mut registries,
+            KeyCheck::Prompt,
+        );
+        match result {
+            Err(Error::ResolveError(ResolveError::AmbiguousRegistry { package, .. })) => {
+                assert_eq!("_/baz", package)
+            }
+            other => panic!("expected an ambiguous registry, found {:?}", other),
+        }
+    }
+}
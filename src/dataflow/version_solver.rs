@@ -0,0 +1,818 @@
+use crate::dataflow::resolved_packages::{self, registry_display_name, PackageRegistries, Resolve};
+use crate::dataflow::{PackageKey, WapmPackageKey, WapmPackageRange};
+use crate::exit_code::{Classify, ErrorKind};
+use semver::{Version, VersionReq};
+use std::collections::btree_map::BTreeMap;
+use std::collections::btree_set::BTreeSet;
+use std::collections::hash_map::HashMap;
+use std::collections::hash_set::HashSet;
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Clone, Debug, Error)]
+pub enum Error {
+    #[error("{0}")]
+    ResolveError(resolved_packages::Error),
+    #[error("No set of package versions satisfies all requirements.\n{0}")]
+    Conflict(Incompatibility),
+}
+
+impl Classify for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::ResolveError(e) => e.kind(),
+            Error::Conflict(_) => ErrorKind::Dependency,
+        }
+    }
+}
+
+/// A version requirement on a package, remembering who declared it and the registry the package
+/// is resolved from, `None` for the default registry. `parent` is the package version that
+/// declared the requirement, `None` for requirements of the root.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Requirement {
+    pub name: String,
+    pub version_req: VersionReq,
+    pub required_by: String,
+    pub registry: Option<String>,
+    pub parent: Option<(String, Version)>,
+}
+
+impl Requirement {
+    /// Packages from a path or a git repository are not part of the solve.
+    fn from_package_key(
+        required_by: &str,
+        key: &PackageKey,
+        registries: &PackageRegistries,
+    ) -> Option<Self> {
+        let (name, version_req) = match key {
+            PackageKey::WapmPackage(WapmPackageKey { name, version }) => {
+                (name.to_string(), VersionReq::exact(version))
+            }
+            PackageKey::WapmPackageRange(WapmPackageRange { name, version_req }) => {
+                (name.to_string(), version_req.clone())
+            }
+            PackageKey::PathPackage(_) | PackageKey::GitPackage(_) => return None,
+        };
+        Some(Self {
+            registry: registries.registry(&name).map(str::to_string),
+            name,
+            version_req,
+            required_by: required_by.to_string(),
+            parent: None,
+        })
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{} requires {} {}",
+            self.required_by, self.name, self.version_req
+        )
+    }
+}
+
+/// The reason a set of requirements could not be satisfied. Forms a derivation that is printed
+/// from the outermost decision down to the requirements that actually clash.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Incompatibility {
+    /// No published version of the package is within all of the required ranges.
+    NoMatchingVersion {
+        name: String,
+        requirements: Vec<Requirement>,
+        available: Vec<Version>,
+    },
+    /// A version of the package was already selected when a new requirement excluded it.
+    Excluded {
+        name: String,
+        version: Version,
+        requirements: Vec<Requirement>,
+    },
+    /// Every version of the package within the required ranges led to another conflict, which is
+    /// kept for each version that was tried.
+    Exhausted {
+        name: String,
+        requirements: Vec<Requirement>,
+        causes: Vec<(Version, Incompatibility)>,
+    },
+}
+
+impl Incompatibility {
+    fn write_indented(&self, f: &mut fmt::Formatter, indent: usize) -> Result<(), fmt::Error> {
+        let pad = "  ".repeat(indent);
+        let requirements = match self {
+            Incompatibility::NoMatchingVersion { requirements, .. }
+            | Incompatibility::Excluded { requirements, .. }
+            | Incompatibility::Exhausted { requirements, .. } => requirements,
+        };
+        for (i, requirement) in requirements.iter().enumerate() {
+            let prefix = if i == 0 { "Because" } else { "and" };
+            writeln!(f, "{}{} {}", pad, prefix, requirement)?;
+        }
+        match self {
+            Incompatibility::NoMatchingVersion {
+                name, available, ..
+            } => {
+                if available.is_empty() {
+                    write!(f, "{}no version of {} could be found.", pad, name)
+                } else {
+                    write!(
+                        f,
+                        "{}no version of {} satisfies all requirements (available: {}).",
+                        pad,
+                        name,
+                        join_versions(available)
+                    )
+                }
+            }
+            Incompatibility::Excluded { name, version, .. } => write!(
+                f,
+                "{}the already selected version {} {} cannot satisfy all requirements.",
+                pad, name, version
+            ),
+            Incompatibility::Exhausted { name, causes, .. } => {
+                let tried: Vec<Version> = causes.iter().map(|(v, _)| v.clone()).collect();
+                write!(
+                    f,
+                    "{}every matching version of {} ({}) leads to a conflict:",
+                    pad,
+                    name,
+                    join_versions(&tried)
+                )?;
+                for (version, cause) in causes {
+                    writeln!(f)?;
+                    writeln!(f, "{}  with {} {}:", pad, name, version)?;
+                    cause.write_indented(f, indent + 2)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Incompatibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        self.write_indented(f, 0)
+    }
+}
+
+/// The packages a version depends on and the version ranges it requires of them.
+type Dependencies = Vec<(String, VersionReq)>;
+
+/// A set of decisions that cannot all be part of a solution, and the reason why.
+#[derive(Clone, Debug)]
+struct Conflict {
+    decisions: BTreeMap<String, Version>,
+    incompatibility: Incompatibility,
+}
+
+/// The decisions that declared the requirements. Requirements of the root need none.
+fn parents<'r>(
+    requirements: impl IntoIterator<Item = &'r Requirement>,
+) -> BTreeMap<String, Version> {
+    requirements
+        .into_iter()
+        .filter_map(|r| r.parent.clone())
+        .collect()
+}
+
+/// The registry each required package is resolved from, `None` for the default registry.
+/// Fails if a package is required from more than one registry.
+fn package_registries(requirements: &[Requirement]) -> Result<BTreeMap<&str, Option<&str>>, Error> {
+    let mut first_requirements: BTreeMap<&str, &Requirement> = BTreeMap::new();
+    for requirement in requirements {
+        match first_requirements.get(requirement.name.as_str()) {
+            Some(other) if other.registry != requirement.registry => {
+                return Err(Error::ResolveError(
+                    resolved_packages::Error::AmbiguousRegistry {
+                        package: requirement.name.clone(),
+                        registry: registry_display_name(requirement.registry.as_deref()),
+                        required_by: requirement.required_by.clone(),
+                        other_registry: registry_display_name(other.registry.as_deref()),
+                        other_required_by: other.required_by.clone(),
+                    },
+                ));
+            }
+            Some(_) => {}
+            None => {
+                first_requirements.insert(&requirement.name, requirement);
+            }
+        }
+    }
+    Ok(first_requirements
+        .into_iter()
+        .map(|(name, requirement)| (name, requirement.registry.as_deref()))
+        .collect())
+}
+
+fn join_versions(versions: &[Version]) -> String {
+    versions
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Find a single version of every package in the dependency graph such that all requirements are
+/// satisfied. The requirements are labeled as coming from `root` in conflict explanations.
+/// Preferred keys, typically the contents of the existing lockfile, are chosen over newer
+/// versions whenever they are compatible. Package versions are fetched through the `Resolver`,
+/// from the registry of each package. The dependencies of a package are fetched from its registry,
+/// unless they are required by `root` as well.
+pub fn solve<'a, Resolver>(
+    root: &str,
+    requirements: &HashSet<PackageKey<'a>>,
+    preferred: &HashSet<PackageKey<'a>>,
+    registries: &PackageRegistries,
+) -> Result<HashSet<PackageKey<'static>>, Error>
+where
+    Resolver: Resolve<'a>,
+{
+    // sort the root requirements so that the search and its explanations are stable
+    let mut requirements: Vec<Requirement> = requirements
+        .iter()
+        .filter_map(|key| Requirement::from_package_key(root, key, registries))
+        .collect();
+    requirements.sort_by(|a, b| a.name.cmp(&b.name));
+    let preferred = preferred
+        .iter()
+        .filter_map(|key| match key {
+            PackageKey::WapmPackage(WapmPackageKey { name, version }) => {
+                Some((name.to_string(), version.clone()))
+            }
+            _ => None,
+        })
+        .collect();
+    // the registries of the root requirements are not inherited by other packages
+    let mut registries = registries.clone();
+    for requirement in requirements.iter() {
+        registries.declare(&requirement.name);
+    }
+    let mut solver = VersionSolver {
+        versions: HashMap::new(),
+        dependencies: HashMap::new(),
+        preferred,
+        registries,
+        learned: vec![],
+    };
+    let decisions = solver
+        .search::<Resolver>(BTreeMap::new(), requirements)?
+        .map_err(|conflict| Error::Conflict(conflict.incompatibility))?;
+    Ok(decisions
+        .into_iter()
+        .map(|(name, version)| PackageKey::new_registry_package(name, version))
+        .collect())
+}
+
+/// A backtracking solver. Packages with the fewest remaining candidates are decided first, and
+/// the newest (or preferred) candidate is tried before older ones. Versions are kept per registry
+/// and package name, the dependencies of a version are only fetched once it is tried.
+///
+/// Every conflict is recorded with the decisions that caused it. Branches that contain all of the
+/// decisions of a recorded conflict are skipped, and when a conflict does not depend on the latest
+/// decision, the search jumps back past it instead of trying its other candidates.
+struct VersionSolver {
+    versions: HashMap<(Option<String>, String), Vec<Version>>,
+    dependencies: HashMap<(Option<String>, String, Version), Dependencies>,
+    preferred: HashMap<String, Version>,
+    registries: PackageRegistries,
+    learned: Vec<Conflict>,
+}
+
+impl VersionSolver {
+    fn search<'a, Resolver>(
+        &mut self,
+        decisions: BTreeMap<String, Version>,
+        requirements: Vec<Requirement>,
+    ) -> Result<Result<BTreeMap<String, Version>, Conflict>, Error>
+    where
+        Resolver: Resolve<'a>,
+    {
+        if let Some(conflict) = self.learned.iter().find(|conflict| {
+            conflict
+                .decisions
+                .iter()
+                .all(|(name, version)| decisions.get(name) == Some(version))
+        }) {
+            return Ok(Err(conflict.clone()));
+        }
+
+        let registries = package_registries(&requirements)?;
+        self.fetch::<Resolver>(&registries)?;
+
+        let name = match registries
+            .iter()
+            .filter(|(name, _)| !decisions.contains_key(**name))
+            .min_by_key(|(name, registry)| self.candidates(name, **registry, &requirements).len())
+        {
+            Some((name, _)) => name.to_string(),
+            None => return Ok(Ok(decisions)),
+        };
+        let registry = registries[name.as_str()];
+
+        let requirements_on_package: Vec<Requirement> = requirements
+            .iter()
+            .filter(|r| r.name == name)
+            .cloned()
+            .collect();
+        let candidates = self.candidates(&name, registry, &requirements);
+        if candidates.is_empty() {
+            return Ok(Err(self.learn(Conflict {
+                decisions: parents(&requirements_on_package),
+                incompatibility: Incompatibility::NoMatchingVersion {
+                    available: self.versions(&name, registry).to_vec(),
+                    name,
+                    requirements: requirements_on_package,
+                },
+            })));
+        }
+
+        let mut conflict_decisions = parents(&requirements_on_package);
+        let mut causes = vec![];
+        for candidate in candidates {
+            let required_by = format!("{} {}", name, candidate);
+            let new_requirements: Vec<Requirement> = self
+                .dependencies::<Resolver>(&name, registry, &candidate)?
+                .iter()
+                .map(|(dependency, version_req)| Requirement {
+                    name: dependency.clone(),
+                    version_req: version_req.clone(),
+                    required_by: required_by.clone(),
+                    registry: self.registries.dependency_registry(registry, dependency),
+                    parent: Some((name.clone(), candidate.clone())),
+                })
+                .collect();
+
+            // a new requirement may exclude a version that was selected earlier
+            let excluded = new_requirements.iter().find_map(|requirement| {
+                match decisions.get(&requirement.name) {
+                    Some(version) if !requirement.version_req.matches(version) => Some(Conflict {
+                        decisions: vec![
+                            (requirement.name.clone(), version.clone()),
+                            (name.clone(), candidate.clone()),
+                        ]
+                        .into_iter()
+                        .collect(),
+                        incompatibility: Incompatibility::Excluded {
+                            name: requirement.name.clone(),
+                            version: version.clone(),
+                            requirements: requirements
+                                .iter()
+                                .filter(|r| r.name == requirement.name)
+                                .chain(std::iter::once(requirement))
+                                .cloned()
+                                .collect(),
+                        },
+                    }),
+                    _ => None,
+                }
+            });
+            let result = match excluded {
+                Some(conflict) => Err(conflict),
+                None => {
+                    let mut decisions = decisions.clone();
+                    decisions.insert(name.clone(), candidate.clone());
+                    let mut requirements = requirements.clone();
+                    requirements.extend(new_requirements);
+                    self.search::<Resolver>(decisions, requirements)?
+                }
+            };
+            match result {
+                Ok(decisions) => return Ok(Ok(decisions)),
+                // no other version of this package can avoid a conflict that does not involve it
+                Err(conflict) if conflict.decisions.get(&name) != Some(&candidate) => {
+                    return Ok(Err(conflict));
+                }
+                Err(mut conflict) => {
+                    conflict.decisions.remove(&name);
+                    conflict_decisions.append(&mut conflict.decisions);
+                    causes.push((candidate, conflict.incompatibility));
+                }
+            }
+        }
+
+        Ok(Err(self.learn(Conflict {
+            decisions: conflict_decisions,
+            incompatibility: Incompatibility::Exhausted {
+                name,
+                requirements: requirements_on_package,
+                causes,
+            },
+        })))
+    }
+
+    /// Remember a conflict, so that other branches containing its decisions are skipped.
+    fn learn(&mut self, conflict: Conflict) -> Conflict {
+        self.learned.push(conflict.clone());
+        conflict
+    }
+
+    /// The fetched versions of the package, from the registry it is required from.
+    fn versions(&self, name: &str, registry: Option<&str>) -> &[Version] {
+        self.versions
+            .get(&(registry.map(str::to_string), name.to_string()))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Versions of the package that satisfy every requirement on it, in the order to try them.
+    fn candidates(
+        &self,
+        name: &str,
+        registry: Option<&str>,
+        requirements: &[Requirement],
+    ) -> Vec<Version> {
+        let mut candidates: Vec<Version> = self
+            .versions(name, registry)
+            .iter()
+            .filter(|version| {
+                requirements
+                    .iter()
+                    .filter(|r| r.name == name)
+                    .all(|r| r.version_req.matches(version))
+            })
+            .cloned()
+            .collect();
+        let preferred = self.preferred.get(name);
+        candidates.sort_by(|a, b| {
+            let a_preferred = Some(a) == preferred;
+            let b_preferred = Some(b) == preferred;
+            b_preferred.cmp(&a_preferred).then_with(|| b.cmp(a))
+        });
+        candidates
+    }
+
+    /// Fetch the versions of every required package that has not been seen yet in one batch.
+    fn fetch<'a, Resolver>(
+        &mut self,
+        registries: &BTreeMap<&str, Option<&str>>,
+    ) -> Result<(), Error>
+    where
+        Resolver: Resolve<'a>,
+    {
+        let mut package_registries = self.registries.clone();
+        let mut keys = BTreeSet::new();
+        for (name, registry) in registries {
+            let key = (registry.map(str::to_string), name.to_string());
+            if self.versions.contains_key(&key) {
+                continue;
+            }
+            if let Some(registry) = registry {
+                package_registries.insert(name, registry.to_string());
+            }
+            keys.insert(key);
+        }
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let names = keys.iter().map(|(_, name)| name.clone()).collect();
+        let mut fetched = Resolver::get_package_versions(names, &package_registries)
+            .map_err(Error::ResolveError)?;
+        for key in keys {
+            let versions = fetched.remove(&key.1).unwrap_or_default();
+            self.versions.insert(key, versions);
+        }
+        Ok(())
+    }
+
+    /// The dependencies of a version of the package, fetched the first time it is tried.
+    fn dependencies<'a, Resolver>(
+        &mut self,
+        name: &str,
+        registry: Option<&str>,
+        version: &Version,
+    ) -> Result<Dependencies, Error>
+    where
+        Resolver: Resolve<'a>,
+    {
+        let key = (
+            registry.map(str::to_string),
+            name.to_string(),
+            version.clone(),
+        );
+        if let Some(dependencies) = self.dependencies.get(&key) {
+            return Ok(dependencies.clone());
+        }
+        let mut package_registries = self.registries.clone();
+        if let Some(registry) = registry {
+            package_registries.insert(name, registry.to_string());
+        }
+        let dependencies = Resolver::get_package_dependencies(name, version, &package_registries)
+            .map_err(Error::ResolveError)?;
+        self.dependencies.insert(key, dependencies.clone());
+        Ok(dependencies)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dataflow::resolved_packages::{Error as ResolveError, PackageRegistries, Resolve};
+    use crate::dataflow::version_solver::{solve, Error};
+    use crate::dataflow::{PackageKey, WapmPackageKey};
+    use crate::keys;
+    use semver::{Version, VersionReq};
+    use std::cell::RefCell;
+    use std::collections::hash_map::HashMap;
+    use std::collections::hash_set::HashSet;
+
+    type PackageVersion = (Version, Vec<(String, VersionReq)>);
+
+    fn package_version(version: &str, dependencies: &[(&str, &str)]) -> PackageVersion {
+        (
+            Version::parse(version).unwrap(),
+            dependencies
+                .iter()
+                .map(|(name, req)| (name.to_string(), VersionReq::parse(req).unwrap()))
+                .collect(),
+        )
+    }
+
+    thread_local! {
+        /// The package versions whose dependencies were fetched by the current test.
+        static FETCHED_DEPENDENCIES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// An in-memory registry. "_/foo" 2.0.0 depends on the 2.x series of "_/sqlite", while
+    /// "_/foo" 1.0.0 works with the 1.x series. Every version of "_/baz" depends on "_/qux", which
+    /// was never published. "_/private" and the "_/private-util" it depends on are in the
+    /// "internal" registry, but another "_/private-util" was published to the default registry,
+    /// which "_/public" depends on.
+    struct TestResolver;
+
+    impl TestResolver {
+        fn published(name: &str, registries: &PackageRegistries) -> Option<Vec<PackageVersion>> {
+            let mut registry = HashMap::new();
+            registry.insert(
+                "_/foo",
+                vec![
+                    package_version("1.0.0", &[("_/sqlite", "^1")]),
+                    package_version("2.0.0", &[("_/sqlite", "^2")]),
+                ],
+            );
+            registry.insert(
+                "_/bar",
+                vec![package_version("1.0.0", &[("_/sqlite", ">=1.1, <2")])],
+            );
+            registry.insert(
+                "_/sqlite",
+                vec![
+                    package_version("1.0.0", &[]),
+                    package_version("1.1.0", &[]),
+                    package_version("1.2.0", &[]),
+                    package_version("2.0.0", &[]),
+                ],
+            );
+            registry.insert(
+                "_/baz",
+                vec![
+                    package_version("1.0.0", &[("_/qux", "*")]),
+                    package_version("1.1.0", &[("_/qux", "*")]),
+                    package_version("1.2.0", &[("_/qux", "*")]),
+                ],
+            );
+            registry.insert("_/private-util", vec![package_version("9.9.9", &[])]);
+            registry.insert(
+                "_/public",
+                vec![package_version("1.0.0", &[("_/private-util", "*")])],
+            );
+            let mut internal_registry = HashMap::new();
+            internal_registry.insert(
+                "_/private",
+                vec![package_version("1.0.0", &[("_/private-util", "^1")])],
+            );
+            internal_registry.insert("_/private-util", vec![package_version("1.0.0", &[])]);
+            match registries.registry(name) {
+                Some("internal") => internal_registry.get(name).cloned(),
+                _ => registry.get(name).cloned(),
+            }
+        }
+    }
+
+    impl<'a> Resolve<'a> for TestResolver {
+        fn sync_packages(
+            _added_packages: Vec<PackageKey<'a>>,
+            _registries: &PackageRegistries,
+        ) -> Result<
+            Vec<(
+                WapmPackageKey<'a>,
+                (String, Option<keys::WapmPackageSignature>),
+            )>,
+            ResolveError,
+        > {
+            Ok(vec![])
+        }
+
+        fn get_package_versions(
+            package_names: Vec<String>,
+            registries: &PackageRegistries,
+        ) -> Result<HashMap<String, Vec<Version>>, ResolveError> {
+            Ok(package_names
+                .into_iter()
+                .filter_map(|name| {
+                    let versions = Self::published(&name, registries)?;
+                    Some((name, versions.into_iter().map(|(v, _)| v).collect()))
+                })
+                .collect())
+        }
+
+        fn get_package_dependencies(
+            package_name: &str,
+            version: &Version,
+            registries: &PackageRegistries,
+        ) -> Result<Vec<(String, VersionReq)>, ResolveError> {
+            FETCHED_DEPENDENCIES.with(|fetched| {
+                fetched
+                    .borrow_mut()
+                    .push(format!("{} {}", package_name, version))
+            });
+            Self::published(package_name, registries)
+                .into_iter()
+                .flatten()
+                .find(|(v, _)| v == version)
+                .map(|(_, dependencies)| dependencies)
+                .ok_or_else(|| ResolveError::CouldNotResolvePackages(package_name.to_string()))
+        }
+    }
+
+    fn requirements(keys: &[(&'static str, &str)]) -> HashSet<PackageKey<'static>> {
+        keys.iter()
+            .map(|(name, req)| {
+                PackageKey::new_registry_package_range(*name, VersionReq::parse(req).unwrap())
+            })
+            .collect()
+    }
+
+    fn key(name: &'static str, version: &str) -> PackageKey<'static> {
+        PackageKey::new_registry_package(name, Version::parse(version).unwrap())
+    }
+
+    #[test]
+    fn picks_newest_versions_satisfying_all_ranges() {
+        let solution = solve::<TestResolver>(
+            "wapm.toml",
+            &requirements(&[("_/bar", "^1"), ("_/sqlite", "<1.2")]),
+            &HashSet::new(),
+            &PackageRegistries::default(),
+        )
+        .unwrap();
+        let expected: HashSet<_> = vec![key("_/bar", "1.0.0"), key("_/sqlite", "1.1.0")]
+            .into_iter()
+            .collect();
+        assert_eq!(expected, solution);
+        // only the dependencies of the versions that were tried are fetched
+        let fetched = FETCHED_DEPENDENCIES.with(|fetched| fetched.borrow().clone());
+        assert_eq!(vec!["_/bar 1.0.0", "_/sqlite 1.1.0"], fetched);
+    }
+
+    #[test]
+    fn backtracks_to_older_version() {
+        let solution = solve::<TestResolver>(
+            "wapm.toml",
+            &requirements(&[("_/foo", "*"), ("_/bar", "^1")]),
+            &HashSet::new(),
+            &PackageRegistries::default(),
+        )
+        .unwrap();
+        assert!(solution.contains(&key("_/foo", "1.0.0")));
+        assert!(solution.contains(&key("_/sqlite", "1.2.0")));
+        assert_eq!(3, solution.len());
+    }
+
+    #[test]
+    fn prefers_locked_versions() {
+        let mut preferred = HashSet::new();
+        preferred.insert(key("_/sqlite", "1.0.0"));
+        let solution = solve::<TestResolver>(
+            "wapm.toml",
+            &requirements(&[("_/foo", "^1")]),
+            &preferred,
+            &PackageRegistries::default(),
+        )
+        .unwrap();
+        assert!(solution.contains(&key("_/sqlite", "1.0.0")));
+    }
+
+    #[test]
+    fn explains_conflicts() {
+        let result = solve::<TestResolver>(
+            "wapm.toml",
+            &requirements(&[("_/foo", "^2"), ("_/sqlite", "^1")]),
+            &HashSet::new(),
+            &PackageRegistries::default(),
+        );
+        let explanation = match result {
+            Err(Error::Conflict(incompatibility)) => incompatibility.to_string(),
+            other => panic!("expected a conflict, found {:?}", other),
+        };
+        assert!(explanation.contains("and _/foo 2.0.0 requires _/sqlite >=2.0.0, <3.0.0"));
+        assert!(explanation.contains("Because wapm.toml requires _/sqlite >=1.0.0, <2.0.0"));
+        assert!(explanation.contains("no version of _/sqlite satisfies all requirements"));
+    }
+
+    #[test]
+    fn explains_missing_packages() {
+        let result = solve::<TestResolver>(
+            "wapm.toml",
+            &requirements(&[("_/qux", "*")]),
+            &HashSet::new(),
+            &PackageRegistries::default(),
+        );
+        let explanation = match result {
+            Err(Error::Conflict(incompatibility)) => incompatibility.to_string(),
+            other => panic!("expected a conflict, found {:?}", other),
+        };
+        assert!(explanation.contains("no version of _/qux could be found"));
+    }
+
+    #[test]
+    fn explains_every_tried_version() {
+        let result = solve::<TestResolver>(
+            "wapm.toml",
+            &requirements(&[("_/baz", "^1")]),
+            &HashSet::new(),
+            &PackageRegistries::default(),
+        );
+        let explanation = match result {
+            Err(Error::Conflict(incompatibility)) => incompatibility.to_string(),
+            other => panic!("expected a conflict, found {:?}", other),
+        };
+        assert!(explanation.contains("every matching version of _/baz (1.2.0, 1.1.0, 1.0.0)"));
+        for version in &["1.2.0", "1.1.0", "1.0.0"] {
+            assert!(explanation.contains(&format!("with _/baz {}:", version)));
+            assert!(explanation.contains(&format!("Because _/baz {} requires _/qux", version)));
+        }
+    }
+
+    #[test]
+    fn skips_decisions_unrelated_to_a_conflict() {
+        let result = solve::<TestResolver>(
+            "wapm.toml",
+            &requirements(&[("_/foo", "*"), ("_/baz", "^1")]),
+            &HashSet::new(),
+            &PackageRegistries::default(),
+        );
+        let explanation = match result {
+            Err(Error::Conflict(incompatibility)) => incompatibility.to_string(),
+            other => panic!("expected a conflict, found {:?}", other),
+        };
+        // "_/foo" is decided first, but no version of it can make "_/qux" available
+        assert!(!explanation.contains("_/foo"));
+        assert!(explanation.contains("no version of _/qux could be found"));
+    }
+
+    #[test]
+    fn fetches_packages_from_their_registry() {
+        let requirements = requirements(&[("_/private", "^1")]);
+        let result = solve::<TestResolver>(
+            "wapm.toml",
+            &requirements,
+            &HashSet::new(),
+            &PackageRegistries::default(),
+        );
+        assert!(result.is_err());
+
+        let mut registries = PackageRegistries::default();
+        registries.insert("private", "internal".to_string());
+        let solution =
+            solve::<TestResolver>("wapm.toml", &requirements, &HashSet::new(), &registries)
+                .unwrap();
+        assert!(solution.contains(&key("_/private", "1.0.0")));
+    }
+
+    #[test]
+    fn fetches_dependencies_from_the_registry_of_their_package() {
+        let mut registries = PackageRegistries::default();
+        registries.insert("_/private", "internal".to_string());
+        let solution = solve::<TestResolver>(
+            "wapm.toml",
+            &requirements(&[("_/private", "^1")]),
+            &HashSet::new(),
+            &registries,
+        )
+        .unwrap();
+        let expected: HashSet<_> = vec![key("_/private", "1.0.0"), key("_/private-util", "1.0.0")]
+            .into_iter()
+            .collect();
+        assert_eq!(expected, solution);
+    }
+
+    #[test]
+    fn rejects_packages_required_from_two_registries() {
+        let mut registries = PackageRegistries::default();
+        registries.insert("_/private", "internal".to_string());
+        let result = solve::<TestResolver>(
+            "wapm.toml",
+            &requirements(&[("_/private", "^1"), ("_/public", "^1")]),
+            &HashSet::new(),
+            &registries,
+        );
+        match result {
+            Err(Error::ResolveError(ResolveError::AmbiguousRegistry { package, .. })) => {
+                assert_eq!("_/private-util", package)
+            }
+            other => panic!("expected an ambiguous registry, found {:?}", other),
+        }
+    }
+}
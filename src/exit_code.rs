@@ -0,0 +1,220 @@
+//! The exit codes of wapm and the machine-readable form of its errors.
+//!
+//! Every error is classified into an [`ErrorKind`], and wapm exits with the code of that kind:
+//!
+//! | Code | Kind                | Errors                                                        |
+//! |------|---------------------|---------------------------------------------------------------|
+//! | 1    | `failure`           | any error not listed below                                    |
+//! | 2    | `usage`             | invalid arguments                                             |
+//! | 3    | `config`            | the config could not be read, written or understood           |
+//! | 4    | `manifest`          | a missing or invalid `wapm.toml`                              |
+//! | 5    | `lockfile`          | a missing, invalid or out of date `wapm.lock`                 |
+//! | 6    | `command-not-found` | the command to run does not exist                             |
+//! | 7    | `registry`          | the registry could not be reached or returned an error        |
+//! | 8    | `dependency`        | the dependencies could not be resolved or installed           |
+//! | 9    | `verification`      | a signature or integrity check failed or a key was not trusted |
+//! | 10   | `io`                | reading or writing local files failed                         |
+//!
+//! When several packages fail to install, wapm exits with the code of the kind their errors share,
+//! or else of the most severe one, in the order `verification`, `io`, `registry`, `dependency`,
+//! `lockfile`, `manifest`, `config`, `command-not-found`, `usage` and `failure`.
+//!
+//! A command run by `wapm run` or `wax` that fails makes wapm exit with the code of the command
+//! instead.
+
+use crate::util::OutputFormat;
+use std::error::Error;
+use std::io;
+
+/// The class of an error, each with a stable exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    Failure,
+    Usage,
+    Config,
+    Manifest,
+    Lockfile,
+    CommandNotFound,
+    Registry,
+    Dependency,
+    Verification,
+    Io,
+}
+
+impl ErrorKind {
+    /// The exit code of wapm for errors of this kind.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Failure => 1,
+            ErrorKind::Usage => 2,
+            ErrorKind::Config => 3,
+            ErrorKind::Manifest => 4,
+            ErrorKind::Lockfile => 5,
+            ErrorKind::CommandNotFound => 6,
+            ErrorKind::Registry => 7,
+            ErrorKind::Dependency => 8,
+            ErrorKind::Verification => 9,
+            ErrorKind::Io => 10,
+        }
+    }
+
+    /// The kind of several errors at once: the kind they share, or else the most severe one.
+    pub fn combine<I: IntoIterator<Item = ErrorKind>>(kinds: I) -> ErrorKind {
+        const BY_SEVERITY: [ErrorKind; 10] = [
+            ErrorKind::Verification,
+            ErrorKind::Io,
+            ErrorKind::Registry,
+            ErrorKind::Dependency,
+            ErrorKind::Lockfile,
+            ErrorKind::Manifest,
+            ErrorKind::Config,
+            ErrorKind::CommandNotFound,
+            ErrorKind::Usage,
+            ErrorKind::Failure,
+        ];
+        let kinds: Vec<ErrorKind> = kinds.into_iter().collect();
+        BY_SEVERITY
+            .iter()
+            .copied()
+            .find(|kind| kinds.contains(kind))
+            .unwrap_or(ErrorKind::Failure)
+    }
+}
+
+/// Errors that know what kind of error they are.
+pub(crate) trait Classify {
+    fn kind(&self) -> ErrorKind;
+}
+
+/// Classify the error if it is an `E`.
+pub(crate) fn classify_as<E: Classify + Error + 'static>(
+    error: &(dyn Error + 'static),
+) -> Option<ErrorKind> {
+    error.downcast_ref::<E>().map(Classify::kind)
+}
+
+fn classify(error: &(dyn Error + 'static)) -> Option<ErrorKind> {
+    use crate::{config, data, dataflow, graphql, runtime};
+
+    crate::commands::error_kind(error)
+        .or_else(|| classify_as::<dataflow::Error>(error))
+        .or_else(|| classify_as::<dataflow::installed_packages::Error>(error))
+        .or_else(|| classify_as::<dataflow::resolved_packages::Error>(error))
+        .or_else(|| classify_as::<dataflow::find_command_result::Error>(error))
+        .or_else(|| classify_as::<data::manifest::ManifestError>(error))
+        .or_else(|| classify_as::<data::manifest::ValidationError>(error))
+        .or_else(|| classify_as::<data::lock::lockfile::LockfileError>(error))
+        .or_else(|| classify_as::<data::wax_index::WaxIndexError>(error))
+        .or_else(|| classify_as::<config::GlobalConfigError>(error))
+        .or_else(|| classify_as::<config::ConfigError>(error))
+        .or_else(|| classify_as::<graphql::GraphQLError>(error))
+        .or_else(|| classify_as::<runtime::RuntimeError>(error))
+        .or_else(|| {
+            error
+                .downcast_ref::<structopt::clap::Error>()
+                .map(|_| ErrorKind::Usage)
+        })
+        .or_else(|| classify_network_error(error))
+        .or_else(|| error.downcast_ref::<io::Error>().map(|_| ErrorKind::Io))
+}
+
+#[cfg(not(target_os = "wasi"))]
+fn classify_network_error(error: &(dyn Error + 'static)) -> Option<ErrorKind> {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .map(|_| ErrorKind::Registry)
+}
+
+#[cfg(target_os = "wasi")]
+fn classify_network_error(_error: &(dyn Error + 'static)) -> Option<ErrorKind> {
+    None
+}
+
+/// The kind of the error, or of the first of its causes that has a known kind.
+pub fn error_kind(error: &anyhow::Error) -> ErrorKind {
+    error
+        .chain()
+        .find_map(classify)
+        .unwrap_or(ErrorKind::Failure)
+}
+
+/// The error, its kind and its causes as JSON.
+pub fn error_to_json(error: &anyhow::Error) -> serde_json::Value {
+    let kind = error_kind(error);
+    let causes: Vec<String> = error.chain().skip(1).map(ToString::to_string).collect();
+    serde_json::json!({
+        "error": {
+            "kind": kind,
+            "exit_code": kind.exit_code(),
+            "message": error.to_string(),
+            "causes": causes,
+        }
+    })
+}
+
+/// Print the error to stderr in the given format.
+pub fn report_error(error: &anyhow::Error, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => eprintln!("Error: {}", error),
+        OutputFormat::Json => eprintln!("{}", error_to_json(error)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::manifest::ManifestError;
+    use crate::dataflow;
+    use crate::dataflow::installed_packages;
+    use crate::exit_code::{error_kind, error_to_json, ErrorKind};
+
+    #[test]
+    fn errors_are_classified_through_wrappers() {
+        let error = anyhow::Error::from(ManifestError::MissingManifest("wapm.toml".to_string()));
+        assert_eq!(ErrorKind::Manifest, error_kind(&error));
+
+        let error = anyhow::Error::from(dataflow::Error::InstallError(
+            installed_packages::Error::IntegrityMismatch(
+                "_/foo@1.0.0".to_string(),
+                "abc".to_string(),
+                "def".to_string(),
+            ),
+        ));
+        assert_eq!(ErrorKind::Verification, error_kind(&error));
+        assert_eq!(9, error_kind(&error).exit_code());
+
+        // the first cause with a known kind decides
+        let error = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::NotFound))
+            .context("Could not read the package");
+        assert_eq!(ErrorKind::Io, error_kind(&error));
+        assert_eq!(ErrorKind::Failure, error_kind(&anyhow!("unknown")));
+
+        let error = anyhow::Error::from(structopt::clap::Error::with_description(
+            "unknown argument",
+            structopt::clap::ErrorKind::UnknownArgument,
+        ));
+        assert_eq!(ErrorKind::Usage, error_kind(&error));
+    }
+
+    #[test]
+    fn combined_kinds() {
+        use ErrorKind::*;
+        assert_eq!(Registry, ErrorKind::combine(vec![Registry, Registry]));
+        assert_eq!(
+            Verification,
+            ErrorKind::combine(vec![Registry, Verification, Io])
+        );
+        assert_eq!(Failure, ErrorKind::combine(vec![]));
+    }
+
+    #[test]
+    fn error_as_json() {
+        let error = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::NotFound))
+            .context("Could not read the package");
+        let json = error_to_json(&error);
+        assert_eq!("io", json["error"]["kind"]);
+        assert_eq!(10, json["error"]["exit_code"]);
+        assert_eq!("Could not read the package", json["error"]["message"]);
+        assert_eq!(1, json["error"]["causes"].as_array().unwrap().len());
+    }
+}
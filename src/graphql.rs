@@ -1,4 +1,5 @@
-use graphql_client::{QueryBody, Response};
+use crate::exit_code::{Classify, ErrorKind};
+use graphql_client::{GraphQLQuery, QueryBody, Response};
 use serde;
 use std::string::ToString;
 use thiserror::Error;
@@ -18,19 +19,53 @@ use crate::util::whoami_distro;
 use super::config::Config;
 
 #[derive(Debug, Error)]
-enum GraphQLError {
+pub(crate) enum GraphQLError {
     #[error("{message}")]
     Error { message: String },
+    #[error("The token of the registry has expired and could not be refreshed, log in again with `wapm login`: {message}")]
+    ExpiredToken { message: String },
+}
+
+impl Classify for GraphQLError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Registry
+    }
 }
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub type DateTime = String;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "graphql/schema.graphql",
+    query_path = "graphql/queries/refresh_token.graphql",
+    response_derives = "Debug"
+)]
+struct RefreshTokenMutation;
+
 pub fn execute_query_modifier<R, V, F>(query: &QueryBody<V>, form_modifier: F) -> anyhow::Result<R>
 where
     for<'de> R: serde::Deserialize<'de>,
     V: serde::Serialize,
-    F: FnOnce(Form) -> Form,
+    F: Fn(Form) -> Form,
+{
+    execute_query_modifier_on_registry(None, query, form_modifier)
+}
+
+/// Run the query on the registry with the given name, or on the default registry.
+///
+/// The token of the registry comes from the config, so `WAPM_REGISTRY_TOKEN` overrides the
+/// token saved by `wapm login` for the default registry. An expired token is refreshed, saved
+/// in the config if it came from there, and the query is sent once more.
+pub fn execute_query_modifier_on_registry<R, V, F>(
+    registry_name: Option<&str>,
+    query: &QueryBody<V>,
+    form_modifier: F,
+) -> anyhow::Result<R>
+where
+    for<'de> R: serde::Deserialize<'de>,
+    V: serde::Serialize,
+    F: Fn(Form) -> Form,
 {
     let client = {
         let builder = Client::builder();
@@ -44,8 +79,142 @@ where
         builder.build()?
     };
     let config = Config::from_file()?;
+    let registry_name = registry_name.unwrap_or(&config.default_registry);
+    let registry = config.registry(Some(registry_name))?;
+
+    execute_query_with_token_refresh(
+        &client,
+        &registry.get_graphql_url(),
+        registry.token.as_deref(),
+        query,
+        form_modifier,
+        |expired_token, token| save_refreshed_token(registry_name, expired_token, token),
+    )
+}
+
+/// Run the query, refreshing the token and running it once more if the token has expired.
+fn execute_query_with_token_refresh<R, V, F, S>(
+    client: &Client,
+    registry_url: &str,
+    token: Option<&str>,
+    query: &QueryBody<V>,
+    form_modifier: F,
+    save_token: S,
+) -> anyhow::Result<R>
+where
+    for<'de> R: serde::Deserialize<'de>,
+    V: serde::Serialize,
+    F: Fn(Form) -> Form,
+    S: FnOnce(&str, &str) -> anyhow::Result<()>,
+{
+    let token = match token.filter(|token| !token.is_empty()) {
+        Some(token) => token,
+        None => return send_query(client, registry_url, None, query, &form_modifier)?.data(),
+    };
+    let response = send_query(client, registry_url, Some(token), query, &form_modifier)?;
+    if !response.is_auth_failure() {
+        return response.data();
+    }
+    debug!("The token of the registry was rejected, refreshing it");
+
+    let refresh_query = RefreshTokenMutation::build_query(refresh_token_mutation::Variables {
+        refresh_token: token.to_string(),
+    });
+    let refresh_response: refresh_token_mutation::ResponseData =
+        send_query(client, registry_url, None, &refresh_query, |f| f)?
+            .data()
+            .map_err(|e| GraphQLError::ExpiredToken {
+                message: e.to_string(),
+            })?;
+    let refreshed_token = refresh_response
+        .refresh_token
+        .and_then(|payload| payload.refresh_token.or(payload.token))
+        .ok_or_else(|| GraphQLError::ExpiredToken {
+            message: "the registry did not return a new token".to_string(),
+        })?;
+    save_token(token, &refreshed_token)?;
+
+    send_query(
+        client,
+        registry_url,
+        Some(&refreshed_token),
+        query,
+        &form_modifier,
+    )?
+    .data()
+}
+
+/// Replace the expired token of the registry in the global config with the refreshed one,
+/// unless the expired token came from elsewhere, like `WAPM_REGISTRY_TOKEN`.
+fn save_refreshed_token(
+    registry_name: &str,
+    expired_token: &str,
+    token: &str,
+) -> anyhow::Result<()> {
+    let mut config = Config::from_global_file()?;
+    match config.registries.get_mut(registry_name) {
+        Some(registry) if registry.token.as_deref() == Some(expired_token) => {
+            registry.token = Some(token.to_string());
+            config.save()
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The error of the registry for an access token that has expired.
+const EXPIRED_TOKEN_MESSAGE: &str = "Signature has expired";
+
+/// The response of the registry to a query.
+struct QueryResponse<R> {
+    unauthorized: bool,
+    body: Option<Response<R>>,
+}
+
+impl<R> QueryResponse<R> {
+    /// Whether the registry rejected the token, with a 401 or because it expired.
+    fn is_auth_failure(&self) -> bool {
+        let errors = self.body.as_ref().and_then(|body| body.errors.as_ref());
+        self.unauthorized
+            || errors
+                .into_iter()
+                .flatten()
+                .any(|error| error.message == EXPIRED_TOKEN_MESSAGE)
+    }
+
+    /// The data of the response, or its errors.
+    fn data(self) -> anyhow::Result<R> {
+        let body = match self.body {
+            Some(body) => body,
+            None => {
+                return Err(GraphQLError::Error {
+                    message: "Unauthorized".to_string(),
+                }
+                .into())
+            }
+        };
+        if let Some(errors) = body.errors {
+            let error_messages: Vec<String> = errors.into_iter().map(|err| err.message).collect();
+            return Err(GraphQLError::Error {
+                message: error_messages.join(", "),
+            }
+            .into());
+        }
+        Ok(body.data.expect("missing response data"))
+    }
+}
 
-    let registry_url = &config.registry.get_graphql_url();
+fn send_query<R, V, F>(
+    client: &Client,
+    registry_url: &str,
+    token: Option<&str>,
+    query: &QueryBody<V>,
+    form_modifier: F,
+) -> anyhow::Result<QueryResponse<R>>
+where
+    for<'de> R: serde::Deserialize<'de>,
+    V: serde::Serialize,
+    F: Fn(Form) -> Form,
+{
     let vars = serde_json::to_string(&query.variables).unwrap();
 
     let form = Form::new()
@@ -62,22 +231,27 @@ where
         whoami_distro(),
     );
 
-    let res = client
+    let request = client
         .post(registry_url)
         .multipart(form)
-        .bearer_auth(&config.registry.token.unwrap_or_else(|| "".to_string()))
-        .header(USER_AGENT, user_agent)
-        .send()?;
-
-    let response_body: Response<R> = res.json()?;
-    if let Some(errors) = response_body.errors {
-        let error_messages: Vec<String> = errors.into_iter().map(|err| err.message).collect();
-        return Err(GraphQLError::Error {
-            message: error_messages.join(", "),
-        }
-        .into());
+        .header(USER_AGENT, user_agent);
+    let request = match token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    };
+    let res = request.send()?;
+
+    // the body of an unauthorized response is not necessarily a GraphQL response
+    if res.status().as_u16() == 401 {
+        return Ok(QueryResponse {
+            unauthorized: true,
+            body: res.json().ok(),
+        });
     }
-    Ok(response_body.data.expect("missing response data"))
+    Ok(QueryResponse {
+        unauthorized: false,
+        body: Some(res.json()?),
+    })
 }
 
 pub fn execute_query<R, V>(query: &QueryBody<V>) -> anyhow::Result<R>
@@ -87,3 +261,188 @@ where
 {
     execute_query_modifier(query, |f| f)
 }
+
+pub fn execute_query_on_registry<R, V>(
+    registry_name: Option<&str>,
+    query: &QueryBody<V>,
+) -> anyhow::Result<R>
+where
+    for<'de> R: serde::Deserialize<'de>,
+    V: serde::Serialize,
+{
+    execute_query_modifier_on_registry(registry_name, query, |f| f)
+}
+
+#[cfg(all(test, not(target_os = "wasi")))]
+mod test {
+    use crate::graphql::{execute_query_with_token_refresh, GraphQLError};
+    use graphql_client::QueryBody;
+    use reqwest::blocking::Client;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread::{self, JoinHandle};
+
+    /// A registry that answers the requests with the given statuses and bodies, in order, and
+    /// returns the requests it received.
+    fn mock_registry(responses: Vec<(u16, &'static str)>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/graphql", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|(status, body)| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let request = read_request(&mut stream);
+                    write!(
+                        stream,
+                        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    )
+                    .unwrap();
+                    request
+                })
+                .collect()
+        });
+        (url, handle)
+    }
+
+    fn read_request(stream: &mut TcpStream) -> String {
+        let mut reader = BufReader::new(stream);
+        let mut request = String::new();
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(length) = line.to_lowercase().strip_prefix("content-length:") {
+                content_length = length.trim().parse().unwrap();
+            }
+            request.push_str(&line);
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        request.push_str(&String::from_utf8_lossy(&body));
+        request
+    }
+
+    fn whoami_query() -> QueryBody<()> {
+        QueryBody {
+            variables: (),
+            query: "query WhoAmIQuery { viewer { username } }",
+            operation_name: "WhoAmIQuery",
+        }
+    }
+
+    fn run_query(
+        url: &str,
+        saved_tokens: &mut Vec<(String, String)>,
+    ) -> anyhow::Result<serde_json::Value> {
+        let client = Client::builder().no_proxy().build().unwrap();
+        execute_query_with_token_refresh(
+            &client,
+            url,
+            Some("old-token"),
+            &whoami_query(),
+            |f| f,
+            |expired_token, token| {
+                saved_tokens.push((expired_token.to_string(), token.to_string()));
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn expired_token_is_refreshed_and_query_retried() {
+        let (url, registry) = mock_registry(vec![
+            (200, r#"{"errors": [{"message": "Signature has expired"}]}"#),
+            (
+                200,
+                r#"{"data": {"refreshToken": {"token": "access-token", "refreshToken": "new-token"}}}"#,
+            ),
+            (200, r#"{"data": {"viewer": {"username": "foo"}}}"#),
+        ]);
+        let mut saved_tokens = vec![];
+        let data = run_query(&url, &mut saved_tokens).unwrap();
+        assert_eq!("foo", data["viewer"]["username"]);
+        assert_eq!(
+            vec![("old-token".to_string(), "new-token".to_string())],
+            saved_tokens
+        );
+
+        let requests = registry.join().unwrap();
+        assert!(requests[0].contains("Bearer old-token"));
+        // the expired token is not sent as authorization to refresh it
+        assert!(!requests[1].to_lowercase().contains("authorization"));
+        assert!(requests[1].contains("RefreshTokenMutation"));
+        assert!(requests[1].contains("old-token"));
+        assert!(requests[2].contains("Bearer new-token"));
+    }
+
+    #[test]
+    fn query_is_retried_only_once() {
+        let (url, registry) = mock_registry(vec![
+            (401, ""),
+            (
+                200,
+                r#"{"data": {"refreshToken": {"token": "access-token"}}}"#,
+            ),
+            (401, r#"{"errors": [{"message": "Unauthorized"}]}"#),
+        ]);
+        let mut saved_tokens = vec![];
+        let error = run_query(&url, &mut saved_tokens).unwrap_err();
+        assert_eq!("Unauthorized", error.to_string());
+        assert_eq!(
+            vec![("old-token".to_string(), "access-token".to_string())],
+            saved_tokens
+        );
+        assert_eq!(3, registry.join().unwrap().len());
+    }
+
+    #[test]
+    fn token_that_can_not_be_refreshed_is_reported() {
+        let (url, registry) = mock_registry(vec![
+            (200, r#"{"errors": [{"message": "Signature has expired"}]}"#),
+            (
+                200,
+                r#"{"errors": [{"message": "Refresh token is expired"}]}"#,
+            ),
+        ]);
+        let mut saved_tokens = vec![];
+        let error = run_query(&url, &mut saved_tokens).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<GraphQLError>(),
+            Some(GraphQLError::ExpiredToken { .. })
+        ));
+        assert!(saved_tokens.is_empty());
+        assert_eq!(2, registry.join().unwrap().len());
+    }
+
+    #[test]
+    fn other_errors_are_not_retried() {
+        let (url, registry) = mock_registry(vec![(
+            200,
+            r#"{"errors": [{"message": "Package not found"}]}"#,
+        )]);
+        let mut saved_tokens = vec![];
+        let error = run_query(&url, &mut saved_tokens).unwrap_err();
+        assert_eq!("Package not found", error.to_string());
+        assert_eq!(1, registry.join().unwrap().len());
+    }
+
+    #[test]
+    fn other_errors_about_expiry_are_not_retried() {
+        let (url, registry) = mock_registry(vec![(
+            200,
+            r#"{"errors": [{"message": "The upload url has expired"}]}"#,
+        )]);
+        let mut saved_tokens = vec![];
+        let error = run_query(&url, &mut saved_tokens).unwrap_err();
+        assert_eq!("The upload url has expired", error.to_string());
+        assert!(saved_tokens.is_empty());
+        assert_eq!(1, registry.join().unwrap().len());
+    }
+}
@@ -95,6 +95,7 @@ pub fn init(dir: PathBuf, force_yes: bool) -> anyhow::Result<()> {
                 license_file: None,
                 homepage: None,
                 wasmer_extra_flags: None,
+                runtime: None,
                 readme: None,
                 disable_command_rename: false,
                 rename_commands_to_raw_command_name: false,
@@ -220,6 +221,9 @@ Press ^C at any time to quit."
                                 module: module.name.clone(),
                                 main_args: None,
                                 package: None,
+                                env: None,
+                                mapdir: None,
+                                wasmer_extra_flags: None,
                             });
                     all_commands.extend(module_commands);
                 }
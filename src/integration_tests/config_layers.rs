@@ -0,0 +1,36 @@
+#![cfg(test)]
+
+use super::data::RAW_PROJECT_CONFIG_DATA;
+use super::prelude::*;
+use crate::config::{Config, ConfigOrigin, ConfigSources};
+
+#[test]
+fn project_config_overrides_global_config() {
+    set_registry_to_dev().unwrap();
+    RAW_PROJECT_CONFIG_DATA.with(|rpcd| {
+        *rpcd.borrow_mut() = Some("wax_cooldown = 0\n".to_string());
+    });
+
+    let sources = ConfigSources::load().unwrap();
+    let config = sources.config().unwrap();
+    assert_eq!(0, config.wax_cooldown);
+    assert_eq!(
+        "https://registry.wapm.dev",
+        config.registry(None).unwrap().url
+    );
+    assert!(matches!(
+        sources.origin(&config, "wax.cooldown").unwrap(),
+        ConfigOrigin::Project(_)
+    ));
+    assert!(matches!(
+        sources.origin(&config, "registry.url").unwrap(),
+        ConfigOrigin::Global(_)
+    ));
+
+    // setting a key only changes the global config
+    set_registry_to_dev().unwrap();
+    assert_eq!(
+        crate::config::wax_default_cooldown(),
+        Config::from_global_file().unwrap().wax_cooldown
+    );
+}
@@ -12,4 +12,8 @@ thread_local! {
     /// The string is the contents of the manifest, the Option is whether or not the manifest exists.
     /// Used to mock reading and writing the manifest to the file system.
     pub static RAW_CONFIG_DATA: RefCell<Option<String>> = RefCell::new(None);
+
+    /// The contents of the `.wapm/config.toml` of the project, if it exists.
+    /// Used to mock reading the project config from the file system.
+    pub static RAW_PROJECT_CONFIG_DATA: RefCell<Option<String>> = RefCell::new(None);
 }
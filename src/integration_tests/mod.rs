@@ -1,5 +1,6 @@
 //! An experimental way to write integration tests
 
 mod add_remove_init;
+mod config_layers;
 pub mod data;
 pub mod prelude;
@@ -26,6 +26,7 @@ pub mod data;
 #[cfg(feature = "full")]
 mod database;
 mod dataflow;
+pub mod exit_code;
 mod graphql;
 mod init;
 #[cfg(feature = "full")]
@@ -34,6 +35,7 @@ mod keys;
 pub mod logging;
 #[cfg(not(target_os = "wasi"))]
 mod proxy;
+mod runtime;
 mod sql;
 #[cfg(feature = "update-notifications")]
 pub mod update_notifier;
@@ -0,0 +1,399 @@
+//! The runtimes that run the modules of commands for `wapm run` and `wax`.
+//!
+//! The runtime is an executable with optional arguments, e.g. `wasmer --backend=llvm`. It is
+//! taken from the `WAPM_RUNTIME` environment variable, the `runtime` of the package in the
+//! `wapm.toml` of the project, the `runtime` key of the config, or defaults to `wasmer`, in that
+//! order. The name of the executable selects the backend that translates the run options into
+//! its command line syntax.
+//!
+//! A project can only pick one of the known runtimes in [`PROJECT_RUNTIMES`], so that running a
+//! command of a cloned project never starts an executable of the project's choosing.
+
+use crate::config::{ConfigOrigin, ConfigSources};
+use crate::constants::WAPM_RUNTIME_ENV_KEY;
+use crate::data::manifest::MANIFEST_FILE_NAME;
+use crate::dataflow::manifest_packages::ManifestResult;
+use crate::exit_code::{Classify, ErrorKind};
+use crate::util::get_runtime_with_args;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// The runtimes that the `wapm.toml` or the `.wapm/config.toml` of a project may select. Other
+/// runtimes have to be set in the global config or with `WAPM_RUNTIME`.
+pub const PROJECT_RUNTIMES: &[&str] = &["wasmer", "wasmer-js", "wasmtime", "wasmedge", "iwasm"];
+
+/// Everything a runtime needs to know to run a module.
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    /// The wasm module to run
+    pub module_path: PathBuf,
+    /// The program name the module sees, if it should be the name of the command
+    pub command_name: Option<String>,
+    /// Host directories made available to the module under the same path
+    pub pre_opened_directories: Vec<String>,
+    /// Host directories made available to the module, of the form Guest -> Host path
+    pub mapped_directories: Vec<(String, PathBuf)>,
+    /// Environment variables of the module
+    pub env: BTreeMap<String, String>,
+    /// The `wasmer-extra-flags` of the package and the command
+    pub wasmer_extra_flags: Vec<OsString>,
+    /// The prehashed key of the module in the cache of the runtime
+    pub cache_key: Option<String>,
+    /// The arguments of the module
+    pub args: Vec<OsString>,
+}
+
+/// Translates the run options into the command line of a runtime.
+pub trait Runtime {
+    /// The arguments of the runtime executable to run the module.
+    fn run_args(&self, options: &RunOptions) -> Vec<OsString>;
+
+    /// The environment variables of the runtime process.
+    fn process_env(&self, _options: &RunOptions) -> BTreeMap<String, String> {
+        BTreeMap::new()
+    }
+
+    /// The options the runtime cannot express, which are left out of its command line.
+    fn discarded_options(&self, _options: &RunOptions) -> Vec<&'static str> {
+        vec![]
+    }
+}
+
+/// The wasmer CLI, `wasmer <module> [flags] -- [args]`
+pub struct Wasmer {
+    /// Whether the runtime understands `--command-name`, which e.g. `wasmer-js` does not
+    pub supports_command_name: bool,
+}
+
+impl Runtime for Wasmer {
+    fn run_args(&self, options: &RunOptions) -> Vec<OsString> {
+        let mut run_args = vec![options.module_path.clone().into_os_string()];
+        if let (true, Some(command_name)) = (self.supports_command_name, &options.command_name) {
+            run_args.push(format!("--command-name={}", command_name).into());
+        }
+        for directory in options.pre_opened_directories.iter() {
+            run_args.push(format!("--dir={}", directory).into());
+        }
+        for (guest_path, host_path) in options.mapped_directories.iter() {
+            run_args
+                .push(format!("--mapdir={}:{}", guest_path, host_path.to_string_lossy()).into());
+        }
+        for (key, value) in options.env.iter() {
+            run_args.push(format!("--env={}={}", key, value).into());
+        }
+        run_args.extend(options.wasmer_extra_flags.iter().cloned());
+        if let Some(ref cache_key) = options.cache_key {
+            run_args.push(format!("--cache-key=\"{}\"", cache_key).into());
+        }
+        // NOTE:
+        // never push empty arguments for optional values:
+        // an empty OsString may pass empty args to the child program which can cause issues
+        run_args.push("--".into());
+        run_args.extend(options.args.iter().cloned());
+        run_args
+    }
+}
+
+/// The wasmtime CLI, `wasmtime [flags] <module> [args]`
+pub struct Wasmtime;
+
+impl Runtime for Wasmtime {
+    fn run_args(&self, options: &RunOptions) -> Vec<OsString> {
+        let mut run_args = vec![];
+        if let Some(ref command_name) = options.command_name {
+            run_args.push(format!("--argv0={}", command_name).into());
+        }
+        for directory in options.pre_opened_directories.iter() {
+            run_args.push(format!("--dir={}", directory).into());
+        }
+        for (guest_path, host_path) in options.mapped_directories.iter() {
+            run_args.push(format!("--dir={}::{}", host_path.to_string_lossy(), guest_path).into());
+        }
+        for (key, value) in options.env.iter() {
+            run_args.push(format!("--env={}={}", key, value).into());
+        }
+        // everything after the module is an argument of the module
+        run_args.push(options.module_path.clone().into_os_string());
+        run_args.extend(options.args.iter().cloned());
+        run_args
+    }
+
+    fn discarded_options(&self, options: &RunOptions) -> Vec<&'static str> {
+        let mut discarded = vec![];
+        if !options.wasmer_extra_flags.is_empty() {
+            discarded.push("wasmer-extra-flags");
+        }
+        if options.cache_key.is_some() {
+            discarded.push("cache key");
+        }
+        discarded
+    }
+}
+
+/// Any other runtime that runs a WASI module with `<runtime> <module> [args]`. The environment
+/// variables are passed through the environment of the runtime process.
+pub struct Generic;
+
+impl Runtime for Generic {
+    fn run_args(&self, options: &RunOptions) -> Vec<OsString> {
+        let mut run_args = vec![options.module_path.clone().into_os_string()];
+        run_args.extend(options.args.iter().cloned());
+        run_args
+    }
+
+    fn process_env(&self, options: &RunOptions) -> BTreeMap<String, String> {
+        options.env.clone()
+    }
+
+    fn discarded_options(&self, options: &RunOptions) -> Vec<&'static str> {
+        let mut discarded = vec![];
+        if !options.pre_opened_directories.is_empty() || !options.mapped_directories.is_empty() {
+            discarded.push("directories");
+        }
+        if !options.wasmer_extra_flags.is_empty() {
+            discarded.push("wasmer-extra-flags");
+        }
+        if options.cache_key.is_some() {
+            discarded.push("cache key");
+        }
+        discarded
+    }
+}
+
+/// A runtime executable, the arguments it is always called with, and the backend for its
+/// command line syntax.
+pub struct RuntimeCommand {
+    pub program: String,
+    pub args: Vec<String>,
+    pub backend: Box<dyn Runtime>,
+}
+
+impl RuntimeCommand {
+    /// Pick the backend from the file name of the executable.
+    pub fn new(program: String, args: Vec<String>) -> Self {
+        let file_stem = Path::new(&program)
+            .file_stem()
+            .map(|file_stem| file_stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let backend: Box<dyn Runtime> = if file_stem.starts_with("wasmtime") {
+            Box::new(Wasmtime)
+        } else if file_stem.contains("wasmer") {
+            // avoid `wasmer-js`, allow other wasmers
+            Box::new(Wasmer {
+                supports_command_name: file_stem.ends_with("wasmer"),
+            })
+        } else {
+            Box::new(Generic)
+        };
+        Self {
+            program,
+            args,
+            backend,
+        }
+    }
+
+    /// Find the runtime to use for the project in the given directory.
+    pub fn find<P: AsRef<Path>>(project_directory: P) -> Result<Self, RuntimeError> {
+        let project_directory = project_directory.as_ref();
+        let (configured_runtime, origin) =
+            match ManifestResult::find_in_directory(project_directory) {
+                ManifestResult::Manifest(manifest) if manifest.package.runtime.is_some() => (
+                    manifest.package.runtime,
+                    Some(project_directory.join(MANIFEST_FILE_NAME)),
+                ),
+                _ => match config_runtime() {
+                    Some((runtime, ConfigOrigin::Project(path))) => (Some(runtime), Some(path)),
+                    Some((runtime, _)) => (Some(runtime), None),
+                    None => (None, None),
+                },
+            };
+        let (program, args) = get_runtime_with_args(configured_runtime);
+        // `WAPM_RUNTIME` takes precedence over the runtime of the project
+        if let Some(path) = origin.filter(|_| env::var(WAPM_RUNTIME_ENV_KEY).is_err()) {
+            if !PROJECT_RUNTIMES.contains(&program.as_str()) {
+                return Err(RuntimeError::UnknownProjectRuntime(program, path));
+            }
+        }
+        Ok(Self::new(program, args))
+    }
+
+    /// All arguments of the executable to run the module. Warns about the options the runtime
+    /// cannot express.
+    pub fn command_args(&self, options: &RunOptions) -> Vec<OsString> {
+        for option in self.backend.discarded_options(options) {
+            warn!(
+                "Ignoring the {} of the command, \"{}\" does not support them",
+                option, self.program
+            );
+        }
+        self.args
+            .iter()
+            .map(OsString::from)
+            .chain(self.backend.run_args(options))
+            .collect()
+    }
+}
+
+/// The `runtime` key of the config and where it is set.
+fn config_runtime() -> Option<(String, ConfigOrigin)> {
+    let sources = ConfigSources::load().ok()?;
+    let config = sources.config().ok()?;
+    let origin = sources.origin(&config, "runtime").ok()?;
+    Some((config.runtime?, origin))
+}
+
+#[derive(Debug, Error)]
+pub enum RuntimeError {
+    #[error(
+        "The runtime \"{0}\" set in \"{}\" is not one of the runtimes a project can use ({}). Set it in the global config or with WAPM_RUNTIME to use it.",
+        .1.display(),
+        PROJECT_RUNTIMES.join(", ")
+    )]
+    UnknownProjectRuntime(String, PathBuf),
+}
+
+impl Classify for RuntimeError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Config
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::runtime::{RunOptions, RuntimeCommand};
+    use std::collections::BTreeMap;
+    use std::ffi::OsString;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn run_options() -> RunOptions {
+        let mut env = BTreeMap::new();
+        env.insert("PORT".to_string(), "8080".to_string());
+        RunOptions {
+            module_path: PathBuf::from("foo_entry.wasm"),
+            command_name: Some("foo".to_string()),
+            pre_opened_directories: vec![".".to_string()],
+            mapped_directories: vec![("/data".to_string(), PathBuf::from("pkg/data"))],
+            env,
+            wasmer_extra_flags: vec![OsString::from("--enable-threads")],
+            cache_key: Some("abc".to_string()),
+            args: vec![OsString::from("arg1"), OsString::from("--arg2")],
+        }
+    }
+
+    fn command_args(runtime: &[&str], options: &RunOptions) -> Vec<String> {
+        let args = runtime[1..].iter().map(|arg| arg.to_string()).collect();
+        RuntimeCommand::new(runtime[0].to_string(), args)
+            .command_args(options)
+            .into_iter()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn wasmer_command_args() {
+        assert_eq!(
+            vec![
+                "--backend=llvm",
+                "foo_entry.wasm",
+                "--command-name=foo",
+                "--dir=.",
+                "--mapdir=/data:pkg/data",
+                "--env=PORT=8080",
+                "--enable-threads",
+                "--cache-key=\"abc\"",
+                "--",
+                "arg1",
+                "--arg2",
+            ],
+            command_args(&["/usr/bin/wasmer", "--backend=llvm"], &run_options())
+        );
+        let options = RunOptions {
+            module_path: PathBuf::from("foo_entry.wasm"),
+            ..RunOptions::default()
+        };
+        assert_eq!(
+            vec!["foo_entry.wasm", "--"],
+            command_args(&["wasmer"], &options)
+        );
+        // `wasmer-js` does not understand `--command-name`
+        assert!(!command_args(&["wasmer-js"], &run_options())
+            .contains(&"--command-name=foo".to_string()));
+    }
+
+    #[test]
+    fn wasmtime_command_args() {
+        assert_eq!(
+            vec![
+                "run",
+                "--argv0=foo",
+                "--dir=.",
+                "--dir=pkg/data::/data",
+                "--env=PORT=8080",
+                "foo_entry.wasm",
+                "arg1",
+                "--arg2",
+            ],
+            command_args(&["wasmtime", "run"], &run_options())
+        );
+    }
+
+    #[test]
+    fn wasmtime_discards_wasmer_options() {
+        let runtime = RuntimeCommand::new("wasmtime".to_string(), vec![]);
+        assert_eq!(
+            vec!["wasmer-extra-flags", "cache key"],
+            runtime.backend.discarded_options(&run_options())
+        );
+        let options = RunOptions {
+            wasmer_extra_flags: vec![],
+            cache_key: None,
+            ..run_options()
+        };
+        assert!(runtime.backend.discarded_options(&options).is_empty());
+    }
+
+    #[test]
+    fn generic_command_args() {
+        let options = run_options();
+        let runtime = RuntimeCommand::new("iwasm".to_string(), vec![]);
+        assert_eq!(
+            vec![
+                OsString::from("foo_entry.wasm"),
+                OsString::from("arg1"),
+                OsString::from("--arg2"),
+            ],
+            runtime.command_args(&options)
+        );
+        assert_eq!(options.env, runtime.backend.process_env(&options));
+        assert_eq!(
+            vec!["directories", "wasmer-extra-flags", "cache key"],
+            runtime.backend.discarded_options(&options)
+        );
+    }
+
+    #[test]
+    fn projects_can_only_use_known_runtimes() {
+        let project_dir = tempfile::TempDir::new().unwrap();
+        let write_manifest = |runtime: &str| {
+            let wapm_toml = format!(
+                "[package]\nname = \"test\"\nversion = \"1.0.0\"\ndescription = \"test\"\nruntime = \"{}\"\n",
+                runtime
+            );
+            fs::write(project_dir.path().join("wapm.toml"), wapm_toml).unwrap();
+        };
+
+        write_manifest("wasmtime --dir=.");
+        let runtime = RuntimeCommand::find(project_dir.path()).unwrap();
+        assert_eq!("wasmtime", runtime.program);
+        assert_eq!(vec!["--dir=.".to_string()], runtime.args);
+
+        write_manifest("./run.sh");
+        assert!(RuntimeCommand::find(project_dir.path()).is_err());
+        write_manifest("/usr/bin/wasmer");
+        assert!(RuntimeCommand::find(project_dir.path()).is_err());
+    }
+}
@@ -72,7 +72,7 @@ pub fn get_username() -> anyhow::Result<Option<String>> {
 
 #[cfg(feature = "telemetry")]
 pub fn telemetry_is_enabled() -> bool {
-    let mut config = if let Ok(c) = crate::config::Config::from_file() {
+    let config = if let Ok(c) = crate::config::Config::from_file() {
         c
     } else {
         // TODO: change this to false when wapm becomes more stable
@@ -80,8 +80,10 @@ pub fn telemetry_is_enabled() -> bool {
         // telemetry once we have more confidence in wapm's stability/userbase size
         return true;
     };
-    let telemetry_str = crate::config::get(&mut config, "telemetry.enabled".to_string())
-        .unwrap_or_else(|_| "true".to_string());
+    let telemetry_str = crate::config::get(&config, "telemetry.enabled")
+        .ok()
+        .and_then(|entry| entry.value)
+        .unwrap_or_else(|| "true".to_string());
 
     // if we fail to parse, someone probably tried to turn it off
     telemetry_str.parse::<bool>().unwrap_or(false)
@@ -164,6 +166,8 @@ impl<T: Default> SetOnce<T> {
 lazy_static! {
     /// Global variable that determines the behavior of prompts
     pub static ref WAPM_FORCE_YES_TO_PROMPTS: Mutex<SetOnce<bool>> = Mutex::new(SetOnce::new());
+    /// Held while a prompt waits for input, so that prompts from concurrent installs don't mix
+    static ref PROMPT_LOCK: Mutex<()> = Mutex::new(());
 }
 
 /// If true, prompts should not ask for user input
@@ -182,6 +186,7 @@ pub fn set_wapm_should_accept_all_prompts(val: bool) -> Option<()> {
 pub fn prompt_user_for_yes(prompt: &str) -> anyhow::Result<bool> {
     use std::io::Write;
 
+    let _prompt_guard = PROMPT_LOCK.lock().unwrap();
     print!("{}\n[y/n] ", prompt);
     std::io::stdout().flush()?;
     if wapm_should_accept_all_prompts() {
@@ -269,9 +274,12 @@ pub fn compare_versions(old: &str, new: &str) -> Option<bool> {
 }
 
 /// Returns the value of the WAPM_RUNTIME env var if it exists.
-/// Otherwise returns wasmer
-fn get_runtime() -> String {
-    env::var(WAPM_RUNTIME_ENV_KEY).unwrap_or(DEFAULT_RUNTIME.to_owned())
+/// Otherwise returns the configured runtime, or wasmer
+fn get_runtime(configured_runtime: Option<String>) -> String {
+    env::var(WAPM_RUNTIME_ENV_KEY)
+        .ok()
+        .or(configured_runtime)
+        .unwrap_or_else(|| DEFAULT_RUNTIME.to_owned())
 }
 
 /// Splits the runtime from the rest of arguments
@@ -290,8 +298,82 @@ fn split_runtime_and_args(runtime: String) -> (String, Vec<String>) {
 /// We put this in a new function, to be clear that runtime can be both
 /// 1. A string with the runtime value (eg. "wasmer")
 /// 2. A string with the runtime value and the args (eg. "wasmer --backend=singlepass")
-pub fn get_runtime_with_args() -> (String, Vec<String>) {
-    split_runtime_and_args(get_runtime())
+pub fn get_runtime_with_args(configured_runtime: Option<String>) -> (String, Vec<String>) {
+    split_runtime_and_args(get_runtime(configured_runtime))
+}
+
+/// The format of the data printed by a command, `text` (or `table`) or `json`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" | "table" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!(
+                "unknown format \"{}\", expected `text` or `json`",
+                s
+            )),
+        }
+    }
+}
+
+/// Parse a size in bytes with an optional binary unit, e.g. `1024`, `500K`, `200MB` or `1GiB`
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let unit_start = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(unit_start);
+    let multiplier = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Format a size in bytes with the largest binary unit that fits, e.g. `1.5 MiB`
+pub fn format_size(size: u64) -> String {
+    let units = ["KiB", "MiB", "GiB"];
+    let mut value = size as f64;
+    let mut unit = "B";
+    for next_unit in units.iter() {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next_unit;
+    }
+    if unit == "B" {
+        format!("{} B", size)
+    } else {
+        format!("{:.1} {}", value, unit)
+    }
+}
+
+/// Parse a duration of seconds, minutes, hours, days or weeks, e.g. `90s`, `12h` or `30d`
+pub fn parse_duration(duration: &str) -> Option<time::Duration> {
+    let duration = duration.trim();
+    let unit_start = duration.find(|c: char| !c.is_ascii_digit())?;
+    let (number, unit) = duration.split_at(unit_start);
+    let number = number.parse::<i64>().ok()?;
+    match unit {
+        "s" => Some(time::Duration::seconds(number)),
+        "m" => Some(time::Duration::minutes(number)),
+        "h" => Some(time::Duration::hours(number)),
+        "d" => Some(time::Duration::days(number)),
+        "w" => Some(time::Duration::weeks(number)),
+        _ => None,
+    }
 }
 
 #[cfg(not(target_os = "wasi"))]
@@ -365,4 +447,21 @@ mod test {
             ("wasmer".to_owned(), vec!["run".to_owned()])
         );
     }
+
+    #[test]
+    pub fn test_parse_size_and_duration() {
+        assert_eq!(parse_size("1024"), Some(1024));
+        assert_eq!(parse_size("500K"), Some(500 * 1024));
+        assert_eq!(parse_size("200MB"), Some(200 * 1024 * 1024));
+        assert_eq!(parse_size("1 GiB"), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_size("1TB"), None);
+        assert_eq!(parse_size("MB"), None);
+        assert_eq!(format_size(100), "100 B");
+        assert_eq!(format_size(1536 * 1024), "1.5 MiB");
+
+        assert_eq!(parse_duration("30d"), Some(time::Duration::days(30)));
+        assert_eq!(parse_duration("12h"), Some(time::Duration::hours(12)));
+        assert_eq!(parse_duration("30"), None);
+        assert_eq!(parse_duration("d"), None);
+    }
 }